@@ -1,40 +1,184 @@
 mod builder;
 mod iterator;
 
+use anyhow::{bail, ensure, Result};
 pub use builder::BlockBuilder;
 use bytes::{Buf, BufMut, Bytes};
 pub use iterator::BlockIterator;
 
+use crate::value_type::ValueType;
+use crate::varint::{get_varint, put_varint};
+
 pub const SIZEOF_U16: usize = std::mem::size_of::<u16>();
+pub const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+pub const SIZEOF_U64: usize = std::mem::size_of::<u64>();
 
 /// A block is the smallest unit of read and caching in LSM tree. It is a collection of sorted
-/// key-value pairs.
+/// entries, each encoded as:
+///
+/// ```plaintext
+/// | shared (varint) | unshared (varint) | key suffix | seq (u64) | value type (u8) |
+/// | value_len (varint) | value |
+/// ```
+///
+/// where the key of an entry is the first `shared` bytes of the previous key followed by the
+/// `unshared` bytes of the suffix. Every few entries, a restart point stores its key in full, with
+/// `shared` being 0. The block ends with the offsets of the restart points, and a checksum of
+/// everything before it:
+///
+/// ```plaintext
+/// | entries | restart offset (u32) ... | num of restarts (u32) | checksum (u32) |
+/// ```
+///
+/// A block holds at least one entry however large it is, so a value larger than the block size
+/// makes an oversized block of its own.
+#[derive(Default)]
 pub struct Block {
     data: Vec<u8>,
-    offsets: Vec<u16>,
+    restarts: Vec<u32>,
 }
 
 impl Block {
     pub fn encode(&self) -> Bytes {
         let mut buf = self.data.clone();
-        let offsets_len = self.offsets.len();
-        for offset in &self.offsets {
-            buf.put_u16(*offset);
+        let restarts_len = self.restarts.len();
+        for restart in &self.restarts {
+            buf.put_u32(*restart);
         }
-        buf.put_u16(offsets_len as u16);
+        buf.put_u32(restarts_len as u32);
+        buf.put_u32(crc32fast::hash(&buf));
         buf.into()
     }
 
-    pub fn decode(data: &[u8]) -> Self {
-        let entry_offsets_len = (&data[data.len() - SIZEOF_U16..]).get_u16() as usize;
-        let data_end = data.len() - SIZEOF_U16 - entry_offsets_len * SIZEOF_U16;
-        let offsets_raw = &data[data_end..data.len() - SIZEOF_U16];
-        let offsets = offsets_raw
-            .chunks(SIZEOF_U16)
-            .map(|mut x| x.get_u16())
+    /// Decode a block, checking its checksum and the layout of its entries, so that iterating
+    /// over it cannot go out of bounds.
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        ensure!(data.len() >= SIZEOF_U32 * 2, "block too short");
+        let (data, mut checksum) = data.split_at(data.len() - SIZEOF_U32);
+        ensure!(
+            crc32fast::hash(data) == checksum.get_u32(),
+            "block checksum mismatch"
+        );
+        let restarts_len = (&data[data.len() - SIZEOF_U32..]).get_u32() as usize;
+        ensure!(
+            (data.len() - SIZEOF_U32) / SIZEOF_U32 >= restarts_len,
+            "block restarts out of bounds"
+        );
+        let data_end = data.len() - SIZEOF_U32 - restarts_len * SIZEOF_U32;
+        let restarts_raw = &data[data_end..data.len() - SIZEOF_U32];
+        let restarts: Vec<u32> = restarts_raw
+            .chunks(SIZEOF_U32)
+            .map(|mut x| x.get_u32())
             .collect();
-        let data = data[0..data_end].to_vec();
-        Self { data, offsets }
+        let block = Self {
+            data: data[0..data_end].to_vec(),
+            restarts,
+        };
+        block.validate()?;
+        Ok(block)
+    }
+
+    /// Decode a block written in SST format version 1, where the lengths in the entries are u16s
+    /// rather than varints, and the restart offsets and their number are u16s:
+    ///
+    /// ```plaintext
+    /// | shared (u16) | unshared (u16) | key suffix | seq (u64) | value type (u8) |
+    /// | value_len (u16) | value |
+    /// ```
+    ///
+    /// ```plaintext
+    /// | entries | restart offset (u16) ... | num of restarts (u16) | checksum (u32) |
+    /// ```
+    ///
+    /// The entries are re-encoded in the current layout, which the iterator reads.
+    pub fn decode_v1(data: &[u8]) -> Result<Self> {
+        ensure!(data.len() >= SIZEOF_U16 + SIZEOF_U32, "block too short");
+        let (data, mut checksum) = data.split_at(data.len() - SIZEOF_U32);
+        ensure!(
+            crc32fast::hash(data) == checksum.get_u32(),
+            "block checksum mismatch"
+        );
+        let restarts_len = (&data[data.len() - SIZEOF_U16..]).get_u16() as usize;
+        ensure!(
+            data.len() >= SIZEOF_U16 + restarts_len * SIZEOF_U16,
+            "block restarts out of bounds"
+        );
+        let data_end = data.len() - SIZEOF_U16 - restarts_len * SIZEOF_U16;
+        let mut restarts = data[data_end..data.len() - SIZEOF_U16]
+            .chunks(SIZEOF_U16)
+            .map(|mut x| x.get_u16() as usize)
+            .peekable();
+        let mut block = Self::default();
+        let mut buf = &data[..data_end];
+        while buf.has_remaining() {
+            if restarts.next_if_eq(&(data_end - buf.remaining())).is_some() {
+                block.restarts.push(block.data.len() as u32);
+            }
+            ensure!(buf.remaining() >= SIZEOF_U16 * 2, "block entry too short");
+            let shared = buf.get_u16();
+            let unshared = buf.get_u16() as usize;
+            ensure!(
+                buf.remaining() >= unshared + SIZEOF_U64 + 1 + SIZEOF_U16,
+                "block entry too short"
+            );
+            put_varint(&mut block.data, shared as u64);
+            put_varint(&mut block.data, unshared as u64);
+            block.data.put_slice(&buf[..unshared + SIZEOF_U64 + 1]);
+            buf.advance(unshared + SIZEOF_U64 + 1);
+            let value_len = buf.get_u16() as usize;
+            ensure!(buf.remaining() >= value_len, "block entry too short");
+            put_varint(&mut block.data, value_len as u64);
+            block.data.put_slice(&buf[..value_len]);
+            buf.advance(value_len);
+        }
+        ensure!(
+            restarts.next().is_none(),
+            "restart point within an entry or past the entries"
+        );
+        block.validate()?;
+        Ok(block)
+    }
+
+    /// Check that the entries fill the block, with the restart points at entries storing their
+    /// keys in full.
+    fn validate(&self) -> Result<()> {
+        ensure!(
+            self.data.is_empty() == self.restarts.is_empty(),
+            "block without restart points"
+        );
+        let mut restarts = self.restarts.iter().map(|&x| x as usize).peekable();
+        let mut buf = &self.data[..];
+        let mut key_len = 0;
+        while buf.has_remaining() {
+            let offset = self.data.len() - buf.remaining();
+            let shared = get_varint(&mut buf)? as usize;
+            let unshared = get_varint(&mut buf)? as usize;
+            match restarts.peek() {
+                Some(&restart) if restart == offset => {
+                    ensure!(shared == 0, "restart point with a shared key prefix");
+                    restarts.next();
+                }
+                Some(&restart) if restart < offset => bail!("restart point within an entry"),
+                _ => ensure!(offset > 0, "block without a restart point at the start"),
+            }
+            ensure!(
+                shared <= key_len,
+                "shared key prefix longer than the previous key"
+            );
+            key_len = shared.saturating_add(unshared);
+            ensure!(key_len > 0, "empty key in block");
+            ensure!(
+                buf.remaining() >= unshared.saturating_add(SIZEOF_U64 + 1),
+                "block entry too short"
+            );
+            buf.advance(unshared + SIZEOF_U64);
+            ValueType::try_from(buf.get_u8())?;
+            let value_len = get_varint(&mut buf)? as usize;
+            ensure!(buf.remaining() >= value_len, "block entry too short");
+            buf.advance(value_len);
+        }
+        ensure!(restarts.next().is_none(), "restart point past the entries");
+        Ok(())
     }
 }
 
@@ -1,51 +1,99 @@
 use bytes::BufMut;
 
-use super::{Block, SIZEOF_U16};
+use super::{Block, SIZEOF_U32, SIZEOF_U64};
+use crate::value_type::ValueType;
+use crate::varint::{put_varint, varint_len};
 
 /// Builds a block.
 pub struct BlockBuilder {
-    /// Offsets of each key-value entries.
-    offsets: Vec<u16>,
+    /// Offsets of the restart points, whose keys are stored in full.
+    restarts: Vec<u32>,
     /// All key-value pairs in the block.
     data: Vec<u8>,
     /// The expected block size.
     block_size: usize,
+    /// The number of entries from one restart point to the next.
+    restart_interval: usize,
+    /// The number of entries since the last restart point.
+    counter: usize,
+    last_key: Vec<u8>,
 }
 
 impl BlockBuilder {
-    /// Creates a new block builder.
+    /// Creates a new block builder, with a restart point every 16 entries.
     pub fn new(block_size: usize) -> Self {
         Self {
-            offsets: Vec::new(),
+            restarts: Vec::new(),
             data: Vec::new(),
             block_size,
+            restart_interval: 16,
+            counter: 0,
+            last_key: Vec::new(),
         }
     }
 
+    /// Set the number of entries from one restart point to the next. A longer interval shares
+    /// the prefixes of more keys, but a seek scans more entries after its binary search; 1 stores
+    /// every key in full.
+    pub fn with_restart_interval(mut self, restart_interval: usize) -> Self {
+        assert!(restart_interval > 0, "restart interval must be positive");
+        self.restart_interval = restart_interval;
+        self
+    }
+
     fn estimated_size(&self) -> usize {
-        self.offsets.len() * SIZEOF_U16 + self.data.len() + SIZEOF_U16
+        self.restarts.len() * SIZEOF_U32 + self.data.len() + SIZEOF_U32 * 2
     }
 
-    /// Adds a key-value pair to the block. Returns false when the block is full.
+    /// Adds an entry to the block. Returns false when the block is full. The first entry is always
+    /// added, even if it is larger than the block size.
     #[must_use]
-    pub fn add(&mut self, key: &[u8], value: &[u8]) -> bool {
+    pub fn add(&mut self, key: &[u8], seq: u64, value_type: ValueType, value: &[u8]) -> bool {
         assert!(!key.is_empty(), "key must not be empty");
-        if self.estimated_size() + key.len() + value.len() + SIZEOF_U16 * 3 > self.block_size
-            && !self.is_empty()
-        {
+        let restart = self.is_empty() || self.counter == self.restart_interval;
+        let shared = match restart {
+            true => 0,
+            false => key
+                .iter()
+                .zip(&self.last_key)
+                .take_while(|(a, b)| a == b)
+                .count(),
+        };
+        let entry_size =
+            varint_len(shared as u64) + varint_len((key.len() - shared) as u64) + key.len()
+                - shared
+                + SIZEOF_U64
+                + 1
+                + varint_len(value.len() as u64)
+                + value.len()
+                + if restart { SIZEOF_U32 } else { 0 };
+        // Restart points are stored as `u32`s, so no entry starts past 4 GiB, however large the
+        // block size.
+        let full = self.estimated_size() + entry_size > self.block_size
+            || self.data.len() > u32::MAX as usize;
+        if full && !self.is_empty() {
             return false;
         }
-        self.offsets.push(self.data.len() as u16);
-        self.data.put_u16(key.len() as u16);
-        self.data.put(key);
-        self.data.put_u16(value.len() as u16);
+        if restart {
+            self.restarts.push(self.data.len() as u32);
+            self.counter = 0;
+        }
+        put_varint(&mut self.data, shared as u64);
+        put_varint(&mut self.data, (key.len() - shared) as u64);
+        self.data.put(&key[shared..]);
+        self.data.put_u64(seq);
+        self.data.put_u8(value_type as u8);
+        put_varint(&mut self.data, value.len() as u64);
         self.data.put(value);
+        self.counter += 1;
+        self.last_key.clear();
+        self.last_key.extend(key);
         true
     }
 
     /// Check if there is no key-value pair in the block.
     pub fn is_empty(&self) -> bool {
-        self.offsets.is_empty()
+        self.data.is_empty()
     }
 
     /// Finalize the block.
@@ -55,7 +103,7 @@ impl BlockBuilder {
         }
         Block {
             data: self.data,
-            offsets: self.offsets,
+            restarts: self.restarts,
         }
     }
 }
@@ -3,13 +3,28 @@ use std::sync::Arc;
 use bytes::Buf;
 
 use super::Block;
+use crate::value_type::ValueType;
+use crate::varint::get_varint;
 
-/// Iterates on a block.
+/// Iterates on a block, in both directions.
+///
+/// Moving past the last entry or before the first one makes the iterator invalid. It comes back to
+/// the last entry when moved backward from past the end, and to the first one when moved forward
+/// from before the start.
+///
+/// As the key of an entry shares its prefix with the previous one, entries are decoded forward
+/// from a restart point. Moving backward decodes from the restart point before the current entry.
 pub struct BlockIterator {
     block: Arc<Block>,
     key: Vec<u8>,
+    seq: u64,
+    value_type: ValueType,
     value: Vec<u8>,
-    idx: usize,
+    /// The offset of the current entry, or the end of the entries past the end. Before the start,
+    /// it is 0 with an empty key.
+    offset: usize,
+    /// The offset of the entry after the current one.
+    next_offset: usize,
 }
 
 impl BlockIterator {
@@ -17,8 +32,11 @@ impl BlockIterator {
         Self {
             block,
             key: Vec::new(),
+            seq: 0,
+            value_type: ValueType::Put,
             value: Vec::new(),
-            idx: 0,
+            offset: 0,
+            next_offset: 0,
         }
     }
 
@@ -29,7 +47,14 @@ impl BlockIterator {
         iter
     }
 
-    /// Creates a block iterator and seek to the first key that >= `key`.
+    /// Creates a block iterator and seek to the last entry.
+    pub fn create_and_seek_to_last(block: Arc<Block>) -> Self {
+        let mut iter = Self::new(block);
+        iter.seek_to_last();
+        iter
+    }
+
+    /// Creates a block iterator and seek to the latest version of the first key that >= `key`.
     pub fn create_and_seek_to_key(block: Arc<Block>, key: &[u8]) -> Self {
         let mut iter = Self::new(block);
         iter.seek_to_key(key);
@@ -48,6 +73,18 @@ impl BlockIterator {
         &self.value
     }
 
+    /// Returns the sequence number of the current entry.
+    pub fn seq(&self) -> u64 {
+        debug_assert!(!self.key.is_empty(), "invalid iterator");
+        self.seq
+    }
+
+    /// Returns the type of the current entry.
+    pub fn value_type(&self) -> ValueType {
+        debug_assert!(!self.key.is_empty(), "invalid iterator");
+        self.value_type
+    }
+
     /// Returns true if the iterator is valid.
     pub fn is_valid(&self) -> bool {
         !self.key.is_empty()
@@ -55,55 +92,113 @@ impl BlockIterator {
 
     /// Seeks to the first key in the block.
     pub fn seek_to_first(&mut self) {
-        self.seek_to(0);
+        match self.block.restarts.is_empty() {
+            true => self.move_past_end(),
+            false => self.seek_to_restart(0),
+        }
     }
 
-    /// Seeks to the idx-th key in the block.
-    fn seek_to(&mut self, idx: usize) {
-        if idx >= self.block.offsets.len() {
-            self.key.clear();
-            self.value.clear();
+    /// Seeks to the last entry in the block.
+    pub fn seek_to_last(&mut self) {
+        let Some(last) = self.block.restarts.len().checked_sub(1) else {
+            self.move_past_end();
             return;
+        };
+        self.seek_to_restart(last);
+        while self.next_offset < self.block.data.len() {
+            self.decode_entry(self.next_offset);
         }
-        let offset = self.block.offsets[idx] as usize;
-        self.seek_to_offset(offset);
-        self.idx = idx;
+    }
+
+    /// Seeks to the idx-th restart point, whose key is stored in full.
+    fn seek_to_restart(&mut self, idx: usize) {
+        self.key.clear();
+        self.decode_entry(self.block.restarts[idx] as usize);
+    }
+
+    fn move_past_end(&mut self) {
+        self.offset = self.block.data.len();
+        self.key.clear();
+        self.value.clear();
+    }
+
+    /// Check if the iterator is past the end of the block, rather than before the start.
+    fn is_past_end(&self) -> bool {
+        self.offset == self.block.data.len()
     }
 
     /// Move to the next key in the block.
     pub fn next(&mut self) {
-        self.idx += 1;
-        self.seek_to(self.idx);
+        if self.is_valid() {
+            match self.next_offset < self.block.data.len() {
+                true => self.decode_entry(self.next_offset),
+                false => self.move_past_end(),
+            }
+        } else if !self.is_past_end() {
+            self.seek_to_first();
+        }
     }
 
-    fn seek_to_offset(&mut self, offset: usize) {
+    /// Move to the previous key in the block.
+    pub fn prev(&mut self) {
+        if self.is_valid() && self.offset == 0 {
+            self.key.clear();
+            self.value.clear();
+        } else if self.is_valid() {
+            // Decode from the last restart point before the current entry.
+            let current = self.offset;
+            let restart = self
+                .block
+                .restarts
+                .partition_point(|&x| (x as usize) < current);
+            self.seek_to_restart(restart - 1);
+            while self.next_offset < current {
+                self.decode_entry(self.next_offset);
+            }
+        } else if self.is_past_end() {
+            self.seek_to_last();
+        }
+    }
+
+    /// Decode the entry at `offset`, whose key shares its prefix with the current key.
+    fn decode_entry(&mut self, offset: usize) {
         let mut entry = &self.block.data[offset..];
-        let key_len = entry.get_u16() as usize;
-        let key = entry[..key_len].to_vec();
-        entry.advance(key_len);
-        self.key.clear();
-        self.key.extend(key);
-        let value_len = entry.get_u16() as usize;
-        let value = entry[..value_len].to_vec();
-        entry.advance(value_len);
+        let shared = get_varint(&mut entry).expect("corrupted block") as usize;
+        let unshared = get_varint(&mut entry).expect("corrupted block") as usize;
+        self.key.truncate(shared);
+        self.key.extend(&entry[..unshared]);
+        entry.advance(unshared);
+        self.seq = entry.get_u64();
+        self.value_type = ValueType::try_from(entry.get_u8()).expect("corrupted block");
+        let value_len = get_varint(&mut entry).expect("corrupted block") as usize;
         self.value.clear();
-        self.value.extend(value);
+        self.value.extend(&entry[..value_len]);
+        entry.advance(value_len);
+        self.offset = offset;
+        self.next_offset = self.block.data.len() - entry.remaining();
     }
 
-    /// Seek to the first key that >= `key`.
+    /// Seek to the latest version of the first key that >= `key`: binary search for the last
+    /// restart point before the key, and scan from there.
     pub fn seek_to_key(&mut self, key: &[u8]) {
         let mut low = 0;
-        let mut high = self.block.offsets.len();
+        let mut high = self.block.restarts.len();
         while low < high {
             let mid = low + (high - low) / 2;
-            self.seek_to(mid);
-            assert!(self.is_valid());
-            match self.key().cmp(key) {
-                std::cmp::Ordering::Less => low = mid + 1,
-                std::cmp::Ordering::Greater => high = mid,
-                std::cmp::Ordering::Equal => return,
+            self.seek_to_restart(mid);
+            if self.key() < key {
+                low = mid + 1;
+            } else {
+                high = mid;
             }
         }
-        self.seek_to(low);
+        if low == 0 {
+            self.seek_to_first();
+        } else {
+            self.seek_to_restart(low - 1);
+        }
+        while self.is_valid() && self.key() < key {
+            self.next();
+        }
     }
 }
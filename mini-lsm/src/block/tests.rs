@@ -3,19 +3,20 @@ use std::sync::Arc;
 use super::builder::BlockBuilder;
 use super::iterator::BlockIterator;
 use super::*;
+use crate::value_type::ValueType;
 
 #[test]
 fn test_block_build_single_key() {
     let mut builder = BlockBuilder::new(16);
-    assert!(builder.add(b"233", b"233333"));
+    assert!(builder.add(b"233", 0, ValueType::Put, b"233333"));
     builder.build();
 }
 
 #[test]
 fn test_block_build_full() {
     let mut builder = BlockBuilder::new(16);
-    assert!(builder.add(b"11", b"11"));
-    assert!(!builder.add(b"22", b"22"));
+    assert!(builder.add(b"11", 0, ValueType::Put, b"11"));
+    assert!(!builder.add(b"22", 0, ValueType::Put, b"22"));
     builder.build();
 }
 
@@ -36,7 +37,7 @@ fn generate_block() -> Block {
     for idx in 0..num_of_keys() {
         let key = key_of(idx);
         let value = value_of(idx);
-        assert!(builder.add(&key[..], &value[..]));
+        assert!(builder.add(&key[..], 0, ValueType::Put, &value[..]));
     }
     builder.build()
 }
@@ -56,8 +57,8 @@ fn test_block_encode() {
 fn test_block_decode() {
     let block = generate_block();
     let encoded = block.encode();
-    let decoded_block = Block::decode(&encoded);
-    assert_eq!(block.offsets, decoded_block.offsets);
+    let decoded_block = Block::decode(&encoded).unwrap();
+    assert_eq!(block.restarts, decoded_block.restarts);
     assert_eq!(block.data, decoded_block.data);
 }
 
@@ -120,3 +121,209 @@ fn test_block_seek_key() {
         iter.seek_to_key(b"k");
     }
 }
+
+#[test]
+fn test_block_iterator_backward() {
+    let block = Arc::new(generate_block());
+    let mut iter = BlockIterator::create_and_seek_to_last(block);
+    for i in (0..num_of_keys()).rev() {
+        assert_eq!(iter.key(), key_of(i));
+        assert_eq!(iter.value(), value_of(i));
+        iter.prev();
+    }
+    assert!(!iter.is_valid());
+    // Before the start, the iterator comes back to the first entry.
+    iter.next();
+    assert_eq!(iter.key(), key_of(0));
+
+    iter.seek_to_key(&key_of(num_of_keys() - 1));
+    iter.next();
+    assert!(!iter.is_valid());
+    // Past the end, the iterator comes back to the last entry.
+    iter.prev();
+    assert_eq!(iter.key(), key_of(num_of_keys() - 1));
+    iter.prev();
+    assert_eq!(iter.key(), key_of(num_of_keys() - 2));
+    iter.next();
+    assert_eq!(iter.key(), key_of(num_of_keys() - 1));
+}
+
+fn check_block(block: Arc<Block>, keys: &[Vec<u8>]) {
+    let mut iter = BlockIterator::create_and_seek_to_first(block.clone());
+    for key in keys {
+        assert_eq!(iter.key(), &key[..]);
+        iter.next();
+    }
+    assert!(!iter.is_valid());
+    for key in keys.iter().rev() {
+        iter.prev();
+        assert_eq!(iter.key(), &key[..]);
+    }
+    for (idx, key) in keys.iter().enumerate() {
+        iter.seek_to_key(key);
+        assert_eq!(iter.key(), &key[..]);
+        // A key missing from the block seeks to the next one.
+        let mut missing = key.clone();
+        missing.push(0);
+        iter.seek_to_key(&missing);
+        match keys.get(idx + 1) {
+            Some(next) => assert_eq!(iter.key(), &next[..]),
+            None => assert!(!iter.is_valid()),
+        }
+    }
+    iter.seek_to_key(b"a");
+    assert_eq!(iter.key(), &keys[0][..]);
+}
+
+#[test]
+fn test_block_restart_interval() {
+    let keys: Vec<_> = (0..100)
+        .map(|idx| format!("tenant{:02}/object{:03}", idx / 10, idx).into_bytes())
+        .collect();
+    for restart_interval in [1, 2, 3, 16, 1000] {
+        let mut builder = BlockBuilder::new(10000).with_restart_interval(restart_interval);
+        for key in &keys {
+            assert!(builder.add(key, 0, ValueType::Put, b"value"));
+        }
+        let block = Block::decode(&builder.build().encode()).unwrap();
+        assert_eq!(block.restarts.len(), 100usize.div_ceil(restart_interval));
+        check_block(Arc::new(block), &keys);
+    }
+}
+
+#[test]
+fn test_block_versions_across_restarts() {
+    // The versions of a key span restart points, and a seek finds the latest one.
+    let mut builder = BlockBuilder::new(10000).with_restart_interval(2);
+    assert!(builder.add(b"a", 1, ValueType::Put, b"a"));
+    for seq in (1..=5).rev() {
+        assert!(builder.add(b"key", seq, ValueType::Put, b"value"));
+    }
+    let block = Arc::new(builder.build());
+    let mut iter = BlockIterator::create_and_seek_to_key(block, b"key");
+    assert_eq!((iter.key(), iter.seq()), (&b"key"[..], 5));
+    iter.seek_to_key(b"b");
+    assert_eq!((iter.key(), iter.seq()), (&b"key"[..], 5));
+}
+
+/// The name of a key shape and the function making its keys.
+type KeyShape = (&'static str, fn(usize) -> String);
+
+/// Print the size of blocks with keys sharing long prefixes, with and without prefix compression.
+/// Run with `--nocapture` to see the report.
+#[test]
+fn test_block_prefix_compression_savings() {
+    let shapes: [KeyShape; 3] = [
+        ("key_{:08}", |idx| format!("key_{:08}", idx)),
+        ("tenant/object/version", |idx| {
+            format!("tenant{:04}/object{:06}/v{:03}", idx / 1000, idx, idx % 7)
+        }),
+        ("user/event timestamp", |idx| {
+            format!(
+                "user{:08}/event/{:020}",
+                idx / 50,
+                1_700_000_000_000u64 + idx as u64
+            )
+        }),
+    ];
+    for (name, key_of) in shapes {
+        let size_with_interval = |restart_interval: usize| {
+            let mut builder = BlockBuilder::new(1 << 15).with_restart_interval(restart_interval);
+            let mut num_keys = 0;
+            while builder.add(key_of(num_keys).as_bytes(), 0, ValueType::Put, b"value") {
+                num_keys += 1;
+            }
+            let block = builder.build().encode();
+            block.len() as f64 / num_keys as f64
+        };
+        let full = size_with_interval(1);
+        let compressed = size_with_interval(16);
+        let savings = 1.0 - compressed / full;
+        println!(
+            "{}: {:.1} bytes per entry with full keys, {:.1} with shared prefixes, {:.0}% saved",
+            name,
+            full,
+            compressed,
+            savings * 100.0
+        );
+        assert!(savings > 0.1, "{}: {:.0}% saved", name, savings * 100.0);
+    }
+}
+
+#[test]
+fn test_block_decode_corrupted() {
+    let encoded = generate_block().encode();
+    // Every flipped bit is caught by the checksum.
+    for idx in 0..encoded.len() {
+        let mut corrupted = encoded.to_vec();
+        corrupted[idx] ^= 1 << (idx % 8);
+        assert!(Block::decode(&corrupted).is_err(), "flipped bit at {}", idx);
+    }
+    for len in 0..encoded.len() {
+        assert!(
+            Block::decode(&encoded[..len]).is_err(),
+            "truncated to {}",
+            len
+        );
+    }
+}
+
+#[test]
+fn test_block_decode_invalid_layout() {
+    let with_checksum = |mut data: Vec<u8>| {
+        data.put_u32(crc32fast::hash(&data));
+        data
+    };
+    // An entry longer than the block, with a valid checksum.
+    let mut data = Vec::new();
+    data.put_u16(0);
+    data.put_u16(100);
+    data.put_slice(b"key");
+    data.put_u16(0);
+    data.put_u16(1);
+    assert!(Block::decode(&with_checksum(data)).is_err());
+    // A restart point out of the entries.
+    let mut data = Vec::new();
+    data.put_u16(0);
+    data.put_u16(1);
+    data.put_slice(b"k");
+    data.put_u64(0);
+    data.put_u8(0);
+    data.put_u16(0);
+    data.put_u16(0);
+    data.put_u16(1000);
+    data.put_u16(2);
+    assert!(Block::decode(&with_checksum(data)).is_err());
+}
+
+#[test]
+fn test_block_large_entries() {
+    // A key and a value longer than 64 KiB, in a block larger than the block size.
+    let key = vec![b'k'; 100_000];
+    let value: Vec<u8> = (0..3 << 20).map(|x| x as u8).collect();
+    let mut builder = BlockBuilder::new(4096);
+    assert!(builder.add(&key, 1, ValueType::Put, &value));
+    assert!(!builder.add(b"z", 0, ValueType::Put, b"v"));
+    let block = Block::decode(&builder.build().encode()).unwrap();
+    let iter = BlockIterator::create_and_seek_to_first(Arc::new(block));
+    assert_eq!(iter.key(), &key[..]);
+    assert_eq!(iter.value(), &value[..]);
+
+    // Entries and restart points beyond 64 KiB into the block.
+    let mut builder = BlockBuilder::new(1 << 20).with_restart_interval(4);
+    let key_of = |idx: usize| format!("key_{:06}", idx).into_bytes();
+    let value = vec![b'v'; 1000];
+    for idx in 0..500 {
+        assert!(builder.add(&key_of(idx), 0, ValueType::Put, &value));
+    }
+    let block = Arc::new(Block::decode(&builder.build().encode()).unwrap());
+    assert!(block.data.len() > 1 << 18);
+    let mut iter = BlockIterator::create_and_seek_to_key(block.clone(), &key_of(450));
+    assert_eq!(iter.key(), key_of(450));
+    iter.prev();
+    assert_eq!(iter.key(), key_of(449));
+    let mut iter = BlockIterator::create_and_seek_to_last(block);
+    assert_eq!(iter.key(), key_of(499));
+    iter.prev();
+    assert_eq!(iter.key(), key_of(498));
+}
@@ -0,0 +1,268 @@
+mod leveled;
+mod tiered;
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+pub use leveled::LeveledCompactionOptions;
+pub use tiered::TieredCompactionOptions;
+
+use crate::iterators::merge_iterator::MergeIterator;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{
+    apply_compaction, apply_tiered_compaction, LsmStorageCore, LsmStorageInner,
+};
+use crate::manifest::ManifestRecord;
+use crate::merge_operator::collapse_operands;
+use crate::range_tombstone::RangeTombstone;
+use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+use crate::value_type::ValueType;
+
+/// The compaction strategy of the storage.
+#[derive(Clone, Debug)]
+pub enum CompactionOptions {
+    /// Keep L0 small by merging it into L1, and push each level into the next one once it
+    /// outgrows its target size.
+    Leveled(LeveledCompactionOptions),
+    /// Merge sorted runs of similar size, trading space amplification for less write
+    /// amplification.
+    Tiered(TieredCompactionOptions),
+    /// Never compact. All SSTs stay in L0.
+    NoCompaction,
+}
+
+impl Default for CompactionOptions {
+    fn default() -> Self {
+        CompactionOptions::Leveled(LeveledCompactionOptions::default())
+    }
+}
+
+impl CompactionOptions {
+    /// Pick the next compaction to run on `snapshot`, if any is needed.
+    pub(crate) fn generate_task(&self, snapshot: &LsmStorageInner) -> Option<CompactionTask> {
+        match self {
+            CompactionOptions::Leveled(options) => options.generate_task(snapshot),
+            CompactionOptions::Tiered(options) => options.generate_task(snapshot),
+            CompactionOptions::NoCompaction => None,
+        }
+    }
+
+    /// Check that the options make sense, before the storage runs with them.
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            CompactionOptions::Leveled(options) => options.validate(),
+            CompactionOptions::Tiered(options) => options.validate(),
+            CompactionOptions::NoCompaction => Ok(()),
+        }
+    }
+
+    /// The space amplification the strategy aims for, i.e. the total size of the SSTs over the
+    /// size of the live data, once the LSM tree is in a steady state. `None` if unbounded.
+    pub fn target_space_amplification(&self) -> Option<f64> {
+        match self {
+            CompactionOptions::Leveled(options) => Some(options.target_space_amplification()),
+            CompactionOptions::Tiered(options) => Some(options.target_space_amplification()),
+            CompactionOptions::NoCompaction => None,
+        }
+    }
+}
+
+/// Where the output of a compaction goes.
+pub(crate) enum CompactionOutput {
+    /// Into this level, at the place of the input SSTs.
+    Level(usize),
+    /// Into a new level in front of all other levels.
+    NewRun,
+}
+
+/// A compaction picked by a compaction strategy.
+pub(crate) struct CompactionTask {
+    /// The SSTs to merge, ordered from the newest data to the oldest.
+    pub(crate) ssts: Vec<Arc<SsTable>>,
+    pub(crate) output: CompactionOutput,
+    /// Whether no level below the output has data, so that tombstones can be dropped.
+    pub(crate) is_bottom_level: bool,
+}
+
+impl LsmStorageCore {
+    /// Run compactions until the LSM tree satisfies the compaction options. The caller must hold
+    /// the flush lock.
+    pub(crate) fn compact(&self) -> Result<()> {
+        loop {
+            let snapshot = {
+                let guard = self.inner.read();
+                Arc::clone(&guard)
+            }; // drop global lock here
+            let Some(task) = self.options.compaction_options.generate_task(&snapshot) else {
+                return Ok(());
+            };
+
+            let output = self.compact_ssts(&task)?;
+            self.sync_dir()?;
+            let removed: Vec<usize> = task.ssts.iter().map(|sst| sst.sst_id()).collect();
+            let added: Vec<usize> = output.iter().map(|sst| sst.sst_id()).collect();
+            let record = match task.output {
+                CompactionOutput::Level(level) => ManifestRecord::Compaction {
+                    level,
+                    removed: removed.clone(),
+                    added,
+                },
+                CompactionOutput::NewRun => ManifestRecord::TieredCompaction {
+                    removed: removed.clone(),
+                    added,
+                },
+            };
+            self.manifest.add_record(record)?;
+
+            {
+                let mut guard = self.inner.write();
+                let mut snapshot = guard.as_ref().clone();
+                let id_of = |sst: &Arc<SsTable>| sst.sst_id();
+                match task.output {
+                    CompactionOutput::Level(level) => {
+                        apply_compaction(
+                            &mut snapshot.l0_sstables,
+                            &mut snapshot.levels,
+                            level,
+                            &removed,
+                            output,
+                            id_of,
+                        );
+                        snapshot.levels[level - 1].sort_by(|a, b| a.first_key().cmp(b.first_key()));
+                    }
+                    CompactionOutput::NewRun => apply_tiered_compaction(
+                        &mut snapshot.l0_sstables,
+                        &mut snapshot.levels,
+                        &removed,
+                        output,
+                        id_of,
+                    ),
+                }
+                *guard = Arc::new(snapshot);
+            }
+
+            // Readers holding an older snapshot keep the files open, so they can be deleted now.
+            for id in removed {
+                std::fs::remove_file(self.path_of_sst(id))?;
+            }
+            self.sync_dir()?;
+        }
+    }
+
+    /// Merge the SSTs of `task` into new SSTs of about `target_sst_size` bytes each.
+    ///
+    /// Versions above the watermark are kept for the live snapshots, along with the latest version
+    /// at or below it, which is what the oldest snapshot reads. Older versions are visible to no
+    /// one and are dropped.
+    ///
+    /// A range tombstone at or below the watermark deletes the versions it covers for everyone,
+    /// so they are dropped too, and in the bottom level, so is the tombstone. The other range
+    /// tombstones are split along the output SSTs.
+    ///
+    /// Merge operands at or below the watermark are collapsed with the versions they apply to.
+    fn compact_ssts(&self, task: &CompactionTask) -> Result<Vec<Arc<SsTable>>> {
+        let watermark = self.watermark();
+        let mut range_tombstones: Vec<RangeTombstone> = task
+            .ssts
+            .iter()
+            .flat_map(|sst| sst.range_tombstones().iter().cloned())
+            .collect();
+        let settled: Vec<RangeTombstone> = range_tombstones
+            .iter()
+            .filter(|t| t.seq <= watermark)
+            .cloned()
+            .collect();
+        if task.is_bottom_level {
+            range_tombstones.retain(|t| t.seq > watermark);
+        }
+
+        let mut iters = Vec::with_capacity(task.ssts.len());
+        for sst in &task.ssts {
+            iters.push(Box::new(SsTableIterator::create_and_seek_to_first(
+                sst.clone(),
+            )?));
+        }
+        let mut iter = MergeIterator::create(iters);
+
+        // A new run goes in front of all levels, as the first one.
+        let level = match task.output {
+            CompactionOutput::Level(level) => level,
+            CompactionOutput::NewRun => 1,
+        };
+        let mut output = Vec::new();
+        let mut builder: Option<SsTableBuilder> = None;
+        // The first key of the current output SST, or `None` for the first one.
+        let mut lower: Option<Bytes> = None;
+        let mut prev_key = Vec::new();
+        let mut below_watermark = false;
+        while iter.is_valid() {
+            if iter.key() != prev_key {
+                // Only split at a new key, so that all versions of a key stay in the same SST.
+                if builder
+                    .as_ref()
+                    .is_some_and(|b| b.estimated_size() >= self.options.target_sst_size)
+                {
+                    let mut inner = builder.take().unwrap();
+                    for t in &range_tombstones {
+                        if let Some(t) = t.clip(lower.as_deref(), Some(iter.key())) {
+                            inner.add_range_tombstone(t);
+                        }
+                    }
+                    output.push(self.build_sst(inner)?);
+                    lower = Some(Bytes::copy_from_slice(iter.key()));
+                }
+                prev_key.clear();
+                prev_key.extend(iter.key());
+                below_watermark = false;
+            }
+            if let Some(operator) = self.options.merge_operator.as_deref() {
+                let first_below = iter.seq() <= watermark && !below_watermark;
+                if first_below && iter.value_type() == ValueType::Merge {
+                    let key = Bytes::copy_from_slice(iter.key());
+                    let entries =
+                        collapse_operands(&mut iter, operator, &settled, task.is_bottom_level)?;
+                    let inner = builder.get_or_insert_with(|| self.new_sst_builder(level));
+                    for (seq, value_type, value) in entries {
+                        inner.add(&key, seq, value_type, &value);
+                    }
+                    continue;
+                }
+            }
+            let keep = if iter.seq() > watermark {
+                true
+            } else if below_watermark {
+                false
+            } else {
+                below_watermark = true;
+                // Nothing below can be shadowed by a tombstone in the bottom level.
+                let bottom_delete = task.is_bottom_level && iter.value_type() == ValueType::Delete;
+                !bottom_delete && !settled.iter().any(|t| t.covers(iter.key(), iter.seq()))
+            };
+            if keep {
+                let inner = builder.get_or_insert_with(|| self.new_sst_builder(level));
+                inner.add(iter.key(), iter.seq(), iter.value_type(), iter.value());
+            }
+            iter.next()?;
+        }
+        let mut last = builder.unwrap_or_else(|| self.new_sst_builder(level));
+        for t in &range_tombstones {
+            if let Some(t) = t.clip(lower.as_deref(), None) {
+                last.add_range_tombstone(t);
+            }
+        }
+        if !last.is_empty() {
+            output.push(self.build_sst(last)?);
+        }
+        Ok(output)
+    }
+
+    fn build_sst(&self, builder: SsTableBuilder) -> Result<Arc<SsTable>> {
+        let sst_id = self.next_sst_id();
+        Ok(Arc::new(builder.build(
+            sst_id,
+            Some(self.block_cache.clone()),
+            self.path_of_sst(sst_id),
+        )?))
+    }
+}
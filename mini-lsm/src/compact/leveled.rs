@@ -0,0 +1,124 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use anyhow::{ensure, Result};
+
+use super::{CompactionOutput, CompactionTask};
+use crate::lsm_storage::LsmStorageInner;
+use crate::table::SsTable;
+
+/// Options of leveled compaction.
+#[derive(Clone, Debug)]
+pub struct LeveledCompactionOptions {
+    /// Merge L0 into L1 once L0 has this many SSTs.
+    pub level0_file_num_compaction_trigger: usize,
+    /// The number of levels below L0.
+    pub max_levels: usize,
+    /// The target size of L1 in bytes.
+    pub base_level_size: u64,
+    /// Each level is this many times larger than the level above it.
+    pub level_size_multiplier: u64,
+}
+
+impl Default for LeveledCompactionOptions {
+    fn default() -> Self {
+        Self {
+            level0_file_num_compaction_trigger: 4,
+            max_levels: 6,
+            base_level_size: 16 << 20, // 16MB
+            level_size_multiplier: 10,
+        }
+    }
+}
+
+impl LeveledCompactionOptions {
+    pub(crate) fn validate(&self) -> Result<()> {
+        ensure!(
+            self.level0_file_num_compaction_trigger > 0,
+            "level 0 compaction trigger must be positive"
+        );
+        ensure!(self.max_levels > 0, "max levels must be positive");
+        ensure!(self.base_level_size > 0, "base level size must be positive");
+        ensure!(
+            self.level_size_multiplier > 0,
+            "level size multiplier must be positive"
+        );
+        let max_level_size = u32::try_from(self.max_levels - 1)
+            .ok()
+            .and_then(|exp| self.level_size_multiplier.checked_pow(exp))
+            .and_then(|x| x.checked_mul(self.base_level_size));
+        ensure!(
+            max_level_size.is_some(),
+            "target size of the last level overflows"
+        );
+        Ok(())
+    }
+
+    /// Get the target size of level `level` (1-based) in bytes.
+    pub fn target_level_size(&self, level: usize) -> u64 {
+        self.base_level_size * self.level_size_multiplier.pow(level as u32 - 1)
+    }
+
+    /// The space amplification of a full tree, where the last level holds all live data and each
+    /// level above is at its target size.
+    pub fn target_space_amplification(&self) -> f64 {
+        let total: u64 = (1..=self.max_levels)
+            .map(|level| self.target_level_size(level))
+            .sum();
+        total as f64 / self.target_level_size(self.max_levels) as f64
+    }
+
+    pub(crate) fn generate_task(&self, snapshot: &LsmStorageInner) -> Option<CompactionTask> {
+        let level_ssts = |level: usize| snapshot.levels.get(level - 1).cloned().unwrap_or_default();
+        let is_bottom_level = |level: usize| {
+            snapshot
+                .levels
+                .iter()
+                .skip(level)
+                .all(|ssts| ssts.is_empty())
+        };
+
+        // The SSTs of `level` whose key range overlaps any of `ssts`.
+        let overlapping_ssts = |ssts: &[Arc<SsTable>], level: usize| {
+            let first_key = ssts.iter().map(|sst| sst.first_key()).min().unwrap();
+            let last_key = ssts.iter().map(|sst| sst.last_key()).max().unwrap();
+            level_ssts(level)
+                .into_iter()
+                .filter(|sst| {
+                    sst.range_overlap(Bound::Included(first_key), Bound::Included(last_key))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Merge all of L0 and the overlapping part of L1 into L1.
+        if snapshot.l0_sstables.len() >= self.level0_file_num_compaction_trigger {
+            let mut ssts: Vec<_> = snapshot.l0_sstables.iter().rev().cloned().collect();
+            ssts.extend(overlapping_ssts(&ssts, 1));
+            return Some(CompactionTask {
+                ssts,
+                output: CompactionOutput::Level(1),
+                is_bottom_level: is_bottom_level(1),
+            });
+        }
+
+        // Push the oldest SST of the level that exceeds its target size the most into the next
+        // one, along with the SSTs it overlaps there.
+        let (level, _) = (1..self.max_levels)
+            .map(|level| {
+                let size: u64 = level_ssts(level).iter().map(|sst| sst.table_size()).sum();
+                (level, size as f64 / self.target_level_size(level) as f64)
+            })
+            .filter(|(_, ratio)| *ratio > 1.0)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+        let oldest = level_ssts(level)
+            .into_iter()
+            .min_by_key(|sst| sst.sst_id())?;
+        let mut ssts = vec![oldest];
+        ssts.extend(overlapping_ssts(&ssts, level + 1));
+        Some(CompactionTask {
+            ssts,
+            output: CompactionOutput::Level(level + 1),
+            is_bottom_level: is_bottom_level(level + 1),
+        })
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::Arc;
+
+use anyhow::{ensure, Result};
+
+use super::{CompactionOutput, CompactionTask};
+use crate::lsm_storage::LsmStorageInner;
+use crate::table::SsTable;
+
+/// Options of tiered (universal) compaction.
+///
+/// Each L0 SST and each level below L0 is a sorted run, from the newest to the oldest. Runs of
+/// similar size are merged into a new run, and the new run becomes the first level. L0 is always
+/// merged as a whole, so that the new run is older than everything left in L0.
+#[derive(Clone, Debug)]
+pub struct TieredCompactionOptions {
+    /// Compact once there are at least this many sorted runs.
+    pub num_tiers: usize,
+    /// Merge all runs once the size of all runs but the oldest one exceeds this percentage of the
+    /// oldest run.
+    pub max_size_amplification_percent: usize,
+    /// Merge the newest runs once the next run is larger than their total size by more than this
+    /// percentage.
+    pub size_ratio: usize,
+    /// Merge at least this many runs when merging by size ratio.
+    pub min_merge_width: usize,
+}
+
+impl Default for TieredCompactionOptions {
+    fn default() -> Self {
+        Self {
+            num_tiers: 8,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+        }
+    }
+}
+
+impl TieredCompactionOptions {
+    pub(crate) fn validate(&self) -> Result<()> {
+        ensure!(self.num_tiers >= 2, "number of tiers must be at least 2");
+        ensure!(
+            self.min_merge_width >= 2,
+            "min merge width must be at least 2"
+        );
+        Ok(())
+    }
+
+    /// The space amplification this strategy keeps the LSM tree under.
+    pub fn target_space_amplification(&self) -> f64 {
+        1.0 + self.max_size_amplification_percent as f64 / 100.0
+    }
+
+    pub(crate) fn generate_task(&self, snapshot: &LsmStorageInner) -> Option<CompactionTask> {
+        let runs: Vec<Vec<Arc<SsTable>>> = snapshot
+            .l0_sstables
+            .iter()
+            .rev()
+            .map(|sst| vec![sst.clone()])
+            .chain(
+                snapshot
+                    .levels
+                    .iter()
+                    .filter(|level| !level.is_empty())
+                    .cloned(),
+            )
+            .collect();
+        if runs.len() < self.num_tiers.max(2) {
+            return None;
+        }
+        let sizes: Vec<u64> = runs
+            .iter()
+            .map(|run| run.iter().map(|sst| sst.table_size()).sum())
+            .collect();
+
+        // Space amplification: merge everything into one run.
+        let newer_size: u64 = sizes[..sizes.len() - 1].iter().sum();
+        let oldest_size = sizes[sizes.len() - 1];
+        if newer_size * 100 >= oldest_size * self.max_size_amplification_percent as u64 {
+            return Some(self.merge_runs(snapshot, runs, sizes.len()));
+        }
+
+        // Size ratio: merge the newest runs if the next run is much larger than all of them.
+        let mut size = 0;
+        for idx in 0..sizes.len() - 1 {
+            size += sizes[idx];
+            let ratio = sizes[idx + 1] as f64 / size as f64;
+            if ratio > (100.0 + self.size_ratio as f64) / 100.0 && idx + 1 >= self.min_merge_width {
+                return Some(self.merge_runs(snapshot, runs, idx + 1));
+            }
+        }
+
+        // Reduce the number of sorted runs below the trigger.
+        let num_runs = runs.len() - self.num_tiers + 2;
+        Some(self.merge_runs(snapshot, runs, num_runs))
+    }
+
+    /// Merge the newest `num_runs` runs, extended to cover all of L0.
+    fn merge_runs(
+        &self,
+        snapshot: &LsmStorageInner,
+        runs: Vec<Vec<Arc<SsTable>>>,
+        num_runs: usize,
+    ) -> CompactionTask {
+        let num_runs = num_runs.max(snapshot.l0_sstables.len()).min(runs.len());
+        let is_bottom_level = num_runs == runs.len();
+        CompactionTask {
+            ssts: runs.into_iter().take(num_runs).flatten().collect(),
+            output: CompactionOutput::NewRun,
+            is_bottom_level,
+        }
+    }
+}
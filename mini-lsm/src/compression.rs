@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::{bail, ensure, Result};
+use bytes::{Buf, BufMut};
+
+use crate::block::SIZEOF_U32;
+use crate::varint::{get_varint, put_varint};
+
+/// Compresses the blocks of an SST. Codecs other than [`LzCompressor`] are added to a
+/// [`CompressorRegistry`] to be used.
+pub trait Compressor: Send + Sync {
+    /// Identify the codec in the SSTs, in the byte stored after each block it compressed. Types 0
+    /// and 1 are taken by [`CompressionType::NONE`] and [`CompressionType::LZ`].
+    fn compression_type(&self) -> CompressionType;
+
+    /// Compress `data`, appending the result to `buf`.
+    fn compress(&self, data: &[u8], buf: &mut Vec<u8>);
+
+    /// Decompress `data` written by [`Compressor::compress`], appending the result to `buf`. Must
+    /// fail rather than panic on any other input, as it may come from a corrupted file.
+    fn decompress(&self, data: &[u8], buf: &mut Vec<u8>) -> Result<()>;
+}
+
+impl fmt::Debug for dyn Compressor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Compressor({})", self.compression_type().0)
+    }
+}
+
+/// The compression of a block, stored in a byte after it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CompressionType(pub u8);
+
+impl CompressionType {
+    /// The block is stored as it is.
+    pub const NONE: Self = Self(0);
+    /// The block is compressed by [`LzCompressor`].
+    pub const LZ: Self = Self(1);
+}
+
+/// The codecs that SSTs are written and read with, by their compression type. Holds
+/// [`LzCompressor`] by default.
+#[derive(Clone)]
+pub struct CompressorRegistry {
+    compressors: HashMap<CompressionType, Arc<dyn Compressor>>,
+}
+
+impl CompressorRegistry {
+    /// Add `compressor` under its compression type. Fails if the type is
+    /// [`CompressionType::NONE`] or already taken.
+    pub fn register(&mut self, compressor: Arc<dyn Compressor>) -> Result<()> {
+        let compression = compressor.compression_type();
+        ensure!(
+            compression != CompressionType::NONE,
+            "compression type 0 is reserved for uncompressed blocks"
+        );
+        ensure!(
+            !self.compressors.contains_key(&compression),
+            "compression type {} is already registered",
+            compression.0
+        );
+        self.compressors.insert(compression, compressor);
+        Ok(())
+    }
+
+    /// Get the codec of `compression`, or `None` for [`CompressionType::NONE`]. Fails if no codec
+    /// is registered under it.
+    pub fn get(&self, compression: CompressionType) -> Result<Option<Arc<dyn Compressor>>> {
+        if compression == CompressionType::NONE {
+            return Ok(None);
+        }
+        match self.compressors.get(&compression) {
+            Some(compressor) => Ok(Some(compressor.clone())),
+            None => bail!("unknown compression type {}", compression.0),
+        }
+    }
+}
+
+impl Default for CompressorRegistry {
+    fn default() -> Self {
+        let lz: Arc<dyn Compressor> = Arc::new(LzCompressor);
+        Self {
+            compressors: HashMap::from([(CompressionType::LZ, lz)]),
+        }
+    }
+}
+
+impl fmt::Debug for CompressorRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut types: Vec<_> = self.compressors.keys().map(|x| x.0).collect();
+        types.sort_unstable();
+        f.debug_struct("CompressorRegistry")
+            .field("types", &types)
+            .finish()
+    }
+}
+
+/// Append `block` to `buf`, compressed with `compressor`, as:
+///
+/// ```plaintext
+/// | compressed block | compression type (u8) | checksum (u32) |
+/// ```
+///
+/// where the checksum covers the stored bytes, so that they are checked before being fed to the
+/// decompressor. The block is stored as it is if there is no compressor, or if compressing saves
+/// less than an eighth of its size, which is not worth decompressing it on every read.
+pub fn compress_block(block: &[u8], compressor: Option<&dyn Compressor>, buf: &mut Vec<u8>) {
+    let offset = buf.len();
+    let mut stored = CompressionType::NONE;
+    if let Some(compressor) = compressor {
+        compressor.compress(block, buf);
+        if buf.len() - offset < block.len() - block.len() / 8 {
+            stored = compressor.compression_type();
+        } else {
+            buf.truncate(offset);
+        }
+    }
+    if stored == CompressionType::NONE {
+        buf.put_slice(block);
+    }
+    buf.put_u8(stored.0);
+    let checksum = crc32fast::hash(&buf[offset..]);
+    buf.put_u32(checksum);
+}
+
+/// Verify the checksum of a block written by [`compress_block`] with `compressor`, and
+/// decompress it.
+pub fn decompress_block(mut data: Vec<u8>, compressor: Option<&dyn Compressor>) -> Result<Vec<u8>> {
+    ensure!(data.len() > SIZEOF_U32, "block too short");
+    let checksum = (&data[data.len() - SIZEOF_U32..]).get_u32();
+    data.truncate(data.len() - SIZEOF_U32);
+    ensure!(
+        crc32fast::hash(&data) == checksum,
+        "block checksum mismatch"
+    );
+    decompress_block_v2(data, compressor)
+}
+
+/// Decompress a block written in SST format version 2, followed by its compression type but
+/// without a checksum of the stored bytes.
+pub fn decompress_block_v2(
+    mut data: Vec<u8>,
+    compressor: Option<&dyn Compressor>,
+) -> Result<Vec<u8>> {
+    let Some(compression) = data.pop() else {
+        bail!("block too short");
+    };
+    let compression = CompressionType(compression);
+    if compression == CompressionType::NONE {
+        return Ok(data);
+    }
+    match compressor {
+        Some(compressor) if compressor.compression_type() == compression => {
+            let mut buf = Vec::new();
+            compressor.decompress(&data, &mut buf)?;
+            Ok(buf)
+        }
+        _ => bail!("unexpected compression type {}", compression.0),
+    }
+}
+
+/// A byte-oriented LZ77 codec in the spirit of LZ4, fast rather than compact. The input is split
+/// into sequences of literals followed by a copy of earlier output, found with a hash table of
+/// 4-byte words, and encoded as:
+///
+/// ```plaintext
+/// | len (varint) | sequence | ... | sequence |
+/// ```
+///
+/// where `len` is the size of the decompressed data, and each sequence is:
+///
+/// ```plaintext
+/// | literal len (varint) | literals | match len (varint) | match offset (varint) |
+/// ```
+///
+/// The last sequence has a match length of 0 and no offset.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LzCompressor;
+
+impl LzCompressor {
+    const MIN_MATCH: usize = 4;
+    const HASH_BITS: u32 = 14;
+    const MAX_OFFSET: usize = 1 << 16;
+
+    fn hash(data: &[u8]) -> usize {
+        let word = u32::from_le_bytes(data[..Self::MIN_MATCH].try_into().unwrap());
+        (word.wrapping_mul(0x9e37_79b1) >> (u32::BITS - Self::HASH_BITS)) as usize
+    }
+}
+
+impl Compressor for LzCompressor {
+    fn compression_type(&self) -> CompressionType {
+        CompressionType::LZ
+    }
+
+    fn compress(&self, data: &[u8], buf: &mut Vec<u8>) {
+        put_varint(buf, data.len() as u64);
+        // The last position of each hashed word.
+        let mut table = vec![usize::MAX; 1 << Self::HASH_BITS];
+        let mut anchor = 0;
+        let mut pos = 0;
+        while pos + Self::MIN_MATCH <= data.len() {
+            let candidate = std::mem::replace(&mut table[Self::hash(&data[pos..])], pos);
+            let found = candidate != usize::MAX
+                && pos - candidate <= Self::MAX_OFFSET
+                && data[candidate..candidate + Self::MIN_MATCH] == data[pos..pos + Self::MIN_MATCH];
+            if !found {
+                pos += 1;
+                continue;
+            }
+            let len = Self::MIN_MATCH
+                + data[pos + Self::MIN_MATCH..]
+                    .iter()
+                    .zip(&data[candidate + Self::MIN_MATCH..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+            put_varint(buf, (pos - anchor) as u64);
+            buf.put_slice(&data[anchor..pos]);
+            put_varint(buf, len as u64);
+            put_varint(buf, (pos - candidate) as u64);
+            pos += len;
+            anchor = pos;
+        }
+        put_varint(buf, (data.len() - anchor) as u64);
+        buf.put_slice(&data[anchor..]);
+        put_varint(buf, 0);
+    }
+
+    fn decompress(&self, mut data: &[u8], buf: &mut Vec<u8>) -> Result<()> {
+        let len = get_varint(&mut data)? as usize;
+        let start = buf.len();
+        // The length is not trusted until the data is decompressed.
+        buf.reserve(len.min(data.len().saturating_mul(8)));
+        loop {
+            let remaining = len - (buf.len() - start);
+            let literal_len = get_varint(&mut data)? as usize;
+            ensure!(
+                literal_len <= data.len() && literal_len <= remaining,
+                "invalid literal length"
+            );
+            buf.extend_from_slice(&data[..literal_len]);
+            data.advance(literal_len);
+            let match_len = get_varint(&mut data)? as usize;
+            if match_len == 0 {
+                break;
+            }
+            let offset = get_varint(&mut data)? as usize;
+            ensure!(
+                offset > 0 && offset <= buf.len() - start,
+                "invalid match offset"
+            );
+            ensure!(match_len <= remaining - literal_len, "invalid match length");
+            let from = buf.len() - offset;
+            if offset >= match_len {
+                buf.extend_from_within(from..from + match_len);
+            } else {
+                // The match overlaps itself, repeating the last `offset` bytes.
+                for idx in 0..match_len {
+                    buf.push(buf[from + idx]);
+                }
+            }
+        }
+        ensure!(
+            data.is_empty() && buf.len() - start == len,
+            "invalid compressed data"
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,137 @@
+use super::*;
+
+fn roundtrip(data: &[u8]) -> usize {
+    let mut compressed = Vec::new();
+    LzCompressor.compress(data, &mut compressed);
+    let mut decompressed = b"prefix".to_vec();
+    LzCompressor
+        .decompress(&compressed, &mut decompressed)
+        .unwrap();
+    assert_eq!(&decompressed[6..], data);
+    compressed.len()
+}
+
+#[test]
+fn test_lz_roundtrip() {
+    roundtrip(b"");
+    roundtrip(b"abc");
+    roundtrip(&[7; 100_000]);
+    // Matches overlapping themselves, and far apart.
+    roundtrip(b"abcabcabcabcabcabcabcabcx");
+    let mut data: Vec<u8> = (0..200_000u32).map(|x| (x * 7919 % 251) as u8).collect();
+    data.extend_from_within(..1000);
+    roundtrip(&data);
+
+    let text: Vec<u8> = (0..2000)
+        .flat_map(|idx| format!("key_{:05} => value of key {:05}\n", idx, idx).into_bytes())
+        .collect();
+    let size = roundtrip(&text);
+    assert!(size * 2 < text.len(), "{} of {} bytes", size, text.len());
+}
+
+#[test]
+fn test_lz_invalid() {
+    let data: Vec<u8> = b"hello hello hello hello".repeat(10);
+    let mut compressed = Vec::new();
+    LzCompressor.compress(&data, &mut compressed);
+    // Truncated, trailing garbage, and any single corrupted byte fail or decompress to something
+    // else, without panicking.
+    for len in 0..compressed.len() {
+        assert!(LzCompressor
+            .decompress(&compressed[..len], &mut Vec::new())
+            .is_err());
+    }
+    let mut extended = compressed.clone();
+    extended.push(0);
+    assert!(LzCompressor.decompress(&extended, &mut Vec::new()).is_err());
+    for idx in 0..compressed.len() {
+        for bit in 0..8 {
+            let mut corrupted = compressed.clone();
+            corrupted[idx] ^= 1 << bit;
+            let mut buf = Vec::new();
+            if LzCompressor.decompress(&corrupted, &mut buf).is_ok() {
+                assert_ne!(buf, data);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_compress_block() {
+    let text = b"value value value value value value value value".repeat(20);
+    let mut buf = Vec::new();
+    compress_block(&text, Some(&LzCompressor), &mut buf);
+    assert!(buf.len() < text.len() / 2);
+    assert_eq!(buf[buf.len() - 5], CompressionType::LZ.0);
+    assert_eq!(
+        decompress_block(buf.clone(), Some(&LzCompressor)).unwrap(),
+        text
+    );
+    // A block compressed by another codec than the one of the SST is rejected.
+    let err = decompress_block(buf.clone(), None).unwrap_err();
+    assert_eq!(err.to_string(), "unexpected compression type 1");
+
+    // The checksum covers the stored bytes, which are checked before decompressing them.
+    for pos in [0, buf.len() / 2, buf.len() - 5] {
+        let mut corrupted = buf.clone();
+        corrupted[pos] ^= 1;
+        let err = decompress_block(corrupted, Some(&LzCompressor)).unwrap_err();
+        assert_eq!(err.to_string(), "block checksum mismatch");
+    }
+
+    // Data that does not compress is stored as it is.
+    let random: Vec<u8> = (0..1000u64)
+        .map(|x| (x.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 56) as u8)
+        .collect();
+    let mut buf = Vec::new();
+    compress_block(&random, Some(&LzCompressor), &mut buf);
+    assert_eq!(buf.len(), random.len() + 5);
+    assert_eq!(buf[random.len()], CompressionType::NONE.0);
+    assert_eq!(decompress_block(buf, Some(&LzCompressor)).unwrap(), random);
+
+    assert!(decompress_block(vec![], None).is_err());
+    assert!(decompress_block(vec![1, 2, 3, 9], None).is_err());
+}
+
+/// Stores the data as it is, under its own compression type.
+struct IdentityCompressor(u8);
+
+impl Compressor for IdentityCompressor {
+    fn compression_type(&self) -> CompressionType {
+        CompressionType(self.0)
+    }
+
+    fn compress(&self, data: &[u8], buf: &mut Vec<u8>) {
+        buf.extend_from_slice(data);
+    }
+
+    fn decompress(&self, data: &[u8], buf: &mut Vec<u8>) -> Result<()> {
+        buf.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_compressor_registry() {
+    let mut registry = CompressorRegistry::default();
+    assert!(registry.get(CompressionType::NONE).unwrap().is_none());
+    let lz = registry.get(CompressionType::LZ).unwrap().unwrap();
+    assert_eq!(lz.compression_type(), CompressionType::LZ);
+    let err = registry.get(CompressionType(7)).err().unwrap();
+    assert_eq!(err.to_string(), "unknown compression type 7");
+
+    registry.register(Arc::new(IdentityCompressor(7))).unwrap();
+    let identity = registry.get(CompressionType(7)).unwrap().unwrap();
+    assert_eq!(identity.compression_type(), CompressionType(7));
+    assert_eq!(
+        format!("{:?}", registry),
+        "CompressorRegistry { types: [1, 7] }"
+    );
+
+    // Types 0 and 1 are taken, as is any type registered before.
+    for compression in [0, 1, 7] {
+        assert!(registry
+            .register(Arc::new(IdentityCompressor(compression)))
+            .is_err());
+    }
+}
@@ -1,18 +1,37 @@
+pub mod concat_iterator;
 pub mod merge_iterator;
 pub mod two_merge_iterator;
 
+use crate::value_type::ValueType;
+
 pub trait StorageIterator {
     /// Get the current value.
     fn value(&self) -> &[u8];
 
+    /// Get the type of the current entry.
+    fn value_type(&self) -> ValueType;
+
     /// Get the current key.
     fn key(&self) -> &[u8];
 
+    /// Get the sequence number of the current entry. Versions of the same key are produced from
+    /// the latest to the earliest.
+    fn seq(&self) -> u64;
+
     /// Check if the current iterator is valid.
     fn is_valid(&self) -> bool;
 
-    /// Move to the next position.
+    /// Move to the next position. An iterator moved before the first entry with `prev` comes
+    /// back to the first entry.
     fn next(&mut self) -> anyhow::Result<()>;
+
+    /// Move to the previous position. An iterator moved past the last entry with `next` comes
+    /// back to the last entry.
+    fn prev(&mut self) -> anyhow::Result<()>;
+
+    /// Move forward to the first entry with a key >= `key`. An iterator over a range of keys stays
+    /// within the range, seeking to its first entry for a key before it.
+    fn seek(&mut self, key: &[u8]) -> anyhow::Result<()>;
 }
 
 #[cfg(test)]
@@ -0,0 +1,171 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use super::StorageIterator;
+use crate::table::{SsTable, SsTableIterator};
+use crate::value_type::ValueType;
+
+/// Concatenates SSTs with sorted and non-overlapping key ranges, e.g. the SSTs of a level, into
+/// one iterator. Only one SST is open at a time.
+pub struct SstConcatIterator {
+    current: Option<SsTableIterator>,
+    /// The index of the current SST, or the number of SSTs past the end. Before the start, it is
+    /// 0 without a current SST.
+    sst_idx: usize,
+    sstables: Vec<Arc<SsTable>>,
+}
+
+impl SstConcatIterator {
+    /// Create a new iterator and seek to the first key-value pair.
+    pub fn create_and_seek_to_first(sstables: Vec<Arc<SsTable>>) -> Result<Self> {
+        let mut iter = Self {
+            current: None,
+            sst_idx: 0,
+            sstables,
+        };
+        iter.seek_to_first()?;
+        Ok(iter)
+    }
+
+    /// Create a new iterator and seek to the last key-value pair.
+    pub fn create_and_seek_to_last(sstables: Vec<Arc<SsTable>>) -> Result<Self> {
+        let mut iter = Self {
+            current: None,
+            sst_idx: 0,
+            sstables,
+        };
+        iter.seek_to_last()?;
+        Ok(iter)
+    }
+
+    /// Create a new iterator and seek to the first key-value pair which >= `key`.
+    pub fn create_and_seek_to_key(sstables: Vec<Arc<SsTable>>, key: &[u8]) -> Result<Self> {
+        let mut iter = Self {
+            current: None,
+            sst_idx: 0,
+            sstables,
+        };
+        iter.seek_to_key(key)?;
+        Ok(iter)
+    }
+
+    fn seek_to_key(&mut self, key: &[u8]) -> Result<()> {
+        // The last SST starting at or before `key` is the only one that can contain it.
+        self.sst_idx = self
+            .sstables
+            .partition_point(|table| table.first_key() <= key)
+            .saturating_sub(1);
+        match self.sstables.get(self.sst_idx) {
+            Some(table) => {
+                self.current = Some(SsTableIterator::create_and_seek_to_key(table.clone(), key)?);
+                self.move_until_valid()
+            }
+            None => {
+                self.current = None;
+                self.sst_idx = self.sstables.len();
+                Ok(())
+            }
+        }
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.sst_idx = 0;
+        match self.sstables.first() {
+            Some(table) => {
+                self.current = Some(SsTableIterator::create_and_seek_to_first(table.clone())?);
+                self.move_until_valid()
+            }
+            None => {
+                self.current = None;
+                Ok(())
+            }
+        }
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.sst_idx = self.sstables.len();
+        self.current = None;
+        if let Some(table) = self.sstables.last() {
+            self.sst_idx -= 1;
+            self.current = Some(SsTableIterator::create_and_seek_to_last(table.clone())?);
+            self.move_back_until_valid()?;
+        }
+        Ok(())
+    }
+
+    /// Move forward to the first SST with entries left, or past the end.
+    fn move_until_valid(&mut self) -> Result<()> {
+        while !self.current.as_ref().is_some_and(|iter| iter.is_valid()) {
+            self.sst_idx += 1;
+            let Some(table) = self.sstables.get(self.sst_idx) else {
+                self.current = None;
+                break;
+            };
+            self.current = Some(SsTableIterator::create_and_seek_to_first(table.clone())?);
+        }
+        Ok(())
+    }
+
+    /// Move backward to the last SST with entries left, or before the start.
+    fn move_back_until_valid(&mut self) -> Result<()> {
+        while !self.current.as_ref().is_some_and(|iter| iter.is_valid()) {
+            if self.sst_idx == 0 {
+                self.current = None;
+                break;
+            }
+            self.sst_idx -= 1;
+            let table = self.sstables[self.sst_idx].clone();
+            self.current = Some(SsTableIterator::create_and_seek_to_last(table)?);
+        }
+        Ok(())
+    }
+}
+
+impl StorageIterator for SstConcatIterator {
+    fn key(&self) -> &[u8] {
+        self.current.as_ref().unwrap().key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.current.as_ref().unwrap().value()
+    }
+
+    fn value_type(&self) -> ValueType {
+        self.current.as_ref().unwrap().value_type()
+    }
+
+    fn seq(&self) -> u64 {
+        self.current.as_ref().unwrap().seq()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.current.as_ref().is_some_and(|iter| iter.is_valid())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        match self.current.as_mut() {
+            Some(current) => {
+                current.next()?;
+                self.move_until_valid()
+            }
+            None if self.sst_idx < self.sstables.len() => self.seek_to_first(),
+            None => Ok(()),
+        }
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        match self.current.as_mut() {
+            Some(current) => {
+                current.prev()?;
+                self.move_back_until_valid()
+            }
+            None if self.sst_idx == self.sstables.len() => self.seek_to_last(),
+            None => Ok(()),
+        }
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.seek_to_key(key)
+    }
+}
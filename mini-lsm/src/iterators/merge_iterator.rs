@@ -5,12 +5,15 @@ use std::collections::BinaryHeap;
 use anyhow::Result;
 
 use super::StorageIterator;
+use crate::key::cmp_internal;
+use crate::value_type::ValueType;
 
-struct HeapWrapper<I: StorageIterator>(pub usize, pub Box<I>);
+/// An iterator in the heap, with its index and whether the heap is moving backward.
+struct HeapWrapper<I: StorageIterator>(pub usize, pub Box<I>, pub bool);
 
 impl<I: StorageIterator> PartialEq for HeapWrapper<I> {
     fn eq(&self, other: &Self) -> bool {
-        self.partial_cmp(other).unwrap() == cmp::Ordering::Equal
+        self.cmp(other) == cmp::Ordering::Equal
     }
 }
 
@@ -18,111 +21,108 @@ impl<I: StorageIterator> Eq for HeapWrapper<I> {}
 
 impl<I: StorageIterator> PartialOrd for HeapWrapper<I> {
     fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-        match self.1.key().cmp(other.1.key()) {
-            cmp::Ordering::Greater => Some(cmp::Ordering::Greater),
-            cmp::Ordering::Less => Some(cmp::Ordering::Less),
-            cmp::Ordering::Equal => self.0.partial_cmp(&other.0),
-        }
-        .map(|x| x.reverse())
+        Some(self.cmp(other))
     }
 }
 
 impl<I: StorageIterator> Ord for HeapWrapper<I> {
     fn cmp(&self, other: &Self) -> cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+        let order = cmp_internal(self.1.key(), self.1.seq(), other.1.key(), other.1.seq());
+        // The heap pops the largest item: the smallest entry moving forward, and the largest one
+        // moving backward, preferring the smaller index in both directions.
+        if self.2 {
+            order.then(other.0.cmp(&self.0))
+        } else {
+            order.then(self.0.cmp(&other.0)).reverse()
+        }
     }
 }
 
-/// Merge multiple iterators of the same type. If the same key occurs multiple times in some
-/// iterators, perfer the one with smaller index.
+/// Merge multiple iterators of the same type, in the order of internal keys. If the same version
+/// of a key occurs multiple times in some iterators, perfer the one with smaller index.
+///
+/// The iterator moves in both directions. Changing the direction moves every iterator to the
+/// other side of the current entry.
 pub struct MergeIterator<I: StorageIterator> {
     iters: BinaryHeap<HeapWrapper<I>>,
     current: Option<HeapWrapper<I>>,
+    /// The iterators with no entries left in the current direction.
+    exhausted: Vec<HeapWrapper<I>>,
+    backward: bool,
 }
 
 impl<I: StorageIterator> MergeIterator<I> {
     pub fn create(iters: Vec<Box<I>>) -> Self {
-        if iters.is_empty() {
-            return Self {
-                iters: BinaryHeap::new(),
-                current: None,
-            };
-        }
-
-        let mut heap = BinaryHeap::new();
+        Self::build(iters.into_iter().enumerate().collect(), false)
+    }
 
-        if iters.iter().all(|x| !x.is_valid()) {
-            // All invalid, select the last one as the current.
-            let mut iters = iters;
-            return Self {
-                iters: heap,
-                current: Some(HeapWrapper(0, iters.pop().unwrap())),
-            };
-        }
+    /// Create a merge iterator moving backward, from iterators at their last entries.
+    pub fn create_backward(iters: Vec<Box<I>>) -> Self {
+        Self::build(iters.into_iter().enumerate().collect(), true)
+    }
 
-        for (idx, iter) in iters.into_iter().enumerate() {
+    fn build(iters: Vec<(usize, Box<I>)>, backward: bool) -> Self {
+        let mut heap = BinaryHeap::new();
+        let mut exhausted = Vec::new();
+        for (idx, iter) in iters {
             if iter.is_valid() {
-                heap.push(HeapWrapper(idx, iter));
+                heap.push(HeapWrapper(idx, iter, backward));
+            } else {
+                exhausted.push(HeapWrapper(idx, iter, backward));
             }
         }
-
-        let current = heap.pop().unwrap();
+        let current = heap.pop();
         Self {
             iters: heap,
-            current: Some(current),
+            current,
+            exhausted,
+            backward,
         }
     }
-}
-
-impl<I: StorageIterator> StorageIterator for MergeIterator<I> {
-    fn key(&self) -> &[u8] {
-        unsafe { self.current.as_ref().unwrap_unchecked() }.1.key()
-    }
 
-    fn value(&self) -> &[u8] {
-        unsafe { self.current.as_ref().unwrap_unchecked() }
-            .1
-            .value()
+    /// Get the iterator at the current entry. Panics once the iterators are exhausted.
+    fn current(&self) -> &I {
+        &self.current.as_ref().expect("invalid iterator").1
     }
 
-    fn is_valid(&self) -> bool {
-        self.current
-            .as_ref()
-            .map(|x| x.1.is_valid())
-            .unwrap_or(false)
+    /// Move an iterator one entry in the current direction.
+    fn move_inner(iter: &mut I, backward: bool) -> Result<()> {
+        if backward {
+            iter.prev()
+        } else {
+            iter.next()
+        }
     }
 
-    fn next(&mut self) -> Result<()> {
-        let current = unsafe { self.current.as_mut().unwrap_unchecked() };
+    /// Move to the next entry in the current direction.
+    fn step(&mut self) -> Result<()> {
+        let Some(current) = self.current.as_mut() else {
+            return Ok(());
+        };
         // Pop the item out of the heap if they have the same value.
         while let Some(mut inner_iter) = self.iters.peek_mut() {
-            debug_assert!(
-                inner_iter.1.key() >= current.1.key(),
-                "heap invariant violated"
-            );
-            if inner_iter.1.key() == current.1.key() {
-                // Case 1: an error occurred when calling `next`.
-                if let e @ Err(_) = inner_iter.1.next() {
-                    PeekMut::pop(inner_iter);
+            if inner_iter.1.key() == current.1.key() && inner_iter.1.seq() == current.1.seq() {
+                // Case 1: an error occurred when moving.
+                if let e @ Err(_) = Self::move_inner(&mut inner_iter.1, self.backward) {
+                    self.exhausted.push(PeekMut::pop(inner_iter));
                     return e;
                 }
 
                 // Case 2: iter is no longer valid.
                 if !inner_iter.1.is_valid() {
-                    PeekMut::pop(inner_iter);
+                    self.exhausted.push(PeekMut::pop(inner_iter));
                 }
             } else {
                 break;
             }
         }
 
-        current.1.next()?;
+        Self::move_inner(&mut current.1, self.backward)?;
 
         // If the current iterator is invalid, pop it out of the heap and select the next one.
         if !current.1.is_valid() {
-            if let Some(iter) = self.iters.pop() {
-                *current = iter;
-            }
+            let current = std::mem::replace(&mut self.current, self.iters.pop());
+            self.exhausted.extend(current);
             return Ok(());
         }
 
@@ -135,4 +135,103 @@ impl<I: StorageIterator> StorageIterator for MergeIterator<I> {
 
         Ok(())
     }
+
+    /// Change the direction, and move to the next entry in the new one. Every other iterator is
+    /// moved to the other side of the current entry, and if there is no current entry, every
+    /// iterator comes back from the end it was moved past.
+    fn turn(&mut self) -> Result<()> {
+        let backward = !self.backward;
+        let current = self.current.take();
+        let mut iters: Vec<_> = self
+            .iters
+            .drain()
+            .chain(self.exhausted.drain(..))
+            .map(|x| (x.0, x.1))
+            .collect();
+        match current {
+            Some(HeapWrapper(idx, mut current, _)) if current.is_valid() => {
+                let (key, seq) = (current.key().to_vec(), current.seq());
+                for (_, iter) in &mut iters {
+                    if !iter.is_valid() {
+                        Self::move_inner(iter, backward)?;
+                    }
+                    while iter.is_valid() {
+                        let order = cmp_internal(iter.key(), iter.seq(), &key, seq);
+                        if (backward && order.is_lt()) || (!backward && order.is_gt()) {
+                            break;
+                        }
+                        Self::move_inner(iter, backward)?;
+                    }
+                }
+                Self::move_inner(&mut current, backward)?;
+                iters.push((idx, current));
+            }
+            current => {
+                iters.extend(current.map(|x| (x.0, x.1)));
+                for (_, iter) in &mut iters {
+                    Self::move_inner(iter, backward)?;
+                }
+            }
+        }
+        *self = Self::build(iters, backward);
+        Ok(())
+    }
+}
+
+impl<I: StorageIterator> StorageIterator for MergeIterator<I> {
+    fn key(&self) -> &[u8] {
+        self.current().key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.current().value()
+    }
+
+    fn value_type(&self) -> ValueType {
+        self.current().value_type()
+    }
+
+    fn seq(&self) -> u64 {
+        self.current().seq()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.current
+            .as_ref()
+            .map(|x| x.1.is_valid())
+            .unwrap_or(false)
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if self.backward {
+            self.turn()
+        } else {
+            self.step()
+        }
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        if self.backward {
+            self.step()
+        } else {
+            self.turn()
+        }
+    }
+
+    /// Seek every iterator and rebuild the heap, moving forward.
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        let mut iters: Vec<_> = self
+            .current
+            .take()
+            .into_iter()
+            .chain(self.iters.drain())
+            .chain(self.exhausted.drain(..))
+            .map(|x| (x.0, x.1))
+            .collect();
+        for (_, iter) in &mut iters {
+            iter.seek(key)?;
+        }
+        *self = Self::build(iters, false);
+        Ok(())
+    }
 }
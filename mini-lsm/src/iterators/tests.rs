@@ -2,10 +2,12 @@ use anyhow::Result;
 use bytes::Bytes;
 
 use super::StorageIterator;
+use crate::value_type::ValueType;
 
 pub mod merge_iterator_test;
 pub mod two_merge_iterator_test;
 
+/// An iterator over `data`, before the start when `index` is `usize::MAX`.
 #[derive(Clone)]
 pub struct MockIterator {
     pub data: Vec<(Bytes, Bytes)>,
@@ -16,16 +18,36 @@ impl MockIterator {
     pub fn new(data: Vec<(Bytes, Bytes)>) -> Self {
         Self { data, index: 0 }
     }
+
+    /// Create an iterator at the last entry of `data`.
+    pub fn new_at_last(data: Vec<(Bytes, Bytes)>) -> Self {
+        let index = data.len().wrapping_sub(1);
+        Self { data, index }
+    }
 }
 
 impl StorageIterator for MockIterator {
     fn next(&mut self) -> Result<()> {
-        if self.index < self.data.len() {
+        if self.index == usize::MAX {
+            self.index = 0;
+        } else if self.index < self.data.len() {
             self.index += 1;
         }
         Ok(())
     }
 
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.index = self.data.partition_point(|(k, _)| &k[..] < key);
+        Ok(())
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        if self.index != usize::MAX {
+            self.index = self.index.min(self.data.len()).wrapping_sub(1);
+        }
+        Ok(())
+    }
+
     fn key(&self) -> &[u8] {
         self.data[self.index].0.as_ref()
     }
@@ -34,6 +56,14 @@ impl StorageIterator for MockIterator {
         self.data[self.index].1.as_ref()
     }
 
+    fn value_type(&self) -> ValueType {
+        ValueType::Put
+    }
+
+    fn seq(&self) -> u64 {
+        0
+    }
+
     fn is_valid(&self) -> bool {
         self.index < self.data.len()
     }
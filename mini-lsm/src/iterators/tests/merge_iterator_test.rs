@@ -135,3 +135,142 @@ fn test_merge_empty() {
     let iter = MergeIterator::<MockIterator>::create(vec![]);
     check_iter_result(iter, vec![]);
 }
+
+#[test]
+#[should_panic(expected = "invalid iterator")]
+fn test_merge_key_of_exhausted() {
+    let mut iter = MergeIterator::create(vec![Box::new(MockIterator::new(vec![(
+        Bytes::from("a"),
+        Bytes::from("1.1"),
+    )]))]);
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+    iter.key();
+}
+
+fn check_iter_result_backward(iter: impl StorageIterator, expected: Vec<(Bytes, Bytes)>) {
+    let mut iter = iter;
+    for (k, v) in expected {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), k.as_ref());
+        assert_eq!(iter.value(), v.as_ref());
+        iter.prev().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_merge_backward() {
+    let i1 = vec![
+        (Bytes::from("a"), Bytes::from("1.1")),
+        (Bytes::from("b"), Bytes::from("2.1")),
+        (Bytes::from("c"), Bytes::from("3.1")),
+    ];
+    let i2 = vec![
+        (Bytes::from("a"), Bytes::from("1.2")),
+        (Bytes::from("c"), Bytes::from("3.2")),
+        (Bytes::from("d"), Bytes::from("4.2")),
+    ];
+    let i3 = vec![(Bytes::from("e"), Bytes::from("5.3"))];
+    let iters = || -> Vec<Box<MockIterator>> {
+        vec![
+            Box::new(MockIterator::new_at_last(i1.clone())),
+            Box::new(MockIterator::new_at_last(i2.clone())),
+            Box::new(MockIterator::new_at_last(i3.clone())),
+            Box::new(MockIterator::new_at_last(vec![])),
+        ]
+    };
+
+    check_iter_result_backward(
+        MergeIterator::create_backward(iters()),
+        vec![
+            (Bytes::from("e"), Bytes::from("5.3")),
+            (Bytes::from("d"), Bytes::from("4.2")),
+            (Bytes::from("c"), Bytes::from("3.1")),
+            (Bytes::from("b"), Bytes::from("2.1")),
+            (Bytes::from("a"), Bytes::from("1.1")),
+        ],
+    );
+
+    // Change the direction in the middle, and from both ends.
+    let mut iter = MergeIterator::create_backward(iters());
+    iter.prev().unwrap();
+    iter.prev().unwrap();
+    assert_eq!(iter.key(), b"c");
+    iter.next().unwrap();
+    assert_eq!(iter.key(), b"d");
+    iter.next().unwrap();
+    assert_eq!(iter.key(), b"e");
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+    iter.prev().unwrap();
+    assert_eq!(iter.key(), b"e");
+    iter.prev().unwrap();
+    assert_eq!(iter.key(), b"d");
+    iter.prev().unwrap();
+    assert_eq!((iter.key(), iter.value()), (&b"c"[..], &b"3.1"[..]));
+    check_iter_result_backward(
+        iter,
+        vec![
+            (Bytes::from("c"), Bytes::from("3.1")),
+            (Bytes::from("b"), Bytes::from("2.1")),
+            (Bytes::from("a"), Bytes::from("1.1")),
+        ],
+    );
+
+    let mut iter = MergeIterator::create(
+        [i1.clone(), i2.clone(), i3.clone()]
+            .into_iter()
+            .map(|data| Box::new(MockIterator::new(data)))
+            .collect(),
+    );
+    iter.prev().unwrap();
+    assert!(!iter.is_valid());
+    check_iter_result(
+        {
+            iter.next().unwrap();
+            iter
+        },
+        vec![
+            (Bytes::from("a"), Bytes::from("1.1")),
+            (Bytes::from("b"), Bytes::from("2.1")),
+            (Bytes::from("c"), Bytes::from("3.1")),
+            (Bytes::from("d"), Bytes::from("4.2")),
+            (Bytes::from("e"), Bytes::from("5.3")),
+        ],
+    );
+}
+
+#[test]
+fn test_merge_seek() {
+    let i1 = MockIterator::new(vec![
+        (Bytes::from("a"), Bytes::from("1.1")),
+        (Bytes::from("c"), Bytes::from("3.1")),
+    ]);
+    let i2 = MockIterator::new(vec![
+        (Bytes::from("b"), Bytes::from("2.2")),
+        (Bytes::from("c"), Bytes::from("3.2")),
+        (Bytes::from("e"), Bytes::from("5.2")),
+    ]);
+    let mut iter = MergeIterator::create(vec![Box::new(i1), Box::new(i2)]);
+    iter.seek(b"e").unwrap();
+    assert_eq!(iter.key(), b"e");
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+
+    // Seek back, also after moving backward, with the exhausted iterators.
+    iter.seek(b"bb").unwrap();
+    assert_eq!(iter.key(), b"c");
+    iter.prev().unwrap();
+    assert_eq!(iter.key(), b"b");
+    iter.seek(b"a").unwrap();
+    check_iter_result(
+        iter,
+        vec![
+            (Bytes::from("a"), Bytes::from("1.1")),
+            (Bytes::from("b"), Bytes::from("2.2")),
+            (Bytes::from("c"), Bytes::from("3.1")),
+            (Bytes::from("e"), Bytes::from("5.2")),
+        ],
+    );
+}
@@ -127,3 +127,81 @@ fn test_merge_5() {
     let iter = TwoMergeIterator::create(i1, i2).unwrap();
     check_iter_result(iter, vec![])
 }
+
+#[test]
+fn test_merge_backward() {
+    let i1 = MockIterator::new_at_last(vec![
+        (Bytes::from("a"), Bytes::from("1.1")),
+        (Bytes::from("c"), Bytes::from("3.1")),
+    ]);
+    let i2 = MockIterator::new_at_last(vec![
+        (Bytes::from("a"), Bytes::from("1.2")),
+        (Bytes::from("b"), Bytes::from("2.2")),
+        (Bytes::from("c"), Bytes::from("3.2")),
+        (Bytes::from("d"), Bytes::from("4.2")),
+    ]);
+    let mut iter = TwoMergeIterator::create_backward(i1, i2).unwrap();
+    let mut result = Vec::new();
+    while iter.is_valid() {
+        result.push((as_bytes(iter.key()), as_bytes(iter.value())));
+        iter.prev().unwrap();
+    }
+    assert_eq!(
+        result,
+        vec![
+            (Bytes::from("d"), Bytes::from("4.2")),
+            (Bytes::from("c"), Bytes::from("3.1")),
+            (Bytes::from("b"), Bytes::from("2.2")),
+            (Bytes::from("a"), Bytes::from("1.1")),
+        ]
+    );
+
+    // Come back from before the start, and change the direction in the middle.
+    iter.next().unwrap();
+    assert_eq!(iter.value(), b"1.1");
+    iter.next().unwrap();
+    assert_eq!(iter.value(), b"2.2");
+    iter.next().unwrap();
+    assert_eq!(iter.value(), b"3.1");
+    iter.prev().unwrap();
+    assert_eq!(iter.value(), b"2.2");
+    iter.next().unwrap();
+    assert_eq!(iter.value(), b"3.1");
+    iter.next().unwrap();
+    assert_eq!(iter.value(), b"4.2");
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+    iter.prev().unwrap();
+    assert_eq!(iter.value(), b"4.2");
+}
+
+fn as_bytes(x: &[u8]) -> Bytes {
+    Bytes::copy_from_slice(x)
+}
+
+#[test]
+fn test_merge_seek() {
+    let i1 = MockIterator::new(vec![
+        (Bytes::from("a"), Bytes::from("1.1")),
+        (Bytes::from("c"), Bytes::from("3.1")),
+    ]);
+    let i2 = MockIterator::new(vec![
+        (Bytes::from("b"), Bytes::from("2.2")),
+        (Bytes::from("c"), Bytes::from("3.2")),
+        (Bytes::from("d"), Bytes::from("4.2")),
+    ]);
+    let mut iter = TwoMergeIterator::create(i1, i2).unwrap();
+    iter.seek(b"c").unwrap();
+    assert_eq!(iter.value(), b"3.1");
+    iter.prev().unwrap();
+    assert_eq!(iter.value(), b"2.2");
+    iter.seek(b"b").unwrap();
+    check_iter_result(
+        iter,
+        vec![
+            (Bytes::from("b"), Bytes::from("2.2")),
+            (Bytes::from("c"), Bytes::from("3.1")),
+            (Bytes::from("d"), Bytes::from("4.2")),
+        ],
+    );
+}
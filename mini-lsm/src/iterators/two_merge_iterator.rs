@@ -1,45 +1,126 @@
 use anyhow::Result;
 
 use super::StorageIterator;
+use crate::key::cmp_internal;
+use crate::value_type::ValueType;
 
-/// Merges two iterators of different types into one. If the two iterators have the same key, only
-/// produce the key once and prefer the entry from A.
+/// Merges two iterators of different types into one. If the two iterators have the same version of
+/// a key, only produce it once and prefer the entry from A.
+///
+/// The iterator moves in both directions. Changing the direction moves the other iterator to the
+/// other side of the current entry.
 pub struct TwoMergeIterator<A: StorageIterator, B: StorageIterator> {
     a: A,
     b: B,
     choose_a: bool,
+    backward: bool,
 }
 
 impl<A: StorageIterator, B: StorageIterator> TwoMergeIterator<A, B> {
-    fn choose_a(a: &A, b: &B) -> bool {
+    fn choose_a(a: &A, b: &B, backward: bool) -> bool {
         if !a.is_valid() {
             return false;
         }
         if !b.is_valid() {
             return true;
         }
-        a.key() < b.key()
+        let order = cmp_internal(a.key(), a.seq(), b.key(), b.seq());
+        if backward {
+            order.is_gt()
+        } else {
+            order.is_lt()
+        }
+    }
+
+    /// Move an iterator one entry in the given direction.
+    fn move_inner<I: StorageIterator>(iter: &mut I, backward: bool) -> Result<()> {
+        if backward {
+            iter.prev()
+        } else {
+            iter.next()
+        }
     }
 
     fn skip_b(&mut self) -> Result<()> {
         if self.a.is_valid() {
-            while self.b.is_valid() && self.b.key() == self.a.key() {
-                self.b.next()?;
+            while self.b.is_valid() && self.b.key() == self.a.key() && self.b.seq() == self.a.seq()
+            {
+                Self::move_inner(&mut self.b, self.backward)?;
             }
         }
         Ok(())
     }
 
     pub fn create(a: A, b: B) -> Result<Self> {
+        Self::build(a, b, false)
+    }
+
+    /// Create a merge iterator moving backward, from iterators at their last entries.
+    pub fn create_backward(a: A, b: B) -> Result<Self> {
+        Self::build(a, b, true)
+    }
+
+    fn build(a: A, b: B, backward: bool) -> Result<Self> {
         let mut iter = Self {
             choose_a: false,
             a,
             b,
+            backward,
         };
         iter.skip_b()?;
-        iter.choose_a = Self::choose_a(&iter.a, &iter.b);
+        iter.choose_a = Self::choose_a(&iter.a, &iter.b, backward);
         Ok(iter)
     }
+
+    /// Move to the next entry in the current direction.
+    fn step(&mut self) -> Result<()> {
+        if self.choose_a {
+            Self::move_inner(&mut self.a, self.backward)?;
+        } else {
+            Self::move_inner(&mut self.b, self.backward)?;
+        }
+        self.skip_b()?;
+        self.choose_a = Self::choose_a(&self.a, &self.b, self.backward);
+        Ok(())
+    }
+
+    /// Change the direction, and move to the next entry in the new one. The other iterator is
+    /// moved to the other side of the current entry, and if there is no current entry, both
+    /// iterators come back from the end they were moved past.
+    fn turn(&mut self) -> Result<()> {
+        let backward = !self.backward;
+        self.backward = backward;
+        if !self.is_valid() {
+            Self::move_inner(&mut self.a, backward)?;
+            Self::move_inner(&mut self.b, backward)?;
+        } else {
+            let (key, seq) = (self.key().to_vec(), self.seq());
+            let past = |iter_key: &[u8], iter_seq: u64| {
+                let order = cmp_internal(iter_key, iter_seq, &key, seq);
+                (backward && order.is_lt()) || (!backward && order.is_gt())
+            };
+            if self.choose_a {
+                Self::move_inner(&mut self.a, backward)?;
+                if !self.b.is_valid() {
+                    Self::move_inner(&mut self.b, backward)?;
+                }
+                while self.b.is_valid() && !past(self.b.key(), self.b.seq()) {
+                    Self::move_inner(&mut self.b, backward)?;
+                }
+            } else {
+                Self::move_inner(&mut self.b, backward)?;
+                if !self.a.is_valid() {
+                    Self::move_inner(&mut self.a, backward)?;
+                }
+                while self.a.is_valid() && !past(self.a.key(), self.a.seq()) {
+                    Self::move_inner(&mut self.a, backward)?;
+                }
+            }
+        }
+        self.skip_b()?;
+        self.choose_a = Self::choose_a(&self.a, &self.b, backward);
+        Ok(())
+    }
 }
 
 impl<A: StorageIterator, B: StorageIterator> StorageIterator for TwoMergeIterator<A, B> {
@@ -59,6 +140,22 @@ impl<A: StorageIterator, B: StorageIterator> StorageIterator for TwoMergeIterato
         }
     }
 
+    fn seq(&self) -> u64 {
+        if self.choose_a {
+            self.a.seq()
+        } else {
+            self.b.seq()
+        }
+    }
+
+    fn value_type(&self) -> ValueType {
+        if self.choose_a {
+            self.a.value_type()
+        } else {
+            self.b.value_type()
+        }
+    }
+
     fn is_valid(&self) -> bool {
         if self.choose_a {
             self.a.is_valid()
@@ -68,13 +165,27 @@ impl<A: StorageIterator, B: StorageIterator> StorageIterator for TwoMergeIterato
     }
 
     fn next(&mut self) -> Result<()> {
-        if self.choose_a {
-            self.a.next()?;
+        if self.backward {
+            self.turn()
+        } else {
+            self.step()
+        }
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        if self.backward {
+            self.step()
         } else {
-            self.b.next()?;
+            self.turn()
         }
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.a.seek(key)?;
+        self.b.seek(key)?;
+        self.backward = false;
         self.skip_b()?;
-        self.choose_a = Self::choose_a(&self.a, &self.b);
+        self.choose_a = Self::choose_a(&self.a, &self.b, false);
         Ok(())
     }
 }
@@ -0,0 +1,36 @@
+use std::cmp::Ordering;
+
+use bytes::Bytes;
+
+/// A user key along with the sequence number of the write that produced this version of it.
+///
+/// Internal keys are ordered by user key, then from the latest version to the earliest one, so
+/// that a reader at some sequence number finds the version it sees first.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InternalKey {
+    pub key: Bytes,
+    pub seq: u64,
+}
+
+impl InternalKey {
+    pub fn new(key: Bytes, seq: u64) -> Self {
+        Self { key, seq }
+    }
+}
+
+/// Compare two versions of user keys in the order of internal keys.
+pub fn cmp_internal(key: &[u8], seq: u64, other_key: &[u8], other_seq: u64) -> Ordering {
+    key.cmp(other_key).then(other_seq.cmp(&seq))
+}
+
+impl Ord for InternalKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        cmp_internal(&self.key, self.seq, &other.key, other.seq)
+    }
+}
+
+impl PartialOrd for InternalKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
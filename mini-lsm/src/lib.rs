@@ -1,9 +1,21 @@
 pub mod block;
+pub mod compact;
+pub mod compression;
 pub mod iterators;
+pub mod key;
 pub mod lsm_iterator;
 pub mod lsm_storage;
+pub mod manifest;
 pub mod mem_table;
+pub mod merge_operator;
+pub mod mvcc;
+pub mod prefix_extractor;
+pub mod range_tombstone;
 pub mod table;
+pub mod value_type;
+pub mod varint;
+pub mod wal;
+pub mod write_batch;
 
 #[cfg(test)]
 mod tests;
@@ -1,85 +1,353 @@
 use std::ops::Bound;
+use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use bytes::Bytes;
 
+use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
 use crate::mem_table::MemTableIterator;
+use crate::merge_operator::MergeOperator;
+use crate::range_tombstone::RangeTombstone;
 use crate::table::SsTableIterator;
+use crate::value_type::ValueType;
 
-type LsmIteratorInner =
-    TwoMergeIterator<MergeIterator<MemTableIterator>, MergeIterator<SsTableIterator>>;
+type LsmIteratorInner = TwoMergeIterator<
+    MergeIterator<MemTableIterator>,
+    TwoMergeIterator<MergeIterator<SsTableIterator>, MergeIterator<SstConcatIterator>>,
+>;
 
+/// Iterates over the keys visible at a sequence number, producing the latest visible version of
+/// each key and hiding the deleted ones, including the ones covered by a range tombstone. Merge
+/// operands are folded with the earlier versions of their key.
+///
+/// The iterator moves in both directions within its bounds. Moving backward, the versions of a
+/// key come from the earliest to the latest, so they are all read before the key is produced.
 pub struct LsmIterator {
     iter: LsmIteratorInner,
-    end_bound: Bound<Bytes>,
+    lower: Bound<Bytes>,
+    upper: Bound<Bytes>,
+    /// Whether the inner iterator is valid and within the bounds.
     is_valid: bool,
+    read_seq: u64,
+    /// The range tombstones visible at `read_seq` from all sources of the scan.
+    range_tombstones: Vec<RangeTombstone>,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// The key, sequence number and value of the current entry, if it is not the one of the inner
+    /// iterator: when it is folded from merge operands, the inner iterator is past them, and when
+    /// moving backward, it is before all versions of the key.
+    current: Option<(Bytes, u64, Bytes)>,
+    backward: bool,
 }
 
 impl LsmIterator {
-    pub(crate) fn new(iter: LsmIteratorInner, end_bound: Bound<Bytes>) -> Result<Self> {
+    /// Create an iterator over `inner` within `bounds`. Moving backward, the inner iterator must
+    /// be created moving backward too, from the last entries within the upper bound.
+    pub(crate) fn new(
+        iter: LsmIteratorInner,
+        (lower, upper): (Bound<Bytes>, Bound<Bytes>),
+        read_seq: u64,
+        range_tombstones: Vec<RangeTombstone>,
+        merge_operator: Option<Arc<dyn MergeOperator>>,
+        backward: bool,
+    ) -> Result<Self> {
         let mut iter = Self {
-            is_valid: iter.is_valid(),
+            is_valid: false,
             iter,
-            end_bound,
+            lower,
+            upper,
+            read_seq,
+            range_tombstones,
+            merge_operator,
+            current: None,
+            backward,
         };
-        iter.move_to_non_delete()?;
+        iter.check_bound();
+        if backward {
+            iter.move_to_visible_backward()?;
+        } else {
+            iter.move_to_visible()?;
+        }
         Ok(iter)
     }
 
+    fn above_lower(&self, key: &[u8]) -> bool {
+        match self.lower.as_ref() {
+            Bound::Unbounded => true,
+            Bound::Included(lower) => key >= lower.as_ref(),
+            Bound::Excluded(lower) => key > lower.as_ref(),
+        }
+    }
+
+    fn below_upper(&self, key: &[u8]) -> bool {
+        match self.upper.as_ref() {
+            Bound::Unbounded => true,
+            Bound::Included(upper) => key <= upper.as_ref(),
+            Bound::Excluded(upper) => key < upper.as_ref(),
+        }
+    }
+
+    fn check_bound(&mut self) {
+        self.is_valid = self.iter.is_valid()
+            && self.above_lower(self.iter.key())
+            && self.below_upper(self.iter.key());
+    }
+
     fn next_inner(&mut self) -> Result<()> {
         self.iter.next()?;
-        if !self.iter.is_valid() {
-            self.is_valid = false;
-            return Ok(());
-        }
-        match self.end_bound.as_ref() {
-            Bound::Unbounded => {}
-            Bound::Included(key) => self.is_valid = self.iter.key() <= key.as_ref(),
-            Bound::Excluded(key) => self.is_valid = self.iter.key() < key.as_ref(),
+        self.check_bound();
+        Ok(())
+    }
+
+    fn prev_inner(&mut self) -> Result<()> {
+        self.iter.prev()?;
+        self.check_bound();
+        Ok(())
+    }
+
+    /// Skip the remaining versions of the current key.
+    fn skip_key(&mut self) -> Result<()> {
+        let key = match self.current.take() {
+            Some((key, _, _)) => key.to_vec(),
+            None => self.iter.key().to_vec(),
+        };
+        while self.is_valid && self.iter.key() == key {
+            self.next_inner()?;
         }
         Ok(())
     }
 
-    fn move_to_non_delete(&mut self) -> Result<()> {
-        while self.is_valid() && self.iter.value().is_empty() {
+    /// Check if the current version of the inner iterator is deleted.
+    fn is_deleted(&self) -> bool {
+        let (key, seq) = (self.iter.key(), self.iter.seq());
+        self.iter.value_type() == ValueType::Delete
+            || self.range_tombstones.iter().any(|t| t.covers(key, seq))
+    }
+
+    /// Move to the latest visible version of a key that is not deleted.
+    fn move_to_visible(&mut self) -> Result<()> {
+        loop {
+            while self.is_valid && self.iter.seq() > self.read_seq {
+                self.next_inner()?;
+            }
+            if !self.is_valid {
+                return Ok(());
+            }
+            if !self.is_deleted() {
+                if self.iter.value_type() == ValueType::Merge {
+                    self.fold_operands()?;
+                }
+                return Ok(());
+            }
+            self.skip_key()?;
+        }
+    }
+
+    /// Fold the merge operands of the current key, from the current version down to a put or a
+    /// deletion, into the current entry.
+    fn fold_operands(&mut self) -> Result<()> {
+        let key = Bytes::copy_from_slice(self.iter.key());
+        let seq = self.iter.seq();
+        let mut operands = Vec::new();
+        let mut existing = None;
+        while self.is_valid && self.iter.key() == key && !self.is_deleted() {
+            let value = Bytes::copy_from_slice(self.iter.value());
+            if self.iter.value_type() == ValueType::Put {
+                existing = Some(value);
+                break;
+            }
+            operands.push(value);
             self.next_inner()?;
         }
+        let value = self.full_merge(&key, existing, operands)?;
+        self.current = Some((key, seq, value));
+        Ok(())
+    }
+
+    /// Fold merge operands, from the latest to the earliest, into the existing value.
+    fn full_merge(
+        &self,
+        key: &[u8],
+        existing: Option<Bytes>,
+        operands: Vec<Bytes>,
+    ) -> Result<Bytes> {
+        let Some(operator) = self.merge_operator.as_deref() else {
+            bail!("merge operand without a merge operator");
+        };
+        let operands: Vec<&[u8]> = operands.iter().rev().map(|x| &x[..]).collect();
+        Ok(operator.full_merge(key, existing.as_deref(), &operands))
+    }
+
+    /// Move backward to the previous key that is not deleted, from the inner iterator at the
+    /// earliest version of the key, and leave the inner iterator before all its versions.
+    fn move_to_visible_backward(&mut self) -> Result<()> {
+        self.current = None;
+        while self.is_valid {
+            let key = Bytes::copy_from_slice(self.iter.key());
+            // The visible versions of the key, from the earliest to the latest.
+            let mut versions = Vec::new();
+            while self.is_valid && self.iter.key() == key {
+                if self.iter.seq() <= self.read_seq {
+                    versions.push((
+                        self.iter.seq(),
+                        self.iter.value_type(),
+                        Bytes::copy_from_slice(self.iter.value()),
+                    ));
+                }
+                self.prev_inner()?;
+            }
+            let Some(&(seq, _, _)) = versions.last() else {
+                continue;
+            };
+            let mut operands = Vec::new();
+            let mut existing = None;
+            for (version_seq, value_type, value) in versions.into_iter().rev() {
+                let covered = self
+                    .range_tombstones
+                    .iter()
+                    .any(|t| t.covers(&key, version_seq));
+                if value_type == ValueType::Delete || covered {
+                    break;
+                }
+                if value_type == ValueType::Put {
+                    existing = Some(value);
+                    break;
+                }
+                operands.push(value);
+            }
+            let value = match (existing, operands.is_empty()) {
+                (existing, false) => self.full_merge(&key, existing, operands)?,
+                (Some(value), true) => value,
+                (None, true) => continue,
+            };
+            self.current = Some((key, seq, value));
+            return Ok(());
+        }
         Ok(())
     }
 }
 
 impl StorageIterator for LsmIterator {
     fn is_valid(&self) -> bool {
-        self.is_valid
+        self.current.is_some() || (!self.backward && self.is_valid)
     }
 
     fn key(&self) -> &[u8] {
-        self.iter.key()
+        match &self.current {
+            Some((key, _, _)) => key,
+            None => self.iter.key(),
+        }
     }
 
     fn value(&self) -> &[u8] {
-        self.iter.value()
+        match &self.current {
+            Some((_, _, value)) => value,
+            None => self.iter.value(),
+        }
+    }
+
+    fn value_type(&self) -> ValueType {
+        match &self.current {
+            Some(_) => ValueType::Put,
+            None => self.iter.value_type(),
+        }
+    }
+
+    fn seq(&self) -> u64 {
+        match &self.current {
+            Some((_, seq, _)) => *seq,
+            None => self.iter.seq(),
+        }
     }
 
     fn next(&mut self) -> Result<()> {
-        self.next_inner()?;
-        self.move_to_non_delete()?;
-        Ok(())
+        if self.backward {
+            // Move the inner iterator past all versions of the current key, or from before the
+            // start into the bounds.
+            self.backward = false;
+            let key = self.current.take().map(|(key, _, _)| key);
+            if !self.iter.is_valid() {
+                self.iter.next()?;
+            }
+            while self.iter.is_valid()
+                && match &key {
+                    Some(key) => self.iter.key() <= &key[..],
+                    None => !self.above_lower(self.iter.key()),
+                }
+            {
+                self.iter.next()?;
+            }
+            self.check_bound();
+        } else if self.is_valid() {
+            self.skip_key()?;
+        } else {
+            return Ok(());
+        }
+        self.move_to_visible()
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        if !self.backward {
+            // Move the inner iterator before all versions of the current key, or from past the
+            // end into the bounds.
+            let key = match self.is_valid() {
+                true => Some(Bytes::copy_from_slice(self.key())),
+                false => None,
+            };
+            self.backward = true;
+            self.current = None;
+            if !self.iter.is_valid() {
+                self.iter.prev()?;
+            }
+            while self.iter.is_valid()
+                && match &key {
+                    Some(key) => self.iter.key() >= &key[..],
+                    None => !self.below_upper(self.iter.key()),
+                }
+            {
+                self.iter.prev()?;
+            }
+            self.check_bound();
+        }
+        self.move_to_visible_backward()
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        // Seek to the lower bound instead if `key` is before it.
+        let (target, excluded) = match &self.lower {
+            Bound::Included(lower) if &lower[..] >= key => (lower.clone(), false),
+            Bound::Excluded(lower) if &lower[..] >= key => (lower.clone(), true),
+            _ => (Bytes::copy_from_slice(key), false),
+        };
+        self.iter.seek(&target)?;
+        if excluded {
+            while self.iter.is_valid() && self.iter.key() == target {
+                self.iter.next()?;
+            }
+        }
+        self.current = None;
+        self.backward = false;
+        self.check_bound();
+        self.move_to_visible()
     }
 }
 
-/// A wrapper around existing iterator, will prevent users from calling `next` when the iterator is
-/// invalid.
+/// A wrapper around existing iterator, will prevent users from moving the iterator further past the
+/// end when it is invalid, or further before the start.
 pub struct FusedIterator<I: StorageIterator> {
     iter: I,
+    /// Whether the iterator last moved backward, so that it is before the start when invalid.
+    backward: bool,
 }
 
 impl<I: StorageIterator> FusedIterator<I> {
     pub fn new(iter: I) -> Self {
-        Self { iter }
+        Self {
+            iter,
+            backward: false,
+        }
     }
 }
 
@@ -96,11 +364,34 @@ impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
         self.iter.value()
     }
 
+    fn value_type(&self) -> ValueType {
+        self.iter.value_type()
+    }
+
+    fn seq(&self) -> u64 {
+        self.iter.seq()
+    }
+
     fn next(&mut self) -> Result<()> {
-        // only move when the iterator is valid
-        if self.iter.is_valid() {
+        // only move when the iterator is valid, or back from before the start
+        if self.iter.is_valid() || self.backward {
             self.iter.next()?;
         }
+        self.backward = false;
+        Ok(())
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        if self.iter.is_valid() || !self.backward {
+            self.iter.prev()?;
+        }
+        self.backward = true;
+        Ok(())
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.iter.seek(key)?;
+        self.backward = false;
         Ok(())
     }
 }
@@ -1,229 +1,1183 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fs::File;
 use std::ops::Bound;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::Arc;
+use std::thread::JoinHandle;
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, ensure, Result};
 use bytes::Bytes;
-use parking_lot::{Mutex, RwLock};
+use parking_lot::{Condvar, Mutex, MutexGuard, RwLock};
 
 use crate::block::Block;
+use crate::compact::CompactionOptions;
+use crate::compression::{CompressionType, CompressorRegistry};
+use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
 use crate::lsm_iterator::{FusedIterator, LsmIterator};
+use crate::manifest::{Manifest, ManifestRecord};
 use crate::mem_table::{map_bound, MemTable};
-use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+use crate::merge_operator::MergeOperator;
+use crate::mvcc::{
+    key_range, CommitLog, IsolationLevel, LiveSnapshots, ReadSet, Snapshot, Transaction,
+    TxnConflict,
+};
+use crate::prefix_extractor::{prefix_upper_bound, PrefixExtractor};
+use crate::range_tombstone::RangeTombstone;
+use crate::table::{FileObject, SsTable, SsTableBuilder, SsTableIterator};
+use crate::value_type::ValueType;
+use crate::write_batch::{WriteBatch, WriteBatchRecord};
 
 pub type BlockCache = moka::sync::Cache<(usize, usize), Arc<Block>>;
 
 #[derive(Clone)]
 pub struct LsmStorageInner {
     /// The current memtable.
-    memtable: Arc<MemTable>,
+    pub(crate) memtable: Arc<MemTable>,
     /// Immutable memTables, from earliest to latest.
-    imm_memtables: Vec<Arc<MemTable>>,
+    pub(crate) imm_memtables: Vec<Arc<MemTable>>,
     /// L0 SsTables, from earliest to latest.
-    l0_sstables: Vec<Arc<SsTable>>,
+    pub(crate) l0_sstables: Vec<Arc<SsTable>>,
     /// L1 - L6 SsTables, sorted by key range.
-    #[allow(dead_code)]
-    levels: Vec<Vec<Arc<SsTable>>>,
-    /// The next SSTable ID.
-    next_sst_id: usize,
+    pub(crate) levels: Vec<Vec<Arc<SsTable>>>,
+}
+
+/// Options of the storage.
+#[derive(Clone, Debug)]
+pub struct LsmStorageOptions {
+    /// The target size of a block in bytes.
+    pub block_size: usize,
+    /// The number of entries from one restart point to the next in a block. Keys share their
+    /// prefix with the previous key in between.
+    pub block_restart_interval: usize,
+    /// The target size of an SST written by compaction in bytes.
+    pub target_sst_size: usize,
+    /// The number of bits for each key in the bloom filter of an SST, 0 to disable the filter.
+    pub bloom_bits_per_key: usize,
+    /// The memtable is frozen and flushed in the background once it grows past this size in
+    /// bytes.
+    pub memtable_size_limit: usize,
+    /// Writes stall while this many frozen memtables wait for the flush, so that they cannot
+    /// outrun the flush worker and take up ever more memory.
+    pub max_imm_memtables: usize,
+    pub compaction_options: CompactionOptions,
+    /// Folds the operands of [`LsmStorage::merge`]. The same operator must be given to every
+    /// storage opened on a directory with merge operands.
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// Takes the prefixes of the keys for a bloom filter in each SST, which lets
+    /// [`LsmStorage::scan_prefix`] skip the SSTs without the prefix.
+    pub prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    /// The compression of the blocks of the SSTs written to each level, starting from L0. Levels
+    /// past the end take the last one, so that colder data can be compressed more; an empty list
+    /// disables compression. Each type must be in `compressors`.
+    pub compression_per_level: Vec<CompressionType>,
+    /// The codecs of the compression types, to write SSTs and to read them back. A codec must stay
+    /// registered as long as any SST in the directory is compressed with it.
+    pub compressors: CompressorRegistry,
 }
 
-impl LsmStorageInner {
-    fn create() -> Self {
+impl Default for LsmStorageOptions {
+    fn default() -> Self {
         Self {
-            memtable: Arc::new(MemTable::create()),
-            imm_memtables: vec![],
-            l0_sstables: vec![],
-            levels: vec![],
-            next_sst_id: 1,
+            block_size: 4096,
+            block_restart_interval: 16,
+            target_sst_size: 2 << 20, // 2MB
+            bloom_bits_per_key: 10,
+            memtable_size_limit: 2 << 20, // 2MB
+            max_imm_memtables: 4,
+            compaction_options: CompactionOptions::default(),
+            merge_operator: None,
+            prefix_extractor: None,
+            compression_per_level: Vec::new(),
+            compressors: CompressorRegistry::default(),
         }
     }
 }
 
+/// Apply a compaction to the ids or SSTs of L0 and L1+: remove the `removed` SSTs from wherever
+/// they are, and put the `added` ones at the place of the SSTs removed from `level`, or at the
+/// front of it if none was. Ids carry no key range, so the caller sorts the level by key once the
+/// SSTs are opened.
+pub(crate) fn apply_compaction<T>(
+    l0: &mut Vec<T>,
+    levels: &mut Vec<Vec<T>>,
+    level: usize,
+    removed: &[usize],
+    added: Vec<T>,
+    id_of: impl Fn(&T) -> usize,
+) {
+    let removed: HashSet<usize> = removed.iter().copied().collect();
+    l0.retain(|x| !removed.contains(&id_of(x)));
+    if levels.len() < level {
+        levels.resize_with(level, Vec::new);
+    }
+    for (idx, ssts) in levels.iter_mut().enumerate() {
+        if idx + 1 == level {
+            let pos = ssts
+                .iter()
+                .position(|x| removed.contains(&id_of(x)))
+                .unwrap_or(0);
+            ssts.retain(|x| !removed.contains(&id_of(x)));
+            ssts.splice(pos..pos, added);
+            return;
+        }
+        ssts.retain(|x| !removed.contains(&id_of(x)));
+    }
+}
+
+/// Apply a tiered compaction to the ids or SSTs of L0 and L1+: remove the `removed` SSTs from
+/// wherever they are, drop the levels left empty, and put the `added` ones in a new first level.
+pub(crate) fn apply_tiered_compaction<T>(
+    l0: &mut Vec<T>,
+    levels: &mut Vec<Vec<T>>,
+    removed: &[usize],
+    added: Vec<T>,
+    id_of: impl Fn(&T) -> usize,
+) {
+    let removed: HashSet<usize> = removed.iter().copied().collect();
+    l0.retain(|x| !removed.contains(&id_of(x)));
+    levels.retain_mut(|ssts| {
+        let len = ssts.len();
+        ssts.retain(|x| !removed.contains(&id_of(x)));
+        ssts.len() == len || !ssts.is_empty()
+    });
+    if !added.is_empty() {
+        levels.insert(0, added);
+    }
+}
+
 /// The storage interface of the LSM tree.
+///
+/// Memtables that grow past [`LsmStorageOptions::memtable_size_limit`] are frozen, and flushed
+/// into L0 by a background worker, which also runs compaction. Call [`LsmStorage::close`] to stop
+/// the worker once all frozen memtables are flushed; dropping the storage does the same.
+///
+/// Writes stall once [`LsmStorageOptions::max_imm_memtables`] frozen memtables wait for the
+/// worker. If the worker fails, it stops, and every later write or sync fails with its error
+/// rather than freezing memtables that would never be flushed.
 pub struct LsmStorage {
-    inner: Arc<RwLock<Arc<LsmStorageInner>>>,
-    flush_lock: Mutex<()>,
-    path: PathBuf,
-    block_cache: Arc<BlockCache>,
+    pub(crate) core: Arc<LsmStorageCore>,
+    flush_worker: Mutex<Option<JoinHandle<Result<()>>>>,
 }
 
 impl LsmStorage {
-    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+    /// Open the storage at `path`, creating the directory if it does not exist. The structure of
+    /// the LSM tree is rebuilt from the manifest, and memtables that were not flushed before the
+    /// last shutdown are recovered from their WAL segments and flushed in the background.
+    pub fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Self> {
+        let (notifier, receiver) = channel();
+        let core = Arc::new(LsmStorageCore::open(path, options, notifier)?);
+        if !core.inner.read().imm_memtables.is_empty() {
+            core.notify_flush();
+        }
+        let flush_worker = {
+            let core = core.clone();
+            std::thread::spawn(move || core.flush_worker(receiver))
+        };
         Ok(Self {
-            inner: Arc::new(RwLock::new(Arc::new(LsmStorageInner::create()))),
-            flush_lock: Mutex::new(()),
-            path: path.as_ref().to_path_buf(),
-            block_cache: Arc::new(BlockCache::new(1 << 20)), // 4GB block cache
+            core,
+            flush_worker: Mutex::new(Some(flush_worker)),
         })
     }
 
-    /// Get a key from the storage. In day 7, this can be further optimized by using a bloom filter.
+    /// Stop the background worker after it flushes all frozen memtables, and fsync the WAL of the
+    /// current one. Writes after closing fail, while reads still work.
+    pub fn close(&self) -> Result<()> {
+        {
+            // Let the writes in progress finish, so that none freezes a memtable behind the
+            // worker's back.
+            let _write_lock = self.core.write_lock.lock();
+            self.core.flush_notifier.lock().take();
+        }
+        if let Some(flush_worker) = self.flush_worker.lock().take() {
+            flush_worker
+                .join()
+                .map_err(|_| anyhow!("flush worker panicked"))??;
+        }
+        self.core.sync_wal()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn snapshot_for_test(&self) -> Arc<LsmStorageInner> {
+        self.core.inner.read().clone()
+    }
+
+    /// Get a key from the storage.
     pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
-        let snapshot = {
-            let guard = self.inner.read();
-            Arc::clone(&guard)
-        }; // drop global lock here
+        self.core.get(key, None)
+    }
 
-        // Search on the current memtable.
-        if let Some(value) = snapshot.memtable.get(key) {
-            if value.is_empty() {
-                // found tomestone, return key not exists
-                return Ok(None);
-            }
-            return Ok(Some(value));
+    /// Take a snapshot of the storage, which reads as of now until it is dropped.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(self.core.clone())
+    }
+
+    /// Start an optimistic transaction with snapshot isolation, which reads as of now.
+    pub fn new_txn(&self) -> Transaction {
+        self.new_txn_with_isolation(IsolationLevel::default())
+    }
+
+    /// Start an optimistic transaction with the given isolation level, which reads as of now.
+    pub fn new_txn_with_isolation(&self, isolation: IsolationLevel) -> Transaction {
+        Transaction::new(self.core.clone(), isolation)
+    }
+
+    /// Get the watermark of the storage, i.e. the sequence number of the oldest live snapshot or
+    /// transaction, or of the last write if there is none. Compaction drops the versions of a key
+    /// older than its latest one at or below the watermark.
+    pub fn watermark(&self) -> u64 {
+        self.core.watermark()
+    }
+
+    /// Get a key from the storage as of `snapshot`.
+    pub fn get_with_snapshot(&self, key: &[u8], snapshot: &Snapshot) -> Result<Option<Bytes>> {
+        self.core.get(key, Some(snapshot.seq()))
+    }
+
+    /// Put a key-value pair into the storage.
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.core.put(key, value)
+    }
+
+    /// Remove a key from the storage.
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        self.core.delete(key)
+    }
+
+    /// Remove all keys in `[start, end)` at once. The keys are not scanned; a single range
+    /// tombstone is written instead, and the keys under it are dropped by compaction.
+    pub fn delete_range(&self, start: &[u8], end: &[u8]) -> Result<()> {
+        self.core.delete_range(start, end)
+    }
+
+    /// Merge an operand into the value of a key with [`LsmStorageOptions::merge_operator`],
+    /// without reading the value. Fails if there is no merge operator.
+    pub fn merge(&self, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.core.merge(key, operand)
+    }
+
+    /// Apply all operations of a batch atomically.
+    pub fn write(&self, batch: &WriteBatch) -> Result<()> {
+        self.core.write(batch)
+    }
+
+    /// Fsync the WAL of every memtable that has not been flushed yet.
+    pub fn sync_wal(&self) -> Result<()> {
+        self.core.sync_wal()
+    }
+
+    /// Flush all memtables into L0 and compact the SSTs if needed, before returning.
+    pub fn sync(&self) -> Result<()> {
+        self.core.sync()
+    }
+
+    /// Create an iterator over a range of keys.
+    pub fn scan(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.core.scan(lower, upper, None)
+    }
+
+    /// Create an iterator over a range of keys, from the last key to the first.
+    pub fn scan_rev(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.core.scan_rev(lower, upper, None)
+    }
+
+    /// Create an iterator over the keys starting with `prefix`.
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Result<FusedIterator<LsmIterator>> {
+        self.core.scan_prefix(prefix, None)
+    }
+
+    /// Create an iterator over a range of keys as of `snapshot`.
+    pub fn scan_with_snapshot(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        snapshot: &Snapshot,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.core.scan(lower, upper, Some(snapshot.seq()))
+    }
+}
+
+impl Drop for LsmStorage {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+/// The state of the storage shared with the background worker.
+pub(crate) struct LsmStorageCore {
+    pub(crate) inner: Arc<RwLock<Arc<LsmStorageInner>>>,
+    /// Serializes writes, so that sequence numbers are published in order, and a transaction is
+    /// checked for conflicts against every commit before it.
+    write_lock: Mutex<()>,
+    /// Serializes freezing the current memtable.
+    freeze_lock: Mutex<()>,
+    /// Serializes flushing and compaction.
+    flush_lock: Mutex<()>,
+    /// Wakes up the flush worker, dropped on close.
+    flush_notifier: Mutex<Option<Sender<()>>>,
+    /// Signalled whenever a frozen memtable is flushed, or the flush worker stops, for the
+    /// stalled writes to check again.
+    flushed: Condvar,
+    /// Pairs with `flushed`.
+    stall_lock: Mutex<()>,
+    /// The error the flush worker stopped on, if any.
+    background_error: Mutex<Option<String>>,
+    pub(crate) manifest: Manifest,
+    path: PathBuf,
+    pub(crate) block_cache: Arc<BlockCache>,
+    /// The next SSTable ID.
+    next_sst_id: AtomicUsize,
+    /// The sequence number of the last write visible to readers.
+    pub(crate) last_seq: AtomicU64,
+    pub(crate) live_snapshots: LiveSnapshots,
+    commit_log: CommitLog,
+    pub(crate) options: LsmStorageOptions,
+}
+
+impl LsmStorageCore {
+    fn open(
+        path: impl AsRef<Path>,
+        options: LsmStorageOptions,
+        flush_notifier: Sender<()>,
+    ) -> Result<Self> {
+        ensure!(
+            options.block_restart_interval > 0,
+            "block restart interval must be positive"
+        );
+        ensure!(
+            options.max_imm_memtables > 0,
+            "max number of immutable memtables must be positive"
+        );
+        options.compaction_options.validate()?;
+        for &compression in &options.compression_per_level {
+            options.compressors.get(compression)?;
         }
-        // Search on immutable memtables.
-        for memtable in snapshot.imm_memtables.iter().rev() {
-            if let Some(value) = memtable.get(key) {
-                if value.is_empty() {
-                    // found tomestone, return key not exists
-                    return Ok(None);
+        let path = path.as_ref();
+        std::fs::create_dir_all(path)?;
+        let block_cache = Arc::new(BlockCache::new(1 << 20)); // 4GB block cache
+
+        let manifest_path = path.join("MANIFEST");
+        let (manifest, records) = if manifest_path.exists() {
+            Manifest::recover(&manifest_path)?
+        } else {
+            // Without a manifest there is no telling which SSTs are live, so rather than start
+            // over on top of them, leave them for someone to look at.
+            ensure!(
+                Self::sst_ids_in_dir(path)?.is_empty(),
+                "{} has SSTs but no MANIFEST",
+                path.display()
+            );
+            (Manifest::create(&manifest_path)?, Vec::new())
+        };
+
+        // Replay the manifest on SST ids.
+        let mut memtable_ids = BTreeSet::new();
+        let mut l0_ids = Vec::new();
+        let mut level_ids = Vec::new();
+        let mut max_id = 0;
+        for record in records {
+            match record {
+                ManifestRecord::NewMemtable(id) => {
+                    memtable_ids.insert(id);
+                    max_id = max_id.max(id);
+                }
+                ManifestRecord::Flush(id) => {
+                    memtable_ids.remove(&id);
+                    l0_ids.push(id);
+                }
+                ManifestRecord::Compaction {
+                    level,
+                    removed,
+                    added,
+                } => {
+                    max_id = added.iter().copied().fold(max_id, usize::max);
+                    apply_compaction(&mut l0_ids, &mut level_ids, level, &removed, added, |id| {
+                        *id
+                    });
+                }
+                ManifestRecord::TieredCompaction { removed, added } => {
+                    max_id = added.iter().copied().fold(max_id, usize::max);
+                    apply_tiered_compaction(&mut l0_ids, &mut level_ids, &removed, added, |id| *id);
                 }
-                return Ok(Some(value));
             }
         }
-        let mut iters = Vec::new();
-        iters.reserve(snapshot.l0_sstables.len());
+
+        // SSTs the manifest does not know of were written by a flush or compaction that crashed
+        // before recording them. Their ids may be handed out again, so they go away now.
+        let live_ids: HashSet<usize> = l0_ids
+            .iter()
+            .chain(level_ids.iter().flatten())
+            .copied()
+            .collect();
+        for id in Self::sst_ids_in_dir(path)? {
+            if !live_ids.contains(&id) {
+                std::fs::remove_file(Self::path_of_sst_static(path, id))?;
+            }
+        }
+
+        let open_sst = |id: usize| -> Result<Arc<SsTable>> {
+            let file = FileObject::open(&Self::path_of_sst_static(path, id))?;
+            Ok(Arc::new(SsTable::open(
+                id,
+                Some(block_cache.clone()),
+                &options.compressors,
+                file,
+            )?))
+        };
+        let l0_sstables: Vec<_> = l0_ids.into_iter().map(open_sst).collect::<Result<_>>()?;
+        let levels: Vec<Vec<_>> = level_ids
+            .into_iter()
+            .map(|ids| {
+                let mut level = ids.into_iter().map(open_sst).collect::<Result<Vec<_>>>()?;
+                level.sort_by(|a, b| a.first_key().cmp(b.first_key()));
+                Ok(level)
+            })
+            .collect::<Result<_>>()?;
+
+        // Recovered memtables are frozen right away, and will be flushed in the background. The
+        // WAL of a memtable is missing if it was dropped without an SST because it was empty, or
+        // if the process crashed right after the memtable was recorded.
+        let mut imm_memtables = Vec::with_capacity(memtable_ids.len());
+        for id in memtable_ids {
+            let wal_path = Self::path_of_wal_static(path, id);
+            if wal_path.exists() {
+                imm_memtables.push(Arc::new(MemTable::recover_from_wal(id, wal_path)?));
+            }
+        }
+
+        // Sequence numbers continue from the latest write that survived.
+        let last_seq = imm_memtables
+            .iter()
+            .map(|memtable: &Arc<MemTable>| memtable.max_seq())
+            .chain(
+                l0_sstables
+                    .iter()
+                    .map(|table: &Arc<SsTable>| table.max_seq()),
+            )
+            .chain(levels.iter().flatten().map(|table| table.max_seq()))
+            .max()
+            .unwrap_or(0);
+
+        // Never reuse an id, so that new files never overwrite old ones.
+        let memtable_id = max_id + 1;
+        manifest.add_record(ManifestRecord::NewMemtable(memtable_id))?;
+        let memtable =
+            MemTable::create_with_wal(memtable_id, Self::path_of_wal_static(path, memtable_id))?;
+        // Make the manifest, the new WAL and the removal of orphans durable.
+        Self::sync_dir_static(path)?;
+
+        let inner = LsmStorageInner {
+            memtable: Arc::new(memtable),
+            imm_memtables,
+            l0_sstables,
+            levels,
+        };
+        Ok(Self {
+            inner: Arc::new(RwLock::new(Arc::new(inner))),
+            write_lock: Mutex::new(()),
+            freeze_lock: Mutex::new(()),
+            flush_lock: Mutex::new(()),
+            flush_notifier: Mutex::new(Some(flush_notifier)),
+            flushed: Condvar::new(),
+            stall_lock: Mutex::new(()),
+            background_error: Mutex::new(None),
+            manifest,
+            path: path.to_path_buf(),
+            block_cache,
+            next_sst_id: AtomicUsize::new(memtable_id + 1),
+            last_seq: AtomicU64::new(last_seq),
+            live_snapshots: LiveSnapshots::default(),
+            commit_log: CommitLog::default(),
+            options,
+        })
+    }
+
+    pub(crate) fn next_sst_id(&self) -> usize {
+        self.next_sst_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Get the sequence number of the oldest live snapshot, or of the last write if none is live.
+    pub(crate) fn watermark(&self) -> u64 {
+        self.live_snapshots.watermark(&self.last_seq)
+    }
+
+    /// Get the state of the storage along with the sequence number to read at, which is
+    /// `read_seq` if given, or the latest one.
+    fn read_state(&self, read_seq: Option<u64>) -> (Arc<LsmStorageInner>, u64) {
+        let guard = self.inner.read();
+        // Writers publish their sequence number while holding the read lock, so the state always
+        // has the writes up to it.
+        let read_seq = read_seq.unwrap_or_else(|| self.last_seq.load(Ordering::SeqCst));
+        (Arc::clone(&guard), read_seq)
+    }
+
+    /// Get a key from the storage as of `read_seq`, or the latest version if `None`. SSTs whose
+    /// bloom filter rules out the key are skipped.
+    pub fn get(&self, key: &[u8], read_seq: Option<u64>) -> Result<Option<Bytes>> {
+        let (snapshot, read_seq) = self.read_state(read_seq); // drop global lock here
+
+        // The latest range tombstone covering the key so far. Newer sources are searched first,
+        // so it may only delete the entry found in the same source or an older one.
+        let mut tombstone_seq = 0;
+
+        // Search on the memtables, from the latest to the earliest.
+        for memtable in
+            std::iter::once(&snapshot.memtable).chain(snapshot.imm_memtables.iter().rev())
+        {
+            tombstone_seq = tombstone_seq.max(memtable.max_covering_tombstone_seq(key, read_seq));
+            if let Some(entry) = memtable.get(key, read_seq) {
+                return self.entry_to_value(key, read_seq, entry, tombstone_seq);
+            }
+        }
+        // Search on L0 SSTs, from the latest to the earliest.
         for table in snapshot.l0_sstables.iter().rev() {
-            iters.push(Box::new(SsTableIterator::create_and_seek_to_key(
-                table.clone(),
-                key,
-            )?));
+            tombstone_seq = tombstone_seq.max(table.max_covering_tombstone_seq(key, read_seq));
+            if let Some(entry) = table.get(key, read_seq)? {
+                return self.entry_to_value(key, read_seq, entry, tombstone_seq);
+            }
         }
-        let iter = MergeIterator::create(iters);
-        if iter.is_valid() {
-            return Ok(Some(Bytes::copy_from_slice(iter.value())));
+        // Search on L1+, where at most one SST in each level may contain the key.
+        for level in snapshot.levels.iter() {
+            let idx = level
+                .partition_point(|table| table.first_key() <= key)
+                .saturating_sub(1);
+            if let Some(table) = level.get(idx) {
+                tombstone_seq = tombstone_seq.max(table.max_covering_tombstone_seq(key, read_seq));
+                if let Some(entry) = table.get(key, read_seq)? {
+                    return self.entry_to_value(key, read_seq, entry, tombstone_seq);
+                }
+            }
         }
         Ok(None)
     }
 
-    /// Put a key-value pair into the storage by writing into the current memtable.
+    /// The value of the latest visible entry of a key, or `None` if it is a tombstone or deleted
+    /// by a range tombstone at `tombstone_seq`. A merge operand is folded with the earlier
+    /// versions of the key.
+    fn entry_to_value(
+        &self,
+        key: &[u8],
+        read_seq: u64,
+        (value_type, value, seq): (ValueType, Bytes, u64),
+        tombstone_seq: u64,
+    ) -> Result<Option<Bytes>> {
+        if seq < tombstone_seq {
+            return Ok(None);
+        }
+        match value_type {
+            ValueType::Put => Ok(Some(value)),
+            // The operands may be spread across sources, which the iterator merges.
+            ValueType::Merge => {
+                let iter = self.scan(Bound::Included(key), Bound::Included(key), Some(read_seq))?;
+                Ok(iter
+                    .is_valid()
+                    .then(|| Bytes::copy_from_slice(iter.value())))
+            }
+            ValueType::Delete | ValueType::RangeDelete => Ok(None),
+        }
+    }
+
+    /// Put a key-value pair into the storage by writing into the current memtable. The value may
+    /// be empty.
     pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
-        assert!(!value.is_empty(), "value cannot be empty");
-        assert!(!key.is_empty(), "key cannot be empty");
+        self.write(WriteBatch::new().put(key, value))
+    }
 
-        let guard = self.inner.read();
-        guard.memtable.put(key, value);
+    /// Remove a key from the storage by writing a tombstone.
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        self.write(WriteBatch::new().delete(key))
+    }
+
+    /// Remove all keys in `[start, end)` from the storage by writing a single range tombstone.
+    pub fn delete_range(&self, start: &[u8], end: &[u8]) -> Result<()> {
+        self.write(WriteBatch::new().delete_range(start, end))
+    }
+
+    /// Merge an operand into the value of a key by writing it into the current memtable.
+    pub fn merge(&self, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.write(WriteBatch::new().merge(key, operand))
+    }
+
+    /// Apply all operations of a batch to the current memtable, as a single WAL record with a new
+    /// sequence number. A range delete is written as a range tombstone, and also drops the
+    /// operations on keys in the range earlier in the batch. A merge is folded into the earlier
+    /// operation on the same key in the batch, if any.
+    pub fn write(&self, batch: &WriteBatch) -> Result<()> {
+        self.commit(batch, None)
+    }
+
+    /// Write a batch like [`LsmStorageCore::write`]. For a transaction started at `start_seq`,
+    /// fail with [`TxnConflict`] instead if any key of the batch or in the read set, if tracked,
+    /// was written after it.
+    pub(crate) fn commit(
+        &self,
+        batch: &WriteBatch,
+        txn: Option<(u64, Option<&ReadSet>)>,
+    ) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let _write_lock = self.write_lock.lock();
+
+        let mut entries: Vec<(Bytes, ValueType, Bytes)> = Vec::with_capacity(batch.len());
+        for record in batch.records() {
+            match record {
+                WriteBatchRecord::Put(key, value) => {
+                    entries.push((key.clone(), ValueType::Put, value.clone()))
+                }
+                WriteBatchRecord::Delete(key) => {
+                    entries.push((key.clone(), ValueType::Delete, Bytes::new()))
+                }
+                WriteBatchRecord::DeleteRange(start, end) => {
+                    if start >= end {
+                        continue;
+                    }
+                    // The tombstone only covers the writes before the batch, so the ones in the
+                    // batch are dropped here.
+                    entries.retain(|(key, value_type, _)| {
+                        *value_type == ValueType::RangeDelete || key < start || key >= end
+                    });
+                    entries.push((start.clone(), ValueType::RangeDelete, end.clone()));
+                }
+                WriteBatchRecord::Merge(key, operand) => {
+                    let entry = self.merge_in_batch(&mut entries, key, operand)?;
+                    entries.push(entry);
+                }
+            }
+        }
+        let written: Vec<(Bytes, Bytes)> = entries
+            .iter()
+            .map(|(key, value_type, value)| match value_type {
+                ValueType::RangeDelete => (key.clone(), value.clone()),
+                _ => key_range(key),
+            })
+            .collect();
+        if let Some((start_seq, read_set)) = txn {
+            let keys: BTreeSet<&[u8]> = entries.iter().map(|(key, _, _)| &key[..]).collect();
+            if let Some(key) = self.commit_log.find_since(start_seq, |start, end| {
+                keys.range::<[u8], _>((Bound::Included(start), Bound::Excluded(end)))
+                    .next()
+                    .is_some()
+                    || read_set.is_some_and(|read_set| read_set.overlaps(start, end))
+            }) {
+                return Err(TxnConflict { key }.into());
+            }
+        }
+
+        let seq = self.last_seq.load(Ordering::SeqCst) + 1;
+        self.write_memtable(
+            seq,
+            &entries
+                .iter()
+                .map(|(key, value_type, value)| (&key[..], *value_type, &value[..]))
+                .collect::<Vec<_>>(),
+        )?;
+        // A snapshot taken from now on starts after this commit, so it only has to be logged for
+        // the live ones.
+        match self.live_snapshots.oldest() {
+            Some(oldest) => self.commit_log.record(seq, written, oldest),
+            None => self.commit_log.clear(),
+        }
+        Ok(())
+    }
 
+    /// Get the entry of a merge in a batch. An earlier entry of the same key in the batch would
+    /// be replaced, having the same sequence number, so it is removed and the operand is folded
+    /// into it instead.
+    fn merge_in_batch(
+        &self,
+        entries: &mut Vec<(Bytes, ValueType, Bytes)>,
+        key: &Bytes,
+        operand: &Bytes,
+    ) -> Result<(Bytes, ValueType, Bytes)> {
+        let Some(operator) = self.options.merge_operator.as_deref() else {
+            bail!("merge without a merge operator");
+        };
+        let Some(idx) = entries
+            .iter()
+            .rposition(|(k, value_type, _)| k == key && *value_type != ValueType::RangeDelete)
+        else {
+            return Ok((key.clone(), ValueType::Merge, operand.clone()));
+        };
+        let (_, value_type, value) = entries.remove(idx);
+        let (value_type, value) = match value_type {
+            ValueType::Put => (
+                ValueType::Put,
+                operator.full_merge(key, Some(&value), &[operand]),
+            ),
+            ValueType::Delete => (ValueType::Put, operator.full_merge(key, None, &[operand])),
+            _ => match operator.partial_merge(key, &[&value, operand]) {
+                Some(operand) => (ValueType::Merge, operand),
+                None => {
+                    // A range delete earlier in the batch deletes the existing value.
+                    let deleted = entries.iter().any(|(start, value_type, end)| {
+                        *value_type == ValueType::RangeDelete && start <= key && key < end
+                    });
+                    let existing = if deleted { None } else { self.get(key, None)? };
+                    let value = operator.full_merge(key, existing.as_deref(), &[&value, operand]);
+                    (ValueType::Put, value)
+                }
+            },
+        };
+        Ok((key.clone(), value_type, value))
+    }
+
+    /// Write into the current memtable at `seq`, and freeze it if it grows past the size limit.
+    /// The read lock on the state keeps the memtable from being frozen in the middle of the write.
+    fn write_memtable(&self, seq: u64, entries: &[(&[u8], ValueType, &[u8])]) -> Result<()> {
+        ensure!(self.flush_notifier.lock().is_some(), "storage is closed");
+        self.stall_writes()?;
+        let size = {
+            let guard = self.inner.read();
+            guard.memtable.insert_batch(seq, entries)?;
+            // Readers see the whole write at once, from now on.
+            self.last_seq.store(seq, Ordering::SeqCst);
+            guard.memtable.approximate_size()
+        };
+        if size >= self.options.memtable_size_limit {
+            let freeze_lock = self.freeze_lock.lock();
+            // Another writer may have frozen the memtable while we were waiting for the lock.
+            if self.inner.read().memtable.approximate_size() >= self.options.memtable_size_limit {
+                self.freeze_memtable(&freeze_lock)?;
+                self.notify_flush();
+            }
+        }
         Ok(())
     }
 
-    /// Remove a key from the storage by writing an empty value.
-    pub fn delete(&self, key: &[u8]) -> Result<()> {
-        assert!(!key.is_empty(), "key cannot be empty");
+    fn path_of_sst_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
+        path.as_ref().join(format!("{:05}.sst", id))
+    }
 
-        let guard = self.inner.read();
-        guard.memtable.put(key, b"");
+    pub(crate) fn path_of_sst(&self, id: usize) -> PathBuf {
+        Self::path_of_sst_static(&self.path, id)
+    }
+
+    fn path_of_wal_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
+        path.as_ref().join(format!("{:05}.wal", id))
+    }
 
+    fn path_of_wal(&self, id: usize) -> PathBuf {
+        Self::path_of_wal_static(&self.path, id)
+    }
+
+    /// The ids of the SST files in `path`, by their names.
+    fn sst_ids_in_dir(path: &Path) -> Result<Vec<usize>> {
+        let mut ids = Vec::new();
+        for entry in std::fs::read_dir(path)? {
+            let name = entry?.file_name();
+            let id = name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".sst"))
+                .and_then(|id| id.parse().ok());
+            if let Some(id) = id {
+                ids.push(id);
+            }
+        }
+        Ok(ids)
+    }
+
+    fn sync_dir_static(path: &Path) -> Result<()> {
+        File::open(path)?.sync_all()?;
         Ok(())
     }
 
-    fn path_of_sst(&self, id: usize) -> PathBuf {
-        self.path.join(format!("{:05}.sst", id))
+    /// Fsync the directory, so that the files created or removed in it so far stay that way
+    /// after a power loss. Fsyncing a file does not cover its entry in the directory.
+    pub(crate) fn sync_dir(&self) -> Result<()> {
+        Self::sync_dir_static(&self.path)
+    }
+
+    /// Fsync the WAL of every memtable that has not been flushed yet, so that all writes
+    /// acknowledged so far survive a power loss.
+    pub fn sync_wal(&self) -> Result<()> {
+        let snapshot = {
+            let guard = self.inner.read();
+            Arc::clone(&guard)
+        }; // drop global lock here
+
+        snapshot.memtable.sync_wal()?;
+        for memtable in snapshot.imm_memtables.iter() {
+            memtable.sync_wal()?;
+        }
+        Ok(())
     }
 
     /// Persist data to disk.
     ///
     /// In day 3: flush the current memtable to disk as L0 SST.
-    /// In day 6: call `fsync` on WAL.
+    /// In day 5: compact the SSTs if needed.
+    /// In day 6: delete the WAL segment of each memtable once it has been flushed.
     pub fn sync(&self) -> Result<()> {
-        let _flush_lock = self.flush_lock.lock();
+        self.check_background_error()?;
+        {
+            let freeze_lock = self.freeze_lock.lock();
+            self.freeze_memtable(&freeze_lock)?;
+        }
+        self.flush_and_compact()
+    }
 
-        let flush_memtable;
-        let sst_id;
+    /// Replace the current memtable with a new one, and move it to the immutable memtables.
+    fn freeze_memtable(&self, _freeze_lock: &MutexGuard<()>) -> Result<()> {
+        let memtable_id = self.next_sst_id();
+        self.manifest
+            .add_record(ManifestRecord::NewMemtable(memtable_id))?;
+        let memtable = Arc::new(MemTable::create_with_wal(
+            memtable_id,
+            self.path_of_wal(memtable_id),
+        )?);
+        self.sync_dir()?;
 
         // Move mutable memtable to immutable memtables.
         {
             let mut guard = self.inner.write();
             // Swap the current memtable with a new one.
             let mut snapshot = guard.as_ref().clone();
-            let memtable = std::mem::replace(&mut snapshot.memtable, Arc::new(MemTable::create()));
-            flush_memtable = memtable.clone();
-            sst_id = snapshot.next_sst_id;
+            let memtable = std::mem::replace(&mut snapshot.memtable, memtable);
             // Add the memtable to the immutable memtables.
             snapshot.imm_memtables.push(memtable);
             // Update the snapshot.
             *guard = Arc::new(snapshot);
         }
+        Ok(())
+    }
 
-        // At this point, the old memtable should be disabled for write, and all write threads
-        // should be operating on the new memtable. We can safely flush the old memtable to
-        // disk.
+    /// Create a builder for an SST written to `level`, 0 for L0.
+    pub(crate) fn new_sst_builder(&self, level: usize) -> SsTableBuilder {
+        let compression = &self.options.compression_per_level;
+        let compression = compression
+            .get(level)
+            .or(compression.last())
+            .copied()
+            .unwrap_or_default();
+        let compressor = self
+            .options
+            .compressors
+            .get(compression)
+            .expect("compression types are checked at open");
+        let mut builder = SsTableBuilder::new(self.options.block_size)
+            .with_restart_interval(self.options.block_restart_interval)
+            .with_bloom_bits_per_key(self.options.bloom_bits_per_key);
+        if let Some(compressor) = compressor {
+            builder = builder.with_compressor(compressor);
+        }
+        match &self.options.prefix_extractor {
+            Some(extractor) => builder.with_prefix_extractor(extractor.clone()),
+            None => builder,
+        }
+    }
 
-        let mut builder = SsTableBuilder::new(4096);
-        flush_memtable.flush(&mut builder)?;
-        let sst = Arc::new(builder.build(
-            sst_id,
-            Some(self.block_cache.clone()),
-            self.path_of_sst(sst_id),
-        )?);
+    /// Wake up the flush worker, unless the storage is closed.
+    fn notify_flush(&self) {
+        if let Some(notifier) = self.flush_notifier.lock().as_ref() {
+            // The worker only goes away on close, or after an error that later writes report.
+            let _ = notifier.send(());
+        }
+    }
 
-        // Add the flushed L0 table to the list.
-        {
-            let mut guard = self.inner.write();
-            let mut snapshot = guard.as_ref().clone();
-            // Remove the memtable from the immutable memtables.
-            snapshot.imm_memtables.pop();
-            // Add L0 table
-            snapshot.l0_sstables.push(sst);
-            // Update SST ID
-            snapshot.next_sst_id += 1;
-            // Update the snapshot.
-            *guard = Arc::new(snapshot);
+    /// Wait until fewer than `max_imm_memtables` frozen memtables are left to flush, or fail if the
+    /// flush worker has stopped on an error.
+    fn stall_writes(&self) -> Result<()> {
+        let mut guard = self.stall_lock.lock();
+        loop {
+            self.check_background_error()?;
+            if self.inner.read().imm_memtables.len() < self.options.max_imm_memtables {
+                return Ok(());
+            }
+            self.flushed.wait(&mut guard);
         }
+    }
 
-        Ok(())
+    /// Wake up the stalled writes. Taking the lock orders this after their last check.
+    fn notify_flushed(&self) {
+        drop(self.stall_lock.lock());
+        self.flushed.notify_all();
     }
 
-    /// Create an iterator over a range of keys.
+    /// Fail if the flush worker has stopped on an error.
+    fn check_background_error(&self) -> Result<()> {
+        match self.background_error.lock().as_deref() {
+            Some(err) => bail!("background flush failed: {}", err),
+            None => Ok(()),
+        }
+    }
+
+    /// The loop of the flush worker. Flush and compact whenever notified, and once more after
+    /// the storage is closed, so that no frozen memtable is left behind. Stop on the first error,
+    /// which is kept for later writes to report.
+    fn flush_worker(&self, receiver: Receiver<()>) -> Result<()> {
+        let result = (|| {
+            while receiver.recv().is_ok() {
+                self.flush_and_compact()?;
+            }
+            self.flush_and_compact()
+        })();
+        if let Err(err) = &result {
+            *self.background_error.lock() = Some(format!("{:#}", err));
+            self.notify_flushed();
+        }
+        result
+    }
+
+    /// Flush the immutable memtables into L0, then compact the SSTs if needed.
+    fn flush_and_compact(&self) -> Result<()> {
+        let _flush_lock = self.flush_lock.lock();
+
+        // The memtables to flush are disabled for write, and all write threads are operating on
+        // the current memtable. We can safely flush them to disk, from earliest to latest.
+        loop {
+            let Some(flush_memtable) = self.inner.read().imm_memtables.first().cloned() else {
+                break;
+            };
+
+            // An empty memtable is dropped without an SST or a manifest record.
+            let sst = if flush_memtable.is_empty() {
+                None
+            } else {
+                let sst_id = flush_memtable.id();
+                let mut builder = self.new_sst_builder(0);
+                flush_memtable.flush(
+                    &mut builder,
+                    self.watermark(),
+                    self.options.merge_operator.as_deref(),
+                )?;
+                let sst = Arc::new(builder.build(
+                    sst_id,
+                    Some(self.block_cache.clone()),
+                    self.path_of_sst(sst_id),
+                )?);
+                // The SST must be in the directory for good before the manifest refers to it.
+                self.sync_dir()?;
+                self.manifest.add_record(ManifestRecord::Flush(sst_id))?;
+                Some(sst)
+            };
+
+            // Add the flushed L0 table to the list.
+            {
+                let mut guard = self.inner.write();
+                let mut snapshot = guard.as_ref().clone();
+                // Remove the memtable from the immutable memtables.
+                snapshot.imm_memtables.remove(0);
+                // Add L0 table
+                if let Some(sst) = sst {
+                    snapshot.l0_sstables.push(sst);
+                }
+                // Update the snapshot.
+                *guard = Arc::new(snapshot);
+            }
+
+            self.notify_flushed();
+
+            // The data is now in the SST, so the WAL segment is no longer needed.
+            std::fs::remove_file(self.path_of_wal(flush_memtable.id()))?;
+            self.sync_dir()?;
+        }
+
+        self.compact()
+    }
+
+    /// Create an iterator over a range of keys as of `read_seq`, or the latest versions if
+    /// `None`.
     pub fn scan(
         &self,
         lower: Bound<&[u8]>,
         upper: Bound<&[u8]>,
+        read_seq: Option<u64>,
     ) -> Result<FusedIterator<LsmIterator>> {
-        let snapshot = {
-            let guard = self.inner.read();
-            Arc::clone(&guard)
-        }; // drop global lock here
+        self.scan_inner(lower, upper, read_seq, false, None)
+    }
 
-        let mut memtable_iters = Vec::new();
-        memtable_iters.reserve(snapshot.imm_memtables.len() + 1);
-        memtable_iters.push(Box::new(snapshot.memtable.scan(lower, upper)));
-        for memtable in snapshot.imm_memtables.iter().rev() {
-            memtable_iters.push(Box::new(memtable.scan(lower, upper)));
+    /// Create an iterator over a range of keys moving backward, from the last key to the first.
+    pub fn scan_rev(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_seq: Option<u64>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.scan_inner(lower, upper, read_seq, true, None)
+    }
+
+    /// Create an iterator over the keys starting with `prefix`, skipping the SSTs whose prefix
+    /// filter rules it out.
+    pub fn scan_prefix(
+        &self,
+        prefix: &[u8],
+        read_seq: Option<u64>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        let upper = prefix_upper_bound(prefix);
+        let upper = upper.as_ref().map(|x| &x[..]);
+        self.scan_inner(
+            Bound::Included(prefix),
+            upper,
+            read_seq,
+            false,
+            Some(prefix),
+        )
+    }
+
+    /// Create an iterator over a range of keys, with all its sources positioned at the first
+    /// entry within the range, or at the last one when moving backward. If all the keys start
+    /// with `prefix`, the SSTs without it are skipped, except for their range tombstones.
+    fn scan_inner(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_seq: Option<u64>,
+        backward: bool,
+        prefix: Option<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        let may_contain_prefix = |table: &SsTable| match (prefix, &self.options.prefix_extractor) {
+            (Some(prefix), Some(extractor)) => table.may_contain_prefix(extractor.as_ref(), prefix),
+            _ => true,
+        };
+        let (snapshot, read_seq) = self.read_state(read_seq); // drop global lock here
+
+        let mut range_tombstones = Vec::new();
+        let mut memtable_iters = Vec::with_capacity(snapshot.imm_memtables.len() + 1);
+        for memtable in
+            std::iter::once(&snapshot.memtable).chain(snapshot.imm_memtables.iter().rev())
+        {
+            let mut iter = memtable.scan(lower, upper);
+            if backward {
+                iter.seek_to_last();
+            }
+            memtable_iters.push(Box::new(iter));
+            range_tombstones.extend(memtable.range_tombstones(read_seq));
         }
-        let memtable_iter = MergeIterator::create(memtable_iters);
+        let memtable_iter = create_merge_iterator(memtable_iters, backward);
 
-        let mut table_iters = Vec::new();
-        table_iters.reserve(snapshot.l0_sstables.len());
+        let mut table_iters = Vec::with_capacity(snapshot.l0_sstables.len());
         for table in snapshot.l0_sstables.iter().rev() {
-            let iter = match lower {
-                Bound::Included(key) => {
+            if !table.range_overlap(lower, upper) {
+                continue;
+            }
+            range_tombstones.extend(visible_tombstones(table, read_seq));
+            if !may_contain_prefix(table) {
+                continue;
+            }
+            let iter = match (backward, lower, upper) {
+                (false, Bound::Included(key) | Bound::Excluded(key), _)
+                | (true, _, Bound::Included(key) | Bound::Excluded(key)) => {
                     SsTableIterator::create_and_seek_to_key(table.clone(), key)?
                 }
-                Bound::Excluded(key) => {
-                    let mut iter = SsTableIterator::create_and_seek_to_key(table.clone(), key)?;
-                    if iter.is_valid() && iter.key() == key {
-                        iter.next()?;
-                    }
-                    iter
+                (false, Bound::Unbounded, _) => {
+                    SsTableIterator::create_and_seek_to_first(table.clone())?
+                }
+                (true, _, Bound::Unbounded) => {
+                    SsTableIterator::create_and_seek_to_last(table.clone())?
                 }
-                Bound::Unbounded => SsTableIterator::create_and_seek_to_first(table.clone())?,
             };
+            table_iters.push(Box::new(move_into_range(iter, lower, upper, backward)?));
+        }
+        let l0_iter = create_merge_iterator(table_iters, backward);
 
-            table_iters.push(Box::new(iter));
+        let mut level_iters = Vec::with_capacity(snapshot.levels.len());
+        for level in snapshot.levels.iter() {
+            let level: Vec<_> = level
+                .iter()
+                .filter(|table| table.range_overlap(lower, upper))
+                .cloned()
+                .collect();
+            for table in &level {
+                range_tombstones.extend(visible_tombstones(table, read_seq));
+            }
+            let level: Vec<_> = level
+                .into_iter()
+                .filter(|table| may_contain_prefix(table))
+                .collect();
+            if level.is_empty() {
+                continue;
+            }
+            let iter = match (backward, lower, upper) {
+                (false, Bound::Included(key) | Bound::Excluded(key), _)
+                | (true, _, Bound::Included(key) | Bound::Excluded(key)) => {
+                    SstConcatIterator::create_and_seek_to_key(level, key)?
+                }
+                (false, Bound::Unbounded, _) => SstConcatIterator::create_and_seek_to_first(level)?,
+                (true, _, Bound::Unbounded) => SstConcatIterator::create_and_seek_to_last(level)?,
+            };
+            level_iters.push(Box::new(move_into_range(iter, lower, upper, backward)?));
         }
-        let table_iter = MergeIterator::create(table_iters);
+        let level_iter = create_merge_iterator(level_iters, backward);
 
-        let iter = TwoMergeIterator::create(memtable_iter, table_iter)?;
+        let iter = if backward {
+            TwoMergeIterator::create_backward(
+                memtable_iter,
+                TwoMergeIterator::create_backward(l0_iter, level_iter)?,
+            )?
+        } else {
+            TwoMergeIterator::create(
+                memtable_iter,
+                TwoMergeIterator::create(l0_iter, level_iter)?,
+            )?
+        };
 
         Ok(FusedIterator::new(LsmIterator::new(
             iter,
-            map_bound(upper),
+            (map_bound(lower), map_bound(upper)),
+            read_seq,
+            range_tombstones,
+            self.options.merge_operator.clone(),
+            backward,
         )?))
     }
 }
+
+fn create_merge_iterator<I: StorageIterator>(
+    iters: Vec<Box<I>>,
+    backward: bool,
+) -> MergeIterator<I> {
+    if backward {
+        MergeIterator::create_backward(iters)
+    } else {
+        MergeIterator::create(iters)
+    }
+}
+
+/// Move an iterator seeked to the bound of a range to its first entry within the range, or to
+/// its last one when moving backward: past the versions of an excluded lower bound, or before
+/// the ones of the upper bound unless it is included.
+fn move_into_range<I: StorageIterator>(
+    mut iter: I,
+    lower: Bound<&[u8]>,
+    upper: Bound<&[u8]>,
+    backward: bool,
+) -> Result<I> {
+    match (backward, lower, upper) {
+        (false, Bound::Excluded(key), _) => {
+            while iter.is_valid() && iter.key() == key {
+                iter.next()?;
+            }
+        }
+        (true, _, Bound::Included(key)) => {
+            while iter.is_valid() && iter.key() == key {
+                iter.next()?;
+            }
+            iter.prev()?;
+        }
+        (true, _, Bound::Excluded(_)) => iter.prev()?,
+        _ => {}
+    }
+    Ok(iter)
+}
+
+/// Get the range tombstones of an SST visible at `read_seq`.
+fn visible_tombstones(table: &SsTable, read_seq: u64) -> impl Iterator<Item = RangeTombstone> + '_ {
+    table
+        .range_tombstones()
+        .iter()
+        .filter(move |t| t.seq <= read_seq)
+        .cloned()
+}
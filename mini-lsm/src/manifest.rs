@@ -0,0 +1,200 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use bytes::{Buf, BufMut};
+use parking_lot::Mutex;
+
+const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+
+/// A change to the structure of the LSM tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ManifestRecord {
+    /// A memtable with this id was created, along with its WAL segment.
+    NewMemtable(usize),
+    /// The memtable with this id was flushed into an L0 SST with the same id.
+    Flush(usize),
+    /// A compaction deleted the SSTs in `removed` and wrote the ones in `added` into `level`.
+    ///
+    /// `added` is sorted by key, and takes the place of the SSTs removed from `level`.
+    Compaction {
+        level: usize,
+        removed: Vec<usize>,
+        added: Vec<usize>,
+    },
+    /// A tiered compaction deleted the SSTs in `removed`, and wrote the ones in `added` as a new
+    /// level in front of all others. Levels left empty are dropped.
+    TieredCompaction {
+        removed: Vec<usize>,
+        added: Vec<usize>,
+    },
+}
+
+const TAG_NEW_MEMTABLE: u8 = 0;
+const TAG_FLUSH: u8 = 1;
+const TAG_COMPACTION: u8 = 2;
+const TAG_TIERED_COMPACTION: u8 = 3;
+
+impl ManifestRecord {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        fn put_ids(buf: &mut Vec<u8>, ids: &[usize]) {
+            buf.put_u32(ids.len() as u32);
+            for &id in ids {
+                buf.put_u64(id as u64);
+            }
+        }
+
+        match self {
+            ManifestRecord::NewMemtable(id) => {
+                buf.put_u8(TAG_NEW_MEMTABLE);
+                buf.put_u64(*id as u64);
+            }
+            ManifestRecord::Flush(id) => {
+                buf.put_u8(TAG_FLUSH);
+                buf.put_u64(*id as u64);
+            }
+            ManifestRecord::Compaction {
+                level,
+                removed,
+                added,
+            } => {
+                buf.put_u8(TAG_COMPACTION);
+                buf.put_u32(*level as u32);
+                put_ids(buf, removed);
+                put_ids(buf, added);
+            }
+            ManifestRecord::TieredCompaction { removed, added } => {
+                buf.put_u8(TAG_TIERED_COMPACTION);
+                put_ids(buf, removed);
+                put_ids(buf, added);
+            }
+        }
+    }
+
+    fn decode(mut buf: &[u8]) -> Result<Self> {
+        fn get_ids(buf: &mut &[u8]) -> Result<Vec<usize>> {
+            if buf.remaining() < SIZEOF_U32 {
+                bail!("truncated manifest record");
+            }
+            let len = buf.get_u32() as usize;
+            if buf.remaining() < len * std::mem::size_of::<u64>() {
+                bail!("truncated manifest record");
+            }
+            Ok((0..len).map(|_| buf.get_u64() as usize).collect())
+        }
+
+        if !buf.has_remaining() {
+            bail!("empty manifest record");
+        }
+        let record = match buf.get_u8() {
+            TAG_NEW_MEMTABLE if buf.remaining() == 8 => {
+                ManifestRecord::NewMemtable(buf.get_u64() as usize)
+            }
+            TAG_FLUSH if buf.remaining() == 8 => ManifestRecord::Flush(buf.get_u64() as usize),
+            TAG_COMPACTION if buf.remaining() >= SIZEOF_U32 => {
+                let level = buf.get_u32() as usize;
+                let removed = get_ids(&mut buf)?;
+                let added = get_ids(&mut buf)?;
+                ManifestRecord::Compaction {
+                    level,
+                    removed,
+                    added,
+                }
+            }
+            TAG_TIERED_COMPACTION => {
+                let removed = get_ids(&mut buf)?;
+                let added = get_ids(&mut buf)?;
+                ManifestRecord::TieredCompaction { removed, added }
+            }
+            tag => bail!("invalid manifest record with tag {}", tag),
+        };
+        if buf.has_remaining() {
+            bail!("trailing bytes in manifest record");
+        }
+        Ok(record)
+    }
+}
+
+/// An append-only log of [`ManifestRecord`]s. Each record is framed as:
+///
+/// ```plaintext
+/// | len (u32) | record | checksum (u32) |
+/// ```
+///
+/// The checksum covers the length and the record.
+pub struct Manifest {
+    file: Mutex<File>,
+}
+
+impl Manifest {
+    /// Create a new manifest at `path`.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(path.as_ref())
+            .with_context(|| format!("failed to create manifest {}", path.as_ref().display()))?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Read all records of an existing manifest, and reopen it for appending.
+    ///
+    /// A torn record at the tail means the process crashed before the change took effect, so it
+    /// is dropped and the manifest is truncated to the last complete record.
+    pub fn recover(path: impl AsRef<Path>) -> Result<(Self, Vec<ManifestRecord>)> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("failed to recover manifest {}", path.display()))?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+
+        let mut records = Vec::new();
+        let mut rbuf = &buf[..];
+        while rbuf.remaining() >= SIZEOF_U32 {
+            let len = (&rbuf[..SIZEOF_U32]).get_u32() as usize;
+            if rbuf.remaining() < SIZEOF_U32 + len + SIZEOF_U32 {
+                break;
+            }
+            let (frame, mut rest) = rbuf.split_at(SIZEOF_U32 + len);
+            if crc32fast::hash(frame) != rest.get_u32() {
+                break;
+            }
+            records.push(ManifestRecord::decode(&frame[SIZEOF_U32..])?);
+            rbuf = rest;
+        }
+        let valid_len = (buf.len() - rbuf.len()) as u64;
+        if valid_len < buf.len() as u64 {
+            file.set_len(valid_len)?;
+            file.sync_all()?;
+        }
+        file.seek(SeekFrom::End(0))?;
+        Ok((
+            Self {
+                file: Mutex::new(file),
+            },
+            records,
+        ))
+    }
+
+    /// Append a record and fsync the manifest.
+    pub fn add_record(&self, record: ManifestRecord) -> Result<()> {
+        let mut buf = vec![0; SIZEOF_U32];
+        record.encode(&mut buf);
+        let len = buf.len() - SIZEOF_U32;
+        (&mut buf[..SIZEOF_U32]).put_u32(len as u32);
+        buf.put_u32(crc32fast::hash(&buf));
+        let mut file = self.file.lock();
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
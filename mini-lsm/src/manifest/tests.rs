@@ -0,0 +1,73 @@
+use std::fs::OpenOptions;
+
+use tempfile::tempdir;
+
+use super::{Manifest, ManifestRecord};
+
+fn records() -> Vec<ManifestRecord> {
+    vec![
+        ManifestRecord::NewMemtable(1),
+        ManifestRecord::Flush(1),
+        ManifestRecord::NewMemtable(2),
+        ManifestRecord::Compaction {
+            level: 1,
+            removed: vec![1, 2],
+            added: vec![3, 4, 5],
+        },
+        ManifestRecord::Compaction {
+            level: 2,
+            removed: vec![],
+            added: vec![],
+        },
+        ManifestRecord::TieredCompaction {
+            removed: vec![3, 4, 5],
+            added: vec![7],
+        },
+    ]
+}
+
+#[test]
+fn test_manifest_recover() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("MANIFEST");
+    {
+        let manifest = Manifest::create(&path).unwrap();
+        for record in records() {
+            manifest.add_record(record).unwrap();
+        }
+    }
+    let (manifest, recovered) = Manifest::recover(&path).unwrap();
+    assert_eq!(recovered, records());
+
+    manifest.add_record(ManifestRecord::Flush(6)).unwrap();
+    drop(manifest);
+    let (_, recovered) = Manifest::recover(&path).unwrap();
+    assert_eq!(recovered.len(), records().len() + 1);
+    assert_eq!(recovered.last(), Some(&ManifestRecord::Flush(6)));
+}
+
+#[test]
+fn test_manifest_recover_torn_tail() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("MANIFEST");
+    {
+        let manifest = Manifest::create(&path).unwrap();
+        for record in records() {
+            manifest.add_record(record).unwrap();
+        }
+    }
+    // Simulate a crash in the middle of writing the last record.
+    let len = std::fs::metadata(&path).unwrap().len();
+    let file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(len - 1).unwrap();
+    drop(file);
+
+    let (manifest, recovered) = Manifest::recover(&path).unwrap();
+    assert_eq!(recovered, records()[..records().len() - 1]);
+
+    manifest.add_record(ManifestRecord::Flush(6)).unwrap();
+    drop(manifest);
+    let (_, recovered) = Manifest::recover(&path).unwrap();
+    assert_eq!(recovered.len(), records().len());
+    assert_eq!(recovered.last(), Some(&ManifestRecord::Flush(6)));
+}
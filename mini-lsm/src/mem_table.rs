@@ -1,4 +1,6 @@
 use std::ops::Bound;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use anyhow::Result;
@@ -6,13 +8,27 @@ use bytes::Bytes;
 use crossbeam_skiplist::map::Entry;
 use crossbeam_skiplist::SkipMap;
 use ouroboros::self_referencing;
+use parking_lot::RwLock;
 
 use crate::iterators::StorageIterator;
+use crate::key::InternalKey;
+use crate::merge_operator::{collapse_operands, MergeOperator};
+use crate::range_tombstone::{max_covering_seq, RangeTombstone};
 use crate::table::SsTableBuilder;
+use crate::value_type::ValueType;
+use crate::wal::Wal;
 
-/// A basic mem-table based on crossbeam-skiplist
+/// A basic mem-table based on crossbeam-skiplist. Each mem-table is identified by the id of the
+/// SST it will be flushed into, and may own a WAL segment that records every write.
+///
+/// Every write is kept as a new version of the key, tagged with the sequence number of the write.
+/// Range deletes are kept aside as range tombstones.
 pub struct MemTable {
-    map: Arc<SkipMap<Bytes, Bytes>>,
+    map: Arc<SkipMap<InternalKey, (ValueType, Bytes)>>,
+    range_tombstones: RwLock<Vec<RangeTombstone>>,
+    wal: Option<Wal>,
+    id: usize,
+    approximate_size: AtomicUsize,
 }
 
 pub(crate) fn map_bound(bound: Bound<&[u8]>) -> Bound<Bytes> {
@@ -23,32 +39,181 @@ pub(crate) fn map_bound(bound: Bound<&[u8]>) -> Bound<Bytes> {
     }
 }
 
+/// Map a lower bound of user keys to a bound of internal keys, covering all versions of the keys
+/// within it.
+fn map_lower_bound(bound: Bound<&[u8]>) -> Bound<InternalKey> {
+    match bound {
+        Bound::Included(x) => {
+            Bound::Included(InternalKey::new(Bytes::copy_from_slice(x), u64::MAX))
+        }
+        Bound::Excluded(x) => Bound::Excluded(InternalKey::new(Bytes::copy_from_slice(x), 0)),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// Map an upper bound of user keys to a bound of internal keys, covering all versions of the keys
+/// within it.
+fn map_upper_bound(bound: Bound<&[u8]>) -> Bound<InternalKey> {
+    match bound {
+        Bound::Included(x) => Bound::Included(InternalKey::new(Bytes::copy_from_slice(x), 0)),
+        Bound::Excluded(x) => {
+            Bound::Excluded(InternalKey::new(Bytes::copy_from_slice(x), u64::MAX))
+        }
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
 impl MemTable {
-    /// Create a new mem-table.
-    pub fn create() -> Self {
+    /// Create a new mem-table without a WAL.
+    pub fn create(id: usize) -> Self {
         Self {
             map: Arc::new(SkipMap::new()),
+            range_tombstones: RwLock::new(Vec::new()),
+            wal: None,
+            id,
+            approximate_size: AtomicUsize::new(0),
         }
     }
 
-    /// Get a value by key.
-    pub fn get(&self, key: &[u8]) -> Option<Bytes> {
-        self.map.get(key).map(|e| e.value().clone())
+    /// Create a new mem-table backed by a new WAL segment at `path`.
+    pub fn create_with_wal(id: usize, path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            map: Arc::new(SkipMap::new()),
+            range_tombstones: RwLock::new(Vec::new()),
+            wal: Some(Wal::create(path)?),
+            id,
+            approximate_size: AtomicUsize::new(0),
+        })
+    }
+
+    /// Rebuild a mem-table by replaying the WAL segment at `path`.
+    pub fn recover_from_wal(id: usize, path: impl AsRef<Path>) -> Result<Self> {
+        let map = Arc::new(SkipMap::new());
+        let mut range_tombstones = Vec::new();
+        let wal = Wal::recover(path, &map, &mut range_tombstones)?;
+        let approximate_size = map
+            .iter()
+            .map(|entry| entry.key().key.len() + entry.value().1.len())
+            .chain(range_tombstones.iter().map(|t| t.start.len() + t.end.len()))
+            .sum();
+        Ok(Self {
+            map,
+            range_tombstones: RwLock::new(range_tombstones),
+            wal: Some(wal),
+            id,
+            approximate_size: AtomicUsize::new(approximate_size),
+        })
     }
 
-    /// Put a key-value pair into the mem-table.
-    pub fn put(&self, key: &[u8], value: &[u8]) {
+    /// Get the latest entry of a key visible at `read_seq`, along with its type and sequence
+    /// number. Range tombstones are not checked.
+    pub fn get(&self, key: &[u8], read_seq: u64) -> Option<(ValueType, Bytes, u64)> {
+        let lower = InternalKey::new(Bytes::copy_from_slice(key), read_seq);
         self.map
-            .insert(Bytes::copy_from_slice(key), Bytes::copy_from_slice(value));
+            .range(lower..)
+            .next()
+            .filter(|e| e.key().key == key)
+            .map(|e| {
+                let (value_type, value) = e.value().clone();
+                (value_type, value, e.key().seq)
+            })
+    }
+
+    /// Get the sequence number of the latest range tombstone visible at `read_seq` that covers
+    /// `key`, or 0 if there is none.
+    pub fn max_covering_tombstone_seq(&self, key: &[u8], read_seq: u64) -> u64 {
+        max_covering_seq(self.range_tombstones.read().iter(), key, read_seq)
+    }
+
+    /// Get the range tombstones visible at `read_seq`.
+    pub fn range_tombstones(&self, read_seq: u64) -> Vec<RangeTombstone> {
+        self.range_tombstones
+            .read()
+            .iter()
+            .filter(|t| t.seq <= read_seq)
+            .cloned()
+            .collect()
+    }
+
+    /// Put a key-value pair written at `seq` into the mem-table.
+    pub fn put(&self, key: &[u8], seq: u64, value: &[u8]) -> Result<()> {
+        self.insert(key, seq, ValueType::Put, value)
+    }
+
+    /// Put a tombstone of the key written at `seq` into the mem-table.
+    pub fn delete(&self, key: &[u8], seq: u64) -> Result<()> {
+        self.insert(key, seq, ValueType::Delete, b"")
+    }
+
+    /// Put a range tombstone of the keys in `[start, end)` written at `seq` into the mem-table.
+    pub fn delete_range(&self, start: &[u8], end: &[u8], seq: u64) -> Result<()> {
+        self.insert(start, seq, ValueType::RangeDelete, end)
+    }
+
+    /// Insert an entry into the mem-table. The write is logged to the WAL first, if any.
+    pub(crate) fn insert(
+        &self,
+        key: &[u8],
+        seq: u64,
+        value_type: ValueType,
+        value: &[u8],
+    ) -> Result<()> {
+        self.insert_batch(seq, &[(key, value_type, value)])
+    }
+
+    /// Insert several entries written at `seq` into the mem-table, in order. They are logged to
+    /// the WAL first as a single record, if there is a WAL. A later entry of the same key in the
+    /// batch replaces the earlier one. A range delete only deletes the versions before `seq`, so
+    /// it does not cover the other entries of the batch.
+    pub(crate) fn insert_batch(
+        &self,
+        seq: u64,
+        entries: &[(&[u8], ValueType, &[u8])],
+    ) -> Result<()> {
+        if let Some(ref wal) = self.wal {
+            wal.put_batch(seq, entries)?;
+        }
+        for (key, value_type, value) in entries {
+            let (key_bytes, value_bytes) =
+                (Bytes::copy_from_slice(key), Bytes::copy_from_slice(value));
+            if *value_type == ValueType::RangeDelete {
+                self.range_tombstones.write().push(RangeTombstone::new(
+                    key_bytes,
+                    value_bytes,
+                    seq,
+                ));
+            } else {
+                self.map
+                    .insert(InternalKey::new(key_bytes, seq), (*value_type, value_bytes));
+            }
+            self.approximate_size
+                .fetch_add(key.len() + value.len(), Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Fsync the WAL of the mem-table, if any.
+    pub fn sync_wal(&self) -> Result<()> {
+        if let Some(ref wal) = self.wal {
+            wal.sync()?;
+        }
+        Ok(())
     }
 
-    /// Get an iterator over a range of keys.
+    /// Get an iterator over all versions of a range of keys.
     pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> MemTableIterator {
-        let (lower, upper) = (map_bound(lower), map_bound(upper));
+        let (lower, upper) = (map_lower_bound(lower), map_upper_bound(upper));
+        let range = (lower.clone(), upper.clone());
         let mut iter = MemTableIteratorBuilder {
             map: self.map.clone(),
-            iter_builder: |map| map.range((lower, upper)),
-            item: (Bytes::from_static(&[]), Bytes::from_static(&[])),
+            iter_builder: |map| map.range(range),
+            item: (
+                InternalKey::new(Bytes::from_static(&[]), 0),
+                (ValueType::Put, Bytes::from_static(&[])),
+            ),
+            lower,
+            upper,
+            backward: false,
         }
         .build();
         let entry = iter.with_iter_mut(|iter| MemTableIterator::entry_to_item(iter.next()));
@@ -56,50 +221,182 @@ impl MemTable {
         iter
     }
 
-    /// Flush the mem-table to SSTable.
-    pub fn flush(&self, builder: &mut SsTableBuilder) -> Result<()> {
-        for entry in self.map.iter() {
-            builder.add(&entry.key()[..], &entry.value()[..]);
+    /// Flush the mem-table to SSTable. Merge operands at or below the `watermark`, which no
+    /// snapshot reads separately, are collapsed with `merge_operator`.
+    pub fn flush(
+        &self,
+        builder: &mut SsTableBuilder,
+        watermark: u64,
+        merge_operator: Option<&dyn MergeOperator>,
+    ) -> Result<()> {
+        let settled: Vec<RangeTombstone> = self.range_tombstones(watermark);
+        let mut iter = self.scan(Bound::Unbounded, Bound::Unbounded);
+        while iter.is_valid() {
+            if let Some(operator) = merge_operator {
+                if iter.value_type() == ValueType::Merge && iter.seq() <= watermark {
+                    let key = Bytes::copy_from_slice(iter.key());
+                    for (seq, value_type, value) in
+                        collapse_operands(&mut iter, operator, &settled, false)?
+                    {
+                        builder.add(&key, seq, value_type, &value);
+                    }
+                    continue;
+                }
+            }
+            builder.add(iter.key(), iter.seq(), iter.value_type(), iter.value());
+            iter.next()?;
+        }
+        for tombstone in self.range_tombstones.read().iter() {
+            builder.add_range_tombstone(tombstone.clone());
         }
         Ok(())
     }
+
+    /// Get the id of the mem-table, which is also the id of the SST it will be flushed into.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Get the number of key and value bytes written into the mem-table. Overwritten entries are
+    /// still counted, so this may overestimate the size of the SST it flushes into.
+    pub fn approximate_size(&self) -> usize {
+        self.approximate_size.load(Ordering::Relaxed)
+    }
+
+    /// Get the largest sequence number in the mem-table, or 0 if it is empty.
+    pub fn max_seq(&self) -> u64 {
+        let tombstones = self.range_tombstones.read();
+        self.map
+            .iter()
+            .map(|e| e.key().seq)
+            .chain(tombstones.iter().map(|t| t.seq))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Check if the mem-table has no entries nor range tombstones.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty() && self.range_tombstones.read().is_empty()
+    }
 }
 
-type SkipMapRangeIter<'a> =
-    crossbeam_skiplist::map::Range<'a, Bytes, (Bound<Bytes>, Bound<Bytes>), Bytes, Bytes>;
+type SkipMapRangeIter<'a> = crossbeam_skiplist::map::Range<
+    'a,
+    InternalKey,
+    (Bound<InternalKey>, Bound<InternalKey>),
+    InternalKey,
+    (ValueType, Bytes),
+>;
 
-/// An iterator over a range of `SkipMap`.
+/// An iterator over a range of `SkipMap`, in both directions.
+///
+/// The entries are taken from the front of `iter` when moving forward, and from the back when
+/// moving backward. Changing the direction restarts `iter` from the current entry.
 #[self_referencing]
 pub struct MemTableIterator {
-    map: Arc<SkipMap<Bytes, Bytes>>,
+    map: Arc<SkipMap<InternalKey, (ValueType, Bytes)>>,
     #[borrows(map)]
     #[not_covariant]
     iter: SkipMapRangeIter<'this>,
-    item: (Bytes, Bytes),
+    item: (InternalKey, (ValueType, Bytes)),
+    lower: Bound<InternalKey>,
+    upper: Bound<InternalKey>,
+    backward: bool,
 }
 
 impl MemTableIterator {
-    fn entry_to_item(entry: Option<Entry<'_, Bytes, Bytes>>) -> (Bytes, Bytes) {
+    fn entry_to_item(
+        entry: Option<Entry<'_, InternalKey, (ValueType, Bytes)>>,
+    ) -> (InternalKey, (ValueType, Bytes)) {
         entry
             .map(|x| (x.key().clone(), x.value().clone()))
-            .unwrap_or_else(|| (Bytes::from_static(&[]), Bytes::from_static(&[])))
+            .unwrap_or_else(|| {
+                (
+                    InternalKey::new(Bytes::from_static(&[]), 0),
+                    (ValueType::Put, Bytes::from_static(&[])),
+                )
+            })
+    }
+
+    /// Restart the range of the skip list to iterate over, in the given direction.
+    fn restart(&mut self, lower: Bound<InternalKey>, upper: Bound<InternalKey>, backward: bool) {
+        self.with_mut(|x| {
+            *x.iter = x.map.range((lower, upper));
+            *x.backward = backward;
+        });
+    }
+
+    /// Seek to the last entry in the range.
+    pub fn seek_to_last(&mut self) {
+        self.restart(
+            self.borrow_lower().clone(),
+            self.borrow_upper().clone(),
+            true,
+        );
+        let entry = self.with_iter_mut(|iter| MemTableIterator::entry_to_item(iter.next_back()));
+        self.with_mut(|x| *x.item = entry);
     }
 }
 
 impl StorageIterator for MemTableIterator {
     fn value(&self) -> &[u8] {
-        &self.borrow_item().1[..]
+        &self.borrow_item().1 .1[..]
+    }
+
+    fn value_type(&self) -> ValueType {
+        self.borrow_item().1 .0
     }
 
     fn key(&self) -> &[u8] {
-        &self.borrow_item().0[..]
+        &self.borrow_item().0.key[..]
+    }
+
+    fn seq(&self) -> u64 {
+        self.borrow_item().0.seq
     }
 
     fn is_valid(&self) -> bool {
-        !self.borrow_item().0.is_empty()
+        !self.borrow_item().0.key.is_empty()
     }
 
     fn next(&mut self) -> Result<()> {
+        if *self.borrow_backward() {
+            // Restart after the current entry, or from the start if before it.
+            let lower = match self.is_valid() {
+                true => Bound::Excluded(self.borrow_item().0.clone()),
+                false => self.borrow_lower().clone(),
+            };
+            self.restart(lower, self.borrow_upper().clone(), false);
+        }
+        let entry = self.with_iter_mut(|iter| MemTableIterator::entry_to_item(iter.next()));
+        self.with_mut(|x| *x.item = entry);
+        Ok(())
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        if !*self.borrow_backward() {
+            // Restart before the current entry, or from the end if past it.
+            let upper = match self.is_valid() {
+                true => Bound::Excluded(self.borrow_item().0.clone()),
+                false => self.borrow_upper().clone(),
+            };
+            self.restart(self.borrow_lower().clone(), upper, true);
+        }
+        let entry = self.with_iter_mut(|iter| MemTableIterator::entry_to_item(iter.next_back()));
+        self.with_mut(|x| *x.item = entry);
+        Ok(())
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        // Restart from the first version of `key`, unless it is before the range.
+        let target = InternalKey::new(Bytes::copy_from_slice(key), u64::MAX);
+        let lower = match self.borrow_lower() {
+            Bound::Included(lower) | Bound::Excluded(lower) if *lower >= target => {
+                self.borrow_lower().clone()
+            }
+            _ => Bound::Included(target),
+        };
+        self.restart(lower, self.borrow_upper().clone(), false);
         let entry = self.with_iter_mut(|iter| MemTableIterator::entry_to_item(iter.next()));
         self.with_mut(|x| *x.item = entry);
         Ok(())
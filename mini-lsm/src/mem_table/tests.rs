@@ -3,40 +3,69 @@ use tempfile::tempdir;
 use super::MemTable;
 use crate::iterators::StorageIterator;
 use crate::table::{SsTableBuilder, SsTableIterator};
+use crate::value_type::ValueType;
 
 #[test]
 fn test_memtable_get() {
-    let memtable = MemTable::create();
-    memtable.put(b"key1", b"value1");
-    memtable.put(b"key2", b"value2");
-    memtable.put(b"key3", b"value3");
-    assert_eq!(&memtable.get(b"key1").unwrap()[..], b"value1");
-    assert_eq!(&memtable.get(b"key2").unwrap()[..], b"value2");
-    assert_eq!(&memtable.get(b"key3").unwrap()[..], b"value3");
+    let memtable = MemTable::create(0);
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    memtable.put(b"key2", 2, b"value2").unwrap();
+    memtable.put(b"key3", 3, b"value3").unwrap();
+    assert_eq!(&memtable.get(b"key1", u64::MAX).unwrap().1[..], b"value1");
+    assert_eq!(&memtable.get(b"key2", u64::MAX).unwrap().1[..], b"value2");
+    assert_eq!(&memtable.get(b"key3", u64::MAX).unwrap().1[..], b"value3");
 }
 
 #[test]
 fn test_memtable_overwrite() {
-    let memtable = MemTable::create();
-    memtable.put(b"key1", b"value1");
-    memtable.put(b"key2", b"value2");
-    memtable.put(b"key3", b"value3");
-    memtable.put(b"key1", b"value11");
-    memtable.put(b"key2", b"value22");
-    memtable.put(b"key3", b"value33");
-    assert_eq!(&memtable.get(b"key1").unwrap()[..], b"value11");
-    assert_eq!(&memtable.get(b"key2").unwrap()[..], b"value22");
-    assert_eq!(&memtable.get(b"key3").unwrap()[..], b"value33");
+    let memtable = MemTable::create(0);
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    memtable.put(b"key2", 2, b"value2").unwrap();
+    memtable.put(b"key3", 3, b"value3").unwrap();
+    memtable.put(b"key1", 4, b"value11").unwrap();
+    memtable.put(b"key2", 5, b"value22").unwrap();
+    memtable.put(b"key3", 6, b"value33").unwrap();
+    assert_eq!(&memtable.get(b"key1", u64::MAX).unwrap().1[..], b"value11");
+    assert_eq!(&memtable.get(b"key2", u64::MAX).unwrap().1[..], b"value22");
+    assert_eq!(&memtable.get(b"key3", u64::MAX).unwrap().1[..], b"value33");
+}
+
+#[test]
+fn test_memtable_get_versions() {
+    let memtable = MemTable::create(0);
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    memtable.delete(b"key1", 3).unwrap();
+    memtable.put(b"key1", 5, b"value11").unwrap();
+    memtable.put(b"key2", 2, b"value2").unwrap();
+    assert!(memtable.get(b"key1", 0).is_none());
+    assert_eq!(&memtable.get(b"key1", 2).unwrap().1[..], b"value1");
+    assert_eq!(memtable.get(b"key1", 4).unwrap().0, ValueType::Delete);
+    assert_eq!(&memtable.get(b"key1", 5).unwrap().1[..], b"value11");
+    assert!(memtable.get(b"key2", 1).is_none());
+    assert!(memtable.get(b"key3", u64::MAX).is_none());
+    assert_eq!(memtable.max_seq(), 5);
+
+    // All versions are scanned, from the latest to the earliest.
+    let mut iter = memtable.scan(
+        std::ops::Bound::Included(b"key1"),
+        std::ops::Bound::Excluded(b"key2"),
+    );
+    for seq in [5, 3, 1] {
+        assert_eq!(iter.key(), b"key1");
+        assert_eq!(iter.seq(), seq);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
 }
 
 #[test]
 fn test_memtable_flush() {
-    let memtable = MemTable::create();
-    memtable.put(b"key1", b"value1");
-    memtable.put(b"key2", b"value2");
-    memtable.put(b"key3", b"value3");
+    let memtable = MemTable::create(0);
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    memtable.put(b"key2", 2, b"value2").unwrap();
+    memtable.put(b"key3", 3, b"value3").unwrap();
     let mut builder = SsTableBuilder::new(128);
-    memtable.flush(&mut builder).unwrap();
+    memtable.flush(&mut builder, 0, None).unwrap();
     let dir = tempdir().unwrap();
     let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
     let mut iter = SsTableIterator::create_and_seek_to_first(sst.into()).unwrap();
@@ -55,10 +84,10 @@ fn test_memtable_flush() {
 #[test]
 fn test_memtable_iter() {
     use std::ops::Bound;
-    let memtable = MemTable::create();
-    memtable.put(b"key1", b"value1");
-    memtable.put(b"key2", b"value2");
-    memtable.put(b"key3", b"value3");
+    let memtable = MemTable::create(0);
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    memtable.put(b"key2", 2, b"value2").unwrap();
+    memtable.put(b"key3", 3, b"value3").unwrap();
 
     {
         let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
@@ -93,3 +122,69 @@ fn test_memtable_iter() {
         assert!(!iter.is_valid());
     }
 }
+
+#[test]
+fn test_memtable_approximate_size() {
+    let memtable = MemTable::create(0);
+    assert_eq!(memtable.approximate_size(), 0);
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    assert_eq!(memtable.approximate_size(), 10);
+    memtable.put(b"key1", 2, b"value11").unwrap();
+    memtable.put(b"key2", 3, b"").unwrap();
+    assert_eq!(memtable.approximate_size(), 25);
+}
+
+#[test]
+fn test_memtable_iter_backward() {
+    use std::ops::Bound;
+    let memtable = MemTable::create(0);
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    memtable.put(b"key2", 2, b"value2").unwrap();
+    memtable.put(b"key2", 4, b"value2.4").unwrap();
+    memtable.put(b"key3", 3, b"value3").unwrap();
+
+    let mut iter = memtable.scan(Bound::Excluded(b"key1"), Bound::Unbounded);
+    iter.seek_to_last();
+    assert_eq!(iter.key(), b"key3");
+    iter.prev().unwrap();
+    // Moving backward, the versions of a key come from the earliest to the latest.
+    assert_eq!((iter.key(), iter.seq()), (&b"key2"[..], 2));
+    iter.prev().unwrap();
+    assert_eq!((iter.key(), iter.seq()), (&b"key2"[..], 4));
+    iter.next().unwrap();
+    assert_eq!((iter.key(), iter.seq()), (&b"key2"[..], 2));
+    iter.prev().unwrap();
+    iter.prev().unwrap();
+    assert!(!iter.is_valid());
+    iter.next().unwrap();
+    assert_eq!((iter.key(), iter.seq()), (&b"key2"[..], 4));
+}
+
+#[test]
+fn test_memtable_iter_seek() {
+    use std::ops::Bound;
+    let memtable = MemTable::create(0);
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    memtable.put(b"key2", 2, b"value2").unwrap();
+    memtable.put(b"key2", 4, b"value2.4").unwrap();
+    memtable.put(b"key3", 3, b"value3").unwrap();
+    memtable.put(b"key4", 5, b"value4").unwrap();
+
+    let mut iter = memtable.scan(Bound::Excluded(b"key1"), Bound::Included(b"key3"));
+    iter.seek(b"key3").unwrap();
+    assert_eq!(iter.key(), b"key3");
+    // The iterator seeks to the latest version of a key.
+    iter.seek(b"key2").unwrap();
+    assert_eq!((iter.key(), iter.seq()), (&b"key2"[..], 4));
+    // Seeking before the range goes to its first entry, and after it past the end.
+    iter.seek(b"key0").unwrap();
+    assert_eq!((iter.key(), iter.seq()), (&b"key2"[..], 4));
+    iter.seek(b"key4").unwrap();
+    assert!(!iter.is_valid());
+    iter.prev().unwrap();
+    assert_eq!(iter.key(), b"key3");
+    iter.seek(b"key21").unwrap();
+    assert_eq!(iter.key(), b"key3");
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+}
@@ -0,0 +1,132 @@
+use std::fmt;
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes};
+
+use crate::iterators::StorageIterator;
+use crate::range_tombstone::RangeTombstone;
+use crate::value_type::ValueType;
+
+/// Combines the merge operands written by [`crate::lsm_storage::LsmStorage::merge`] with the value
+/// they apply to, so that a read-modify-write does not need to read the value first.
+pub trait MergeOperator: Send + Sync {
+    /// Fold `operands`, from the earliest to the latest, into the existing value of `key`, which
+    /// is `None` if the key is absent or deleted.
+    fn full_merge(&self, key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Bytes;
+
+    /// Fold consecutive `operands`, from the earliest to the latest, into a single operand, or
+    /// return `None` if they cannot be combined without the existing value.
+    fn partial_merge(&self, _key: &[u8], _operands: &[&[u8]]) -> Option<Bytes> {
+        None
+    }
+}
+
+impl fmt::Debug for dyn MergeOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MergeOperator")
+    }
+}
+
+/// Adds up counters stored as big-endian `u64`s, wrapping on overflow. A missing value or one of
+/// another size counts as 0.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct U64AddOperator;
+
+impl U64AddOperator {
+    fn sum<'a>(values: impl IntoIterator<Item = &'a [u8]>) -> Bytes {
+        let sum = values
+            .into_iter()
+            .filter_map(|value| value.try_into().ok().map(u64::from_be_bytes))
+            .fold(0u64, u64::wrapping_add);
+        let mut buf = Vec::with_capacity(8);
+        buf.put_u64(sum);
+        buf.into()
+    }
+}
+
+impl MergeOperator for U64AddOperator {
+    fn full_merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Bytes {
+        Self::sum(existing.into_iter().chain(operands.iter().copied()))
+    }
+
+    fn partial_merge(&self, _key: &[u8], operands: &[&[u8]]) -> Option<Bytes> {
+        Some(Self::sum(operands.iter().copied()))
+    }
+}
+
+/// Appends the operands to the value, making an append-only list.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AppendOperator;
+
+impl MergeOperator for AppendOperator {
+    fn full_merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Bytes {
+        existing
+            .into_iter()
+            .chain(operands.iter().copied())
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    fn partial_merge(&self, _key: &[u8], operands: &[&[u8]]) -> Option<Bytes> {
+        Some(operands.concat().into())
+    }
+}
+
+/// Collapse the versions of the key at `iter`, whose current entry is the latest merge operand
+/// to collapse, and move past the key. Versions under a tombstone or a put are folded into the
+/// operands and dropped, so the caller must make sure that no snapshot reads them.
+///
+/// Returns the entries to write instead, from the latest to the earliest: a single put if the
+/// existing value is found, or in the bottom level, where it is absent. Otherwise, the operands
+/// are combined into one by [`MergeOperator::partial_merge`], or kept as they are if they cannot
+/// be. If a range tombstone covers the latest operand itself, the key is deleted and there is no
+/// entry to write.
+pub(crate) fn collapse_operands<I: StorageIterator>(
+    iter: &mut I,
+    operator: &dyn MergeOperator,
+    range_tombstones: &[RangeTombstone],
+    is_bottom_level: bool,
+) -> Result<Vec<(u64, ValueType, Bytes)>> {
+    let key = iter.key().to_vec();
+    let latest_seq = iter.seq();
+    // The operands with their sequence numbers, from the latest to the earliest.
+    let mut operands = Vec::new();
+    let mut existing = None;
+    let mut found = is_bottom_level;
+    while iter.is_valid() && iter.key() == key {
+        let deleted = iter.value_type() == ValueType::Delete
+            || range_tombstones.iter().any(|t| t.covers(&key, iter.seq()));
+        if deleted || iter.value_type() == ValueType::Put {
+            if !deleted {
+                existing = Some(Bytes::copy_from_slice(iter.value()));
+            }
+            found = true;
+            break;
+        }
+        operands.push((iter.seq(), Bytes::copy_from_slice(iter.value())));
+        iter.next()?;
+    }
+    while iter.is_valid() && iter.key() == key {
+        iter.next()?;
+    }
+
+    if operands.is_empty() {
+        return Ok(Vec::new());
+    }
+    let folded: Vec<&[u8]> = operands.iter().rev().map(|(_, x)| &x[..]).collect();
+    if found {
+        let value = operator.full_merge(&key, existing.as_deref(), &folded);
+        return Ok(vec![(latest_seq, ValueType::Put, value)]);
+    }
+    if let Some(operand) = operator.partial_merge(&key, &folded) {
+        return Ok(vec![(latest_seq, ValueType::Merge, operand)]);
+    }
+    Ok(operands
+        .into_iter()
+        .map(|(seq, operand)| (seq, ValueType::Merge, operand))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests;
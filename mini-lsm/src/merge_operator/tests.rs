@@ -0,0 +1,121 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+
+use super::*;
+use crate::mem_table::MemTable;
+
+fn counter(value: u64) -> Vec<u8> {
+    value.to_be_bytes().to_vec()
+}
+
+#[test]
+fn test_u64_add_operator() {
+    let operator = U64AddOperator;
+    let (one, two) = (counter(1), counter(2));
+    assert_eq!(operator.full_merge(b"k", None, &[&one, &two]), counter(3));
+    assert_eq!(
+        operator.full_merge(b"k", Some(&counter(10)), &[&one]),
+        counter(11)
+    );
+    // A malformed value counts as 0.
+    assert_eq!(operator.full_merge(b"k", Some(b"x"), &[&two]), counter(2));
+    assert_eq!(
+        operator.partial_merge(b"k", &[&one, &two]).unwrap(),
+        counter(3)
+    );
+}
+
+#[test]
+fn test_append_operator() {
+    let operator = AppendOperator;
+    assert_eq!(
+        operator.full_merge(b"k", Some(b"a"), &[b"b", b"c"]),
+        Bytes::from_static(b"abc")
+    );
+    assert_eq!(
+        operator.full_merge(b"k", None, &[b"b"]),
+        Bytes::from_static(b"b")
+    );
+    assert_eq!(
+        operator.partial_merge(b"k", &[b"b", b"c"]).unwrap(),
+        Bytes::from_static(b"bc")
+    );
+}
+
+/// Appends the operands, but cannot combine them without the existing value.
+struct FullOnlyOperator;
+
+impl MergeOperator for FullOnlyOperator {
+    fn full_merge(&self, key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Bytes {
+        AppendOperator.full_merge(key, existing, operands)
+    }
+}
+
+fn collapse(
+    memtable: &MemTable,
+    operator: &dyn MergeOperator,
+    is_bottom_level: bool,
+) -> Vec<(u64, ValueType, Bytes)> {
+    let mut iter = memtable.scan(Bound::Included(b"k"), Bound::Unbounded);
+    let entries = collapse_operands(&mut iter, operator, &[], is_bottom_level).unwrap();
+    // The iterator is moved past all versions of the key.
+    assert_eq!(iter.key(), b"z");
+    entries
+}
+
+#[test]
+fn test_collapse_operands() {
+    let memtable = MemTable::create(0);
+    memtable.insert(b"k", 3, ValueType::Merge, b"c").unwrap();
+    memtable.insert(b"k", 2, ValueType::Merge, b"b").unwrap();
+    memtable.put(b"z", 1, b"z").unwrap();
+
+    // Without the existing value, the operands are combined if possible.
+    assert_eq!(
+        collapse(&memtable, &AppendOperator, false),
+        vec![(3, ValueType::Merge, Bytes::from_static(b"bc"))]
+    );
+    assert_eq!(
+        collapse(&memtable, &FullOnlyOperator, false),
+        vec![
+            (3, ValueType::Merge, Bytes::from_static(b"c")),
+            (2, ValueType::Merge, Bytes::from_static(b"b")),
+        ]
+    );
+    // In the bottom level, there is no existing value.
+    assert_eq!(
+        collapse(&memtable, &FullOnlyOperator, true),
+        vec![(3, ValueType::Put, Bytes::from_static(b"bc"))]
+    );
+
+    // The versions below a put are folded into it.
+    memtable.put(b"k", 1, b"a").unwrap();
+    memtable.insert(b"k", 0, ValueType::Merge, b"x").unwrap();
+    assert_eq!(
+        collapse(&memtable, &FullOnlyOperator, false),
+        vec![(3, ValueType::Put, Bytes::from_static(b"abc"))]
+    );
+}
+
+#[test]
+fn test_collapse_operands_under_range_tombstone() {
+    let memtable = MemTable::create(0);
+    memtable.insert(b"k", 2, ValueType::Merge, b"b").unwrap();
+    memtable.insert(b"k", 1, ValueType::Merge, b"a").unwrap();
+    memtable.put(b"z", 1, b"z").unwrap();
+    let tombstone = RangeTombstone::new(Bytes::from_static(b"a"), Bytes::from_static(b"m"), 3);
+    for is_bottom_level in [false, true] {
+        let mut iter = memtable.scan(Bound::Included(b"k"), Bound::Unbounded);
+        let entries = collapse_operands(
+            &mut iter,
+            &AppendOperator,
+            std::slice::from_ref(&tombstone),
+            is_bottom_level,
+        )
+        .unwrap();
+        // The deleted key is not brought back as an empty value.
+        assert!(entries.is_empty(), "{:?}", entries);
+        assert_eq!(iter.key(), b"z");
+    }
+}
@@ -0,0 +1,119 @@
+mod txn;
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+pub(crate) use txn::ReadSet;
+pub use txn::{IsolationLevel, Transaction, TxnConflict, TxnIterator};
+
+use crate::lsm_storage::LsmStorageCore;
+
+/// A consistent view of the storage as of the moment it was taken, created by
+/// [`crate::lsm_storage::LsmStorage::snapshot`].
+///
+/// Reads through a snapshot see every write committed before it, and none after. Compaction keeps
+/// the versions a live snapshot may read, so a snapshot should be dropped once it is done with, to
+/// let the watermark advance.
+pub struct Snapshot {
+    core: Arc<LsmStorageCore>,
+    seq: u64,
+}
+
+impl Snapshot {
+    pub(crate) fn new(core: Arc<LsmStorageCore>) -> Self {
+        let seq = {
+            // Register under the lock, so that compaction never misses a snapshot that is about
+            // to read at the latest sequence number.
+            let mut live = core.live_snapshots.0.lock();
+            let seq = core.last_seq.load(Ordering::SeqCst);
+            *live.entry(seq).or_default() += 1;
+            seq
+        };
+        Self { core, seq }
+    }
+
+    /// Get the sequence number of the last write visible to the snapshot.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let mut live = self.core.live_snapshots.0.lock();
+        if let Some(count) = live.get_mut(&self.seq) {
+            *count -= 1;
+            if *count == 0 {
+                live.remove(&self.seq);
+            }
+        }
+    }
+}
+
+/// The sequence numbers of the live snapshots, with the number of snapshots at each.
+#[derive(Default)]
+pub(crate) struct LiveSnapshots(Mutex<BTreeMap<u64, usize>>);
+
+impl LiveSnapshots {
+    /// Get the sequence number of the oldest live snapshot, or `last_seq` if none is live. No
+    /// reader needs any version older than the latest one at or below the watermark, and no
+    /// snapshot taken later starts below it.
+    pub(crate) fn watermark(&self, last_seq: &AtomicU64) -> u64 {
+        let live = self.0.lock();
+        live.keys()
+            .next()
+            .copied()
+            .unwrap_or_else(|| last_seq.load(Ordering::SeqCst))
+    }
+
+    /// Get the sequence number of the oldest live snapshot.
+    pub(crate) fn oldest(&self) -> Option<u64> {
+        self.0.lock().keys().next().copied()
+    }
+}
+
+/// Get the range `[key, key + "\0")` of exactly one key.
+pub(crate) fn key_range(key: &Bytes) -> (Bytes, Bytes) {
+    let mut end = Vec::with_capacity(key.len() + 1);
+    end.extend_from_slice(key);
+    end.push(0);
+    (key.clone(), end.into())
+}
+
+/// The key ranges written by each commit, by sequence number, kept as long as a live snapshot
+/// started before the commit. Transactions check their writes against it for conflicts.
+///
+/// A range is `[start, end)`, and a single key is recorded as the range of [`key_range`].
+#[derive(Default)]
+pub(crate) struct CommitLog(Mutex<BTreeMap<u64, Vec<(Bytes, Bytes)>>>);
+
+impl CommitLog {
+    /// Record the key ranges of the commit at `seq`, and forget the commits that no live
+    /// snapshot started before, given the oldest one.
+    pub(crate) fn record(&self, seq: u64, ranges: Vec<(Bytes, Bytes)>, oldest_snapshot: u64) {
+        let mut log = self.0.lock();
+        *log = log.split_off(&(oldest_snapshot + 1));
+        log.insert(seq, ranges);
+    }
+
+    /// Forget all commits, once no snapshot is live.
+    pub(crate) fn clear(&self) {
+        self.0.lock().clear();
+    }
+
+    /// Find a key range committed after `start_seq` that matches `pred`, and get its start.
+    pub(crate) fn find_since(
+        &self,
+        start_seq: u64,
+        mut pred: impl FnMut(&[u8], &[u8]) -> bool,
+    ) -> Option<Bytes> {
+        let log = self.0.lock();
+        log.range(start_seq + 1..)
+            .flat_map(|(_, ranges)| ranges)
+            .find(|(start, end)| pred(start, end))
+            .map(|(start, _)| start.clone())
+    }
+}
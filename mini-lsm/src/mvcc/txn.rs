@@ -0,0 +1,333 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use super::Snapshot;
+use crate::iterators::two_merge_iterator::TwoMergeIterator;
+use crate::iterators::StorageIterator;
+use crate::lsm_iterator::{FusedIterator, LsmIterator};
+use crate::lsm_storage::LsmStorageCore;
+use crate::mem_table::{map_bound, MemTable, MemTableIterator};
+use crate::value_type::ValueType;
+use crate::write_batch::WriteBatch;
+
+/// The sequence number of the entries in the write set of a transaction, which are newer than
+/// any committed version.
+const WRITE_SET_SEQ: u64 = u64::MAX;
+
+/// How much a transaction is isolated from the ones committed while it runs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IsolationLevel {
+    /// The transaction reads from a snapshot, and fails to commit if another commit wrote any key
+    /// it writes. Two transactions may still each write what the other read, known as write skew.
+    #[default]
+    Snapshot,
+    /// On top of snapshot isolation, the transaction fails to commit if another commit wrote any
+    /// key it read, or any key within a range it scanned, so that it could have run after every
+    /// transaction committed before it.
+    Serializable,
+}
+
+/// The keys and the ranges read by a serializable transaction.
+#[derive(Default)]
+pub(crate) struct ReadSet {
+    keys: BTreeSet<Bytes>,
+    ranges: Vec<(Bound<Bytes>, Bound<Bytes>)>,
+}
+
+impl ReadSet {
+    /// Check if writing the keys in `[start, end)` may change what the transaction read.
+    pub(crate) fn overlaps(&self, start: &[u8], end: &[u8]) -> bool {
+        self.keys
+            .range::<[u8], _>((Bound::Included(start), Bound::Excluded(end)))
+            .next()
+            .is_some()
+            || self.ranges.iter().any(|(lower, upper)| {
+                let above_lower = match lower {
+                    Bound::Included(key) | Bound::Excluded(key) => &key[..] < end,
+                    Bound::Unbounded => true,
+                };
+                let below_upper = match upper {
+                    Bound::Included(key) => start <= &key[..],
+                    Bound::Excluded(key) => start < &key[..],
+                    Bound::Unbounded => true,
+                };
+                above_lower && below_upper
+            })
+    }
+}
+
+/// An optimistic transaction, created by [`crate::lsm_storage::LsmStorage::new_txn`].
+///
+/// The transaction reads from a snapshot taken when it started, along with its own writes, which
+/// are buffered until [`Transaction::commit`]. Dropping the transaction without committing
+/// discards the writes.
+pub struct Transaction {
+    core: Arc<LsmStorageCore>,
+    snapshot: Snapshot,
+    /// The uncommitted writes, with the latest write to each key only.
+    write_set: MemTable,
+    /// What the transaction read, tracked in serializable mode only.
+    read_set: Option<Mutex<ReadSet>>,
+}
+
+/// The error of committing a transaction that conflicts with another commit after the
+/// transaction started, on a key it writes, or in serializable mode, a key it read. Retry the
+/// transaction to resolve it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TxnConflict {
+    pub key: Bytes,
+}
+
+impl fmt::Display for TxnConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "transaction conflicts on key {:?} written after it started",
+            self.key
+        )
+    }
+}
+
+impl std::error::Error for TxnConflict {}
+
+impl Transaction {
+    pub(crate) fn new(core: Arc<LsmStorageCore>, isolation: IsolationLevel) -> Self {
+        Self {
+            snapshot: Snapshot::new(core.clone()),
+            core,
+            write_set: MemTable::create(0),
+            read_set: match isolation {
+                IsolationLevel::Snapshot => None,
+                IsolationLevel::Serializable => Some(Mutex::default()),
+            },
+        }
+    }
+
+    /// Get the isolation level of the transaction.
+    pub fn isolation(&self) -> IsolationLevel {
+        match self.read_set {
+            Some(_) => IsolationLevel::Serializable,
+            None => IsolationLevel::Snapshot,
+        }
+    }
+
+    /// Get the sequence number of the snapshot the transaction reads from.
+    pub fn start_seq(&self) -> u64 {
+        self.snapshot.seq()
+    }
+
+    /// Get a key, as written by the transaction or as of its start.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        if let Some(ref read_set) = self.read_set {
+            read_set.lock().keys.insert(Bytes::copy_from_slice(key));
+        }
+        if let Some((value_type, value, _)) = self.write_set.get(key, WRITE_SET_SEQ) {
+            return Ok(match value_type {
+                ValueType::Put => Some(value),
+                ValueType::Delete | ValueType::RangeDelete | ValueType::Merge => None,
+            });
+        }
+        self.core.get(key, Some(self.snapshot.seq()))
+    }
+
+    /// Create an iterator over a range of keys, with the writes of the transaction on top of the
+    /// snapshot it reads from.
+    pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
+        if let Some(ref read_set) = self.read_set {
+            read_set
+                .lock()
+                .ranges
+                .push((map_bound(lower), map_bound(upper)));
+        }
+        let iter = TwoMergeIterator::create(
+            self.write_set.scan(lower, upper),
+            self.core.scan(lower, upper, Some(self.snapshot.seq()))?,
+        )?;
+        TxnIterator::new(iter)
+    }
+
+    /// Put a key-value pair in the transaction.
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        assert!(!key.is_empty(), "key cannot be empty");
+        self.write_set.put(key, WRITE_SET_SEQ, value)
+    }
+
+    /// Remove a key in the transaction.
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        assert!(!key.is_empty(), "key cannot be empty");
+        self.write_set.delete(key, WRITE_SET_SEQ)
+    }
+
+    /// Apply the writes of the transaction atomically, or fail with [`TxnConflict`] if another
+    /// commit after the transaction started wrote any of the keys, or in serializable mode, any
+    /// key the transaction read. A transaction without writes always commits.
+    pub fn commit(self) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        let mut iter = self.write_set.scan(Bound::Unbounded, Bound::Unbounded);
+        while iter.is_valid() {
+            match iter.value_type() {
+                ValueType::Put => batch.put(iter.key(), iter.value()),
+                ValueType::Delete => batch.delete(iter.key()),
+                ValueType::RangeDelete | ValueType::Merge => {
+                    unreachable!("only puts and deletes are in a write set")
+                }
+            };
+            iter.next()?;
+        }
+        let read_set = self.read_set.as_ref().map(|read_set| read_set.lock());
+        self.core
+            .commit(&batch, Some((self.snapshot.seq(), read_set.as_deref())))
+    }
+}
+
+type TxnIteratorInner = TwoMergeIterator<MemTableIterator, FusedIterator<LsmIterator>>;
+
+/// An iterator over the keys visible to a transaction, preferring its own writes and hiding the
+/// keys it deleted.
+///
+/// Moving backward, the write of the transaction comes after the committed version of a key, so
+/// both are read before the key is produced.
+pub struct TxnIterator {
+    iter: TxnIteratorInner,
+    /// The key, sequence number and value of the current entry when moving backward. The inner
+    /// iterator is then before all entries of the key.
+    current: Option<(Bytes, u64, Bytes)>,
+    backward: bool,
+}
+
+impl TxnIterator {
+    fn new(iter: TxnIteratorInner) -> Result<Self> {
+        let mut iter = Self {
+            iter,
+            current: None,
+            backward: false,
+        };
+        iter.move_to_non_delete()?;
+        Ok(iter)
+    }
+
+    /// Skip the remaining entries of the current key, i.e. the committed one overwritten by the
+    /// transaction.
+    fn skip_key(&mut self) -> Result<()> {
+        let key = self.iter.key().to_vec();
+        while self.iter.is_valid() && self.iter.key() == key {
+            self.iter.next()?;
+        }
+        Ok(())
+    }
+
+    fn move_to_non_delete(&mut self) -> Result<()> {
+        while self.iter.is_valid() && self.iter.value_type() == ValueType::Delete {
+            self.skip_key()?;
+        }
+        Ok(())
+    }
+
+    /// Move backward to the previous key that is not deleted, taking the last entry of each key.
+    fn move_to_non_delete_backward(&mut self) -> Result<()> {
+        self.current = None;
+        while self.iter.is_valid() {
+            let key = Bytes::copy_from_slice(self.iter.key());
+            let mut latest = None;
+            while self.iter.is_valid() && self.iter.key() == key {
+                latest = Some((
+                    self.iter.seq(),
+                    self.iter.value_type(),
+                    Bytes::copy_from_slice(self.iter.value()),
+                ));
+                self.iter.prev()?;
+            }
+            if let Some((seq, ValueType::Put, value)) = latest {
+                self.current = Some((key, seq, value));
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StorageIterator for TxnIterator {
+    fn is_valid(&self) -> bool {
+        self.current.is_some() || (!self.backward && self.iter.is_valid())
+    }
+
+    fn key(&self) -> &[u8] {
+        match &self.current {
+            Some((key, _, _)) => key,
+            None => self.iter.key(),
+        }
+    }
+
+    fn value(&self) -> &[u8] {
+        match &self.current {
+            Some((_, _, value)) => value,
+            None => self.iter.value(),
+        }
+    }
+
+    fn value_type(&self) -> ValueType {
+        match &self.current {
+            Some(_) => ValueType::Put,
+            None => self.iter.value_type(),
+        }
+    }
+
+    fn seq(&self) -> u64 {
+        match &self.current {
+            Some((_, seq, _)) => *seq,
+            None => self.iter.seq(),
+        }
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if self.backward {
+            // Move the inner iterator past all entries of the current key.
+            self.backward = false;
+            let key = self.current.take().map(|(key, _, _)| key);
+            if !self.iter.is_valid() {
+                self.iter.next()?;
+            }
+            if let Some(key) = key {
+                while self.iter.is_valid() && self.iter.key() <= &key[..] {
+                    self.iter.next()?;
+                }
+            }
+        } else if self.iter.is_valid() {
+            self.skip_key()?;
+        }
+        self.move_to_non_delete()
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        if !self.backward {
+            // Move the inner iterator before all entries of the current key.
+            self.backward = true;
+            let key = match self.iter.is_valid() {
+                true => Some(self.iter.key().to_vec()),
+                false => None,
+            };
+            if !self.iter.is_valid() {
+                self.iter.prev()?;
+            }
+            if let Some(key) = key {
+                while self.iter.is_valid() && self.iter.key() >= &key[..] {
+                    self.iter.prev()?;
+                }
+            }
+        }
+        self.move_to_non_delete_backward()
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.iter.seek(key)?;
+        self.current = None;
+        self.backward = false;
+        self.move_to_non_delete()
+    }
+}
@@ -0,0 +1,83 @@
+use std::fmt;
+use std::ops::Bound;
+
+/// Extracts the prefix of a key, so that SSTs can keep a bloom filter over the prefixes of their
+/// keys and prefix scans can skip the SSTs without the prefix.
+///
+/// If `extract` returns a prefix for a key, it must return the same one for every key starting
+/// with that key. This lets a scan over the keys starting with `p` probe the filter with the
+/// prefix of `p`.
+pub trait PrefixExtractor: Send + Sync {
+    /// Identify the extractor in the SSTs, so that the filter of an SST built with another one is
+    /// not used.
+    fn name(&self) -> String;
+
+    /// Get the prefix of `key`, or `None` if the key has none and is left out of the filter.
+    fn extract<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]>;
+}
+
+impl fmt::Debug for dyn PrefixExtractor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PrefixExtractor({})", self.name())
+    }
+}
+
+/// Takes the first `len` bytes of a key. Shorter keys have no prefix.
+#[derive(Clone, Copy, Debug)]
+pub struct FixedPrefixExtractor {
+    len: usize,
+}
+
+impl FixedPrefixExtractor {
+    pub fn new(len: usize) -> Self {
+        Self { len }
+    }
+}
+
+impl PrefixExtractor for FixedPrefixExtractor {
+    fn name(&self) -> String {
+        format!("fixed:{}", self.len)
+    }
+
+    fn extract<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        key.get(..self.len)
+    }
+}
+
+/// Takes a key up to and including the first `delimiter`, e.g. `tenant/` for `tenant/object/1`
+/// with `/`. Keys without the delimiter have no prefix.
+#[derive(Clone, Copy, Debug)]
+pub struct DelimiterPrefixExtractor {
+    delimiter: u8,
+}
+
+impl DelimiterPrefixExtractor {
+    pub fn new(delimiter: u8) -> Self {
+        Self { delimiter }
+    }
+}
+
+impl PrefixExtractor for DelimiterPrefixExtractor {
+    fn name(&self) -> String {
+        format!("delimiter:{}", self.delimiter)
+    }
+
+    fn extract<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        let pos = key.iter().position(|&x| x == self.delimiter)?;
+        Some(&key[..=pos])
+    }
+}
+
+/// Get the exclusive upper bound of the keys starting with `prefix`: the prefix with its last
+/// byte below `0xff` incremented and the bytes after it dropped, or no bound if there is none.
+pub fn prefix_upper_bound(prefix: &[u8]) -> Bound<Vec<u8>> {
+    let Some(pos) = prefix.iter().rposition(|&x| x != 0xff) else {
+        return Bound::Unbounded;
+    };
+    let mut upper = prefix[..=pos].to_vec();
+    upper[pos] += 1;
+    Bound::Excluded(upper)
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,31 @@
+use std::ops::Bound;
+
+use super::*;
+
+#[test]
+fn test_fixed_prefix_extractor() {
+    let extractor = FixedPrefixExtractor::new(3);
+    assert_eq!(extractor.extract(b"abcd"), Some(&b"abc"[..]));
+    assert_eq!(extractor.extract(b"abc"), Some(&b"abc"[..]));
+    assert_eq!(extractor.extract(b"ab"), None);
+}
+
+#[test]
+fn test_delimiter_prefix_extractor() {
+    let extractor = DelimiterPrefixExtractor::new(b'/');
+    assert_eq!(extractor.extract(b"tenant/object/1"), Some(&b"tenant/"[..]));
+    assert_eq!(extractor.extract(b"tenant/"), Some(&b"tenant/"[..]));
+    assert_eq!(extractor.extract(b"tenant"), None);
+    assert_ne!(extractor.name(), DelimiterPrefixExtractor::new(b':').name());
+}
+
+#[test]
+fn test_prefix_upper_bound() {
+    assert_eq!(prefix_upper_bound(b"ab"), Bound::Excluded(b"ac".to_vec()));
+    assert_eq!(
+        prefix_upper_bound(b"a\xff\xff"),
+        Bound::Excluded(b"b".to_vec())
+    );
+    assert_eq!(prefix_upper_bound(b"\xff"), Bound::Unbounded);
+    assert_eq!(prefix_upper_bound(b""), Bound::Unbounded);
+}
@@ -0,0 +1,111 @@
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::block::{SIZEOF_U16, SIZEOF_U64};
+use crate::varint::{get_varint, put_varint};
+
+/// A range tombstone deletes every version of the keys in `[start, end)` written before `seq`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RangeTombstone {
+    pub start: Bytes,
+    pub end: Bytes,
+    pub seq: u64,
+}
+
+impl RangeTombstone {
+    pub fn new(start: Bytes, end: Bytes, seq: u64) -> Self {
+        Self { start, end, seq }
+    }
+
+    /// Check if the tombstone deletes the version of `key` at `seq`.
+    pub fn covers(&self, key: &[u8], seq: u64) -> bool {
+        self.start <= key && key < self.end && seq < self.seq
+    }
+
+    /// Check if the tombstone deletes anything in `[start, end)`.
+    pub fn overlaps(&self, start: &[u8], end: &[u8]) -> bool {
+        self.start < end && start < self.end
+    }
+
+    /// Get the part of the tombstone within `[lower, upper)`, where `None` means unbounded.
+    pub fn clip(&self, lower: Option<&[u8]>, upper: Option<&[u8]>) -> Option<Self> {
+        let start = match lower {
+            Some(lower) if lower > self.start => Bytes::copy_from_slice(lower),
+            _ => self.start.clone(),
+        };
+        let end = match upper {
+            Some(upper) if upper < self.end => Bytes::copy_from_slice(upper),
+            _ => self.end.clone(),
+        };
+        (start < end).then(|| Self::new(start, end, self.seq))
+    }
+
+    /// Encode range tombstones to a buffer, each as:
+    ///
+    /// ```plaintext
+    /// | start_len (varint) | start | end_len (varint) | end | seq (u64) |
+    /// ```
+    pub fn encode(tombstones: &[RangeTombstone], buf: &mut Vec<u8>) {
+        for tombstone in tombstones {
+            put_varint(buf, tombstone.start.len() as u64);
+            buf.put_slice(&tombstone.start);
+            put_varint(buf, tombstone.end.len() as u64);
+            buf.put_slice(&tombstone.end);
+            buf.put_u64(tombstone.seq);
+        }
+    }
+
+    /// Decode range tombstones from a buffer.
+    pub fn decode(buf: &[u8]) -> Result<Vec<RangeTombstone>> {
+        Self::decode_with(buf, |buf| Ok(get_varint(buf)? as usize))
+    }
+
+    /// Decode range tombstones written in SST format version 1, where the key lengths are u16s
+    /// rather than varints.
+    pub fn decode_v1(buf: &[u8]) -> Result<Vec<RangeTombstone>> {
+        Self::decode_with(buf, |buf| {
+            if buf.remaining() < SIZEOF_U16 {
+                bail!("truncated range tombstone");
+            }
+            Ok(buf.get_u16() as usize)
+        })
+    }
+
+    fn decode_with(
+        mut buf: &[u8],
+        get_len: impl Fn(&mut &[u8]) -> Result<usize>,
+    ) -> Result<Vec<RangeTombstone>> {
+        let get_key = |buf: &mut &[u8]| -> Result<Bytes> {
+            let len = get_len(buf)?;
+            if buf.remaining() < len {
+                bail!("truncated range tombstone");
+            }
+            Ok(buf.copy_to_bytes(len))
+        };
+        let mut tombstones = Vec::new();
+        while buf.has_remaining() {
+            let start = get_key(&mut buf)?;
+            let end = get_key(&mut buf)?;
+            if buf.remaining() < SIZEOF_U64 {
+                bail!("truncated range tombstone");
+            }
+            tombstones.push(RangeTombstone::new(start, end, buf.get_u64()));
+        }
+        Ok(tombstones)
+    }
+}
+
+/// Get the sequence number of the latest tombstone visible at `read_seq` that covers `key`, or 0
+/// if there is none. A version of the key older than it is deleted.
+pub fn max_covering_seq<'a>(
+    tombstones: impl IntoIterator<Item = &'a RangeTombstone>,
+    key: &[u8],
+    read_seq: u64,
+) -> u64 {
+    tombstones
+        .into_iter()
+        .filter(|t| t.seq <= read_seq && t.start <= key && key < t.end)
+        .map(|t| t.seq)
+        .max()
+        .unwrap_or(0)
+}
@@ -1,17 +1,31 @@
+mod bloom;
 mod builder;
+mod format;
 mod iterator;
 
+use std::fmt;
 use std::fs::File;
+use std::io::Write;
+use std::ops::Bound;
 use std::path::Path;
 use std::sync::Arc;
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use bloom::{bloom_hash, Bloom};
 pub use builder::SsTableBuilder;
 use bytes::{Buf, BufMut, Bytes};
+use format::{BlockHandle, Footer, Properties};
 pub use iterator::SsTableIterator;
 
-use crate::block::Block;
+use crate::block::{Block, BlockIterator, SIZEOF_U16, SIZEOF_U32, SIZEOF_U64};
+use crate::compression::{
+    decompress_block, decompress_block_v2, CompressionType, Compressor, CompressorRegistry,
+};
 use crate::lsm_storage::BlockCache;
+use crate::prefix_extractor::PrefixExtractor;
+use crate::range_tombstone::{max_covering_seq, RangeTombstone};
+use crate::value_type::ValueType;
+use crate::varint::{get_varint, put_varint, varint_len};
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct BlockMeta {
@@ -19,37 +33,87 @@ pub struct BlockMeta {
     pub offset: usize,
     /// The first key of the data block.
     pub first_key: Bytes,
+    /// The last key of the data block.
+    pub last_key: Bytes,
 }
 
 impl BlockMeta {
-    /// Encode block meta to a buffer.
+    /// Encode block meta to a buffer, each as:
+    ///
+    /// ```plaintext
+    /// | offset (u64) | first_key_len (varint) | first_key | last_key_len (varint) | last_key |
+    /// ```
     pub fn encode_block_meta(block_meta: &[BlockMeta], buf: &mut Vec<u8>) {
         let mut estimated_size = 0;
         for meta in block_meta {
-            estimated_size += std::mem::size_of::<u32>();
-            estimated_size += std::mem::size_of::<u16>();
+            estimated_size += SIZEOF_U64;
+            estimated_size += varint_len(meta.first_key.len() as u64);
             estimated_size += meta.first_key.len();
+            estimated_size += varint_len(meta.last_key.len() as u64);
+            estimated_size += meta.last_key.len();
         }
         buf.reserve(estimated_size);
         let original_len = buf.len();
         for meta in block_meta {
-            buf.put_u32(meta.offset as u32);
-            buf.put_u16(meta.first_key.len() as u16);
+            buf.put_u64(meta.offset as u64);
+            put_varint(buf, meta.first_key.len() as u64);
             buf.put_slice(&meta.first_key);
+            put_varint(buf, meta.last_key.len() as u64);
+            buf.put_slice(&meta.last_key);
         }
         assert_eq!(estimated_size, buf.len() - original_len);
     }
 
     /// Decode block meta from a buffer.
-    pub fn decode_block_meta(mut buf: impl Buf) -> Vec<BlockMeta> {
+    pub fn decode_block_meta(mut buf: impl Buf) -> Result<Vec<BlockMeta>> {
         let mut block_meta = Vec::new();
         while buf.has_remaining() {
+            ensure!(buf.remaining() >= SIZEOF_U64, "block meta too short");
+            let offset = usize::try_from(buf.get_u64())?;
+            let first_key_len = get_varint(&mut buf)? as usize;
+            ensure!(buf.remaining() >= first_key_len, "block meta too short");
+            let first_key = buf.copy_to_bytes(first_key_len);
+            let last_key_len = get_varint(&mut buf)? as usize;
+            ensure!(buf.remaining() >= last_key_len, "block meta too short");
+            let last_key = buf.copy_to_bytes(last_key_len);
+            block_meta.push(BlockMeta {
+                offset,
+                first_key,
+                last_key,
+            });
+        }
+        Ok(block_meta)
+    }
+
+    /// Decode block meta written in SST format version 1, each as:
+    ///
+    /// ```plaintext
+    /// | offset (u32) | first_key_len (u16) | first_key | last_key_len (u16) | last_key |
+    /// ```
+    pub fn decode_block_meta_v1(mut buf: impl Buf) -> Result<Vec<BlockMeta>> {
+        let mut block_meta = Vec::new();
+        while buf.has_remaining() {
+            ensure!(
+                buf.remaining() >= SIZEOF_U32 + SIZEOF_U16,
+                "block meta too short"
+            );
             let offset = buf.get_u32() as usize;
             let first_key_len = buf.get_u16() as usize;
+            ensure!(
+                buf.remaining() >= first_key_len + SIZEOF_U16,
+                "block meta too short"
+            );
             let first_key = buf.copy_to_bytes(first_key_len);
-            block_meta.push(BlockMeta { offset, first_key });
+            let last_key_len = buf.get_u16() as usize;
+            ensure!(buf.remaining() >= last_key_len, "block meta too short");
+            let last_key = buf.copy_to_bytes(last_key_len);
+            block_meta.push(BlockMeta {
+                offset,
+                first_key,
+                last_key,
+            });
         }
-        block_meta
+        Ok(block_meta)
     }
 }
 
@@ -93,22 +157,79 @@ impl FileObject {
 
     /// Create a new file object (day 2) and write the file to the disk (day 4).
     pub fn create(path: &Path, data: Vec<u8>) -> Result<Self> {
-        std::fs::write(path, &data)?;
+        // Never truncate an existing file, which may be a live SST.
+        let mut file = File::options().write(true).create_new(true).open(path)?;
+        file.write_all(&data)?;
+        // The WAL of the flushed memtable is deleted right after this, so the SST must be durable.
+        file.sync_all()?;
         Ok(FileObject(
             File::options().read(true).write(false).open(path)?,
             data.len() as u64,
         ))
     }
 
-    pub fn open(_path: &Path) -> Result<Self> {
-        unimplemented!()
+    /// Open an existing file (day 6).
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::options().read(true).write(false).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(FileObject(file, size))
+    }
+}
+
+/// The error of reading an SST whose contents do not match their checksums, or cannot be decoded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Corruption {
+    /// What is corrupted and how.
+    pub message: String,
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "corruption: {}", self.message)
     }
 }
 
+impl std::error::Error for Corruption {}
+
+/// An SSTable, encoded as:
+///
+/// ```plaintext
+/// | data blocks | block meta | bloom filter | prefix filter | range tombstones | properties |
+/// | footer |
+/// ```
+///
+/// The fixed-size [`Footer`] locates the meta sections, and carries a magic number and a format
+/// version. Each data block and each meta section is followed by its own checksum. Data blocks
+/// may be compressed, and are followed by their compression type before the checksum, which
+/// covers the stored bytes; the block keeps a checksum of its own inside, which also checks the
+/// output of the decompression.
+///
+/// The prefix filter is a bloom filter over the prefixes of the keys, along with the name of the
+/// prefix extractor, empty if there is none:
+///
+/// ```plaintext
+/// | name len (u16) | name | bloom filter |
+/// ```
+///
+/// All versions of a key are kept in the same SSTable, from the latest to the earliest. An
+/// SSTable may have range tombstones only, without any data block.
+///
+/// The key range of the SSTable covers both the entries and the range tombstones, taking the end
+/// of a tombstone as its last key.
 pub struct SsTable {
     file: FileObject,
     block_metas: Vec<BlockMeta>,
     block_meta_offset: usize,
+    format_version: u32,
+    bloom: Bloom,
+    prefix_extractor_name: String,
+    prefix_bloom: Bloom,
+    first_key: Bytes,
+    last_key: Bytes,
+    properties: Properties,
+    /// The codec of the compressed data blocks, `None` if there is no compression.
+    compressor: Option<Arc<dyn Compressor>>,
+    range_tombstones: Vec<RangeTombstone>,
     id: usize,
     block_cache: Option<Arc<BlockCache>>,
 }
@@ -116,25 +237,119 @@ pub struct SsTable {
 impl SsTable {
     #[cfg(test)]
     pub(crate) fn open_for_test(file: FileObject) -> Result<Self> {
-        Self::open(0, None, file)
+        Self::open(0, None, &CompressorRegistry::default(), file)
     }
 
-    /// Open SSTable from a file.
-    pub fn open(id: usize, block_cache: Option<Arc<BlockCache>>, file: FileObject) -> Result<Self> {
+    /// Open SSTable from a file, with the codec of its compression in `compressors`. Fails with
+    /// [`Corruption`] if it is not an SSTable, is truncated, is in a newer format, or its meta
+    /// sections are corrupted, and with a plain error if its codec is not registered.
+    pub fn open(
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        compressors: &CompressorRegistry,
+        file: FileObject,
+    ) -> Result<Self> {
+        let corruption = |e: anyhow::Error| Corruption {
+            message: format!("SST {}: {:#}", id, e),
+        };
         let len = file.size();
-        let raw_meta_offset = file.read(len - 4, 4)?;
-        let block_meta_offset = (&raw_meta_offset[..]).get_u32() as u64;
-        let raw_meta = file.read(block_meta_offset, len - 4 - block_meta_offset)?;
-        Ok(Self {
-            file,
-            block_metas: BlockMeta::decode_block_meta(&raw_meta[..]),
-            block_meta_offset: block_meta_offset as usize,
-            id,
-            block_cache,
-        })
-    }
-
-    /// Read a block from the disk.
+        let footer_size = (Footer::SIZE as u64).min(len);
+        let raw_footer = file.read(len - footer_size, footer_size)?;
+        let footer = Footer::decode(&raw_footer).map_err(corruption)?;
+        // All meta sections are between the data blocks and the footer.
+        let block_meta_offset = footer.index.offset;
+        let meta_end = len - footer_size;
+        if block_meta_offset > meta_end {
+            return Err(corruption(anyhow!("invalid index offset")).into());
+        }
+        let raw_meta = file.read(block_meta_offset, meta_end - block_meta_offset)?;
+        let decode = || -> Result<_> {
+            let section = |handle: &BlockHandle| handle.read(&raw_meta, block_meta_offset);
+            let block_metas = match footer.version {
+                1 => BlockMeta::decode_block_meta_v1(section(&footer.index)?)?,
+                _ => BlockMeta::decode_block_meta(section(&footer.index)?)?,
+            };
+            ensure!(
+                block_metas.first().is_none_or(|meta| meta.offset == 0)
+                    && block_metas.windows(2).all(|x| x[0].offset < x[1].offset)
+                    && block_metas
+                        .last()
+                        .is_none_or(|meta| (meta.offset as u64) < block_meta_offset),
+                "invalid block offsets"
+            );
+            let bloom = Bloom::decode(section(&footer.filter)?)?;
+            let (prefix_extractor_name, prefix_bloom) =
+                Self::decode_prefix_filter(section(&footer.prefix_filter)?)?;
+            let range_tombstones = match footer.version {
+                1 => RangeTombstone::decode_v1(section(&footer.range_tombstones)?)?,
+                _ => RangeTombstone::decode(section(&footer.range_tombstones)?)?,
+            };
+            let properties = Properties::decode(section(&footer.properties)?)?;
+            let Some((first_key, last_key)) = Self::key_range(&block_metas, &range_tombstones)
+            else {
+                bail!("SSTable without blocks or range tombstones");
+            };
+            Ok(Self {
+                first_key,
+                last_key,
+                properties,
+                compressor: None,
+                range_tombstones,
+                file,
+                block_metas,
+                block_meta_offset: block_meta_offset as usize,
+                format_version: footer.version,
+                bloom,
+                prefix_extractor_name,
+                prefix_bloom,
+                id,
+                block_cache,
+            })
+        };
+        let mut table = decode().map_err(corruption)?;
+        table.compressor = compressors
+            .get(table.properties.compression)
+            .with_context(|| format!("SST {}", id))?;
+        Ok(table)
+    }
+
+    fn encode_prefix_filter(prefix_extractor_name: &str, prefix_bloom: &Bloom, buf: &mut Vec<u8>) {
+        buf.put_u16(prefix_extractor_name.len() as u16);
+        buf.put_slice(prefix_extractor_name.as_bytes());
+        prefix_bloom.encode(buf);
+    }
+
+    fn decode_prefix_filter(mut buf: &[u8]) -> Result<(String, Bloom)> {
+        if buf.len() < 2 {
+            bail!("prefix filter too short");
+        }
+        let name_len = buf.get_u16() as usize;
+        if buf.len() < name_len {
+            bail!("prefix filter too short");
+        }
+        let (name, bloom) = buf.split_at(name_len);
+        Ok((String::from_utf8(name.to_vec())?, Bloom::decode(bloom)?))
+    }
+
+    /// Get the key range of the data blocks and the range tombstones, if there is any.
+    pub(crate) fn key_range(
+        block_metas: &[BlockMeta],
+        range_tombstones: &[RangeTombstone],
+    ) -> Option<(Bytes, Bytes)> {
+        let first_keys = block_metas.first().map(|meta| &meta.first_key);
+        let last_keys = block_metas.last().map(|meta| &meta.last_key);
+        let first_key = first_keys
+            .into_iter()
+            .chain(range_tombstones.iter().map(|t| &t.start))
+            .min()?;
+        let last_key = last_keys
+            .into_iter()
+            .chain(range_tombstones.iter().map(|t| &t.end))
+            .max()?;
+        Some((first_key.clone(), last_key.clone()))
+    }
+
+    /// Read a block from the disk. Fails with [`Corruption`] if the block is corrupted.
     pub fn read_block(&self, block_idx: usize) -> Result<Arc<Block>> {
         let offset = self.block_metas[block_idx].offset;
         let offset_end = self
@@ -144,7 +359,18 @@ impl SsTable {
         let block_data = self
             .file
             .read(offset as u64, (offset_end - offset) as u64)?;
-        Ok(Arc::new(Block::decode(&block_data[..])))
+        let decode = || match self.format_version {
+            1 => Block::decode_v1(&block_data),
+            2 => Block::decode(&decompress_block_v2(
+                block_data,
+                self.compressor.as_deref(),
+            )?),
+            _ => Block::decode(&decompress_block(block_data, self.compressor.as_deref())?),
+        };
+        let block = decode().map_err(|e| Corruption {
+            message: format!("block {} of SST {}: {:#}", block_idx, self.id, e),
+        })?;
+        Ok(Arc::new(block))
     }
 
     /// Read a block from disk, with block cache.
@@ -152,24 +378,145 @@ impl SsTable {
         if let Some(ref block_cache) = self.block_cache {
             let blk = block_cache
                 .try_get_with((self.id, block_idx), || self.read_block(block_idx))
-                .map_err(|e| anyhow!("{}", e))?;
+                .map_err(|e| match e.downcast_ref::<Corruption>() {
+                    Some(corruption) => corruption.clone().into(),
+                    None => anyhow!("{}", e),
+                })?;
             Ok(blk)
         } else {
             self.read_block(block_idx)
         }
     }
 
-    /// Find the block that may contain `key`.
+    /// Find the block that may contain the latest version of `key`, which is the first block not
+    /// entirely below the key.
     pub fn find_block_idx(&self, key: &[u8]) -> usize {
         self.block_metas
-            .partition_point(|meta| meta.first_key <= key)
-            .saturating_sub(1)
+            .partition_point(|meta| meta.last_key < key)
+            .min(self.block_metas.len().saturating_sub(1))
+    }
+
+    /// Check the bloom filter for whether the SSTable may contain `key`, without reading any data
+    /// block.
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.bloom.may_contain(bloom_hash(key))
+    }
+
+    /// Check the prefix bloom filter for whether the SSTable may contain keys starting with
+    /// `prefix`. It may if the SSTable was built with another prefix extractor, or if `prefix`
+    /// has no prefix of its own.
+    pub fn may_contain_prefix(&self, extractor: &dyn PrefixExtractor, prefix: &[u8]) -> bool {
+        if self.prefix_extractor_name.is_empty() || self.prefix_extractor_name != extractor.name() {
+            return true;
+        }
+        match extractor.extract(prefix) {
+            Some(prefix) => self.prefix_bloom.may_contain(bloom_hash(prefix)),
+            None => true,
+        }
+    }
+
+    /// Look up the latest entry of exactly `key` visible at `read_seq`, along with its type and
+    /// sequence number. The key range and the bloom filter are checked before reading the data
+    /// blocks that may contain the key. Range tombstones are not checked.
+    pub fn get(&self, key: &[u8], read_seq: u64) -> Result<Option<(ValueType, Bytes, u64)>> {
+        if key < self.first_key()
+            || key > self.last_key()
+            || self.block_metas.is_empty()
+            || !self.may_contain(key)
+        {
+            return Ok(None);
+        }
+        let mut block_idx = self.find_block_idx(key);
+        let mut iter =
+            BlockIterator::create_and_seek_to_key(self.read_block_cached(block_idx)?, key);
+        loop {
+            if !iter.is_valid() {
+                // The versions of the key may continue in the next block.
+                block_idx += 1;
+                if block_idx >= self.num_of_blocks() {
+                    return Ok(None);
+                }
+                iter = BlockIterator::create_and_seek_to_first(self.read_block_cached(block_idx)?);
+            }
+            if iter.key() != key {
+                return Ok(None);
+            }
+            if iter.seq() <= read_seq {
+                return Ok(Some((
+                    iter.value_type(),
+                    Bytes::copy_from_slice(iter.value()),
+                    iter.seq(),
+                )));
+            }
+            iter.next();
+        }
     }
 
     /// Get number of data blocks.
     pub fn num_of_blocks(&self) -> usize {
         self.block_metas.len()
     }
+
+    /// Get the smallest key of the SSTable.
+    pub fn first_key(&self) -> &[u8] {
+        &self.first_key
+    }
+
+    /// Get the largest key of the SSTable.
+    pub fn last_key(&self) -> &[u8] {
+        &self.last_key
+    }
+
+    /// Check if the SSTable may have keys within the bounds, by its key range.
+    pub fn range_overlap(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> bool {
+        let below_lower = match lower {
+            Bound::Included(key) => self.last_key() < key,
+            Bound::Excluded(key) => self.last_key() <= key,
+            Bound::Unbounded => false,
+        };
+        let above_upper = match upper {
+            Bound::Included(key) => self.first_key() > key,
+            Bound::Excluded(key) => self.first_key() >= key,
+            Bound::Unbounded => false,
+        };
+        !below_lower && !above_upper
+    }
+
+    /// Get the range tombstones of the SSTable.
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
+    }
+
+    /// Get the sequence number of the latest range tombstone visible at `read_seq` that covers
+    /// `key`, or 0 if there is none.
+    pub fn max_covering_tombstone_seq(&self, key: &[u8], read_seq: u64) -> u64 {
+        max_covering_seq(&self.range_tombstones, key, read_seq)
+    }
+
+    /// Get the largest sequence number in the SSTable.
+    pub fn max_seq(&self) -> u64 {
+        self.properties.max_seq
+    }
+
+    /// Get the compression of the data blocks.
+    pub fn compression(&self) -> CompressionType {
+        self.properties.compression
+    }
+
+    /// Get the number of entries in the SSTable, counting each version of a key.
+    pub fn num_entries(&self) -> u64 {
+        self.properties.num_entries
+    }
+
+    /// Get the size of the SSTable file in bytes.
+    pub fn table_size(&self) -> u64 {
+        self.file.size()
+    }
+
+    /// Get the id of the SSTable.
+    pub fn sst_id(&self) -> usize {
+        self.id
+    }
 }
 
 #[cfg(test)]
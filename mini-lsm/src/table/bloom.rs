@@ -0,0 +1,107 @@
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, Bytes};
+
+/// A bloom filter over the keys of an SSTable, probed with double hashing as in LevelDB.
+///
+/// The filter is encoded as:
+///
+/// ```plaintext
+/// | bit array | number of probes (u8) |
+/// ```
+///
+/// An empty bit array may contain any key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bloom {
+    filter: Bytes,
+    k: u8,
+}
+
+/// Hash a key for the bloom filter, with the hash function of LevelDB.
+pub fn bloom_hash(key: &[u8]) -> u32 {
+    const SEED: u32 = 0xbc9f1d34;
+    const M: u32 = 0xc6a4a793;
+    let mut h = SEED ^ (key.len() as u32).wrapping_mul(M);
+    let mut chunks = key.chunks_exact(4);
+    for chunk in &mut chunks {
+        h = h.wrapping_add(u32::from_le_bytes(chunk.try_into().unwrap()));
+        h = h.wrapping_mul(M);
+        h ^= h >> 16;
+    }
+    let rest = chunks.remainder();
+    if !rest.is_empty() {
+        for (idx, &byte) in rest.iter().enumerate() {
+            h = h.wrapping_add((byte as u32) << (8 * idx));
+        }
+        h = h.wrapping_mul(M);
+        h ^= h >> 24;
+    }
+    h
+}
+
+impl Bloom {
+    /// Build a filter from the hashes of the keys, using about `bits_per_key` bits for each key.
+    /// No filter is built if `bits_per_key` is 0.
+    pub fn build_from_key_hashes(key_hashes: &[u32], bits_per_key: usize) -> Self {
+        if bits_per_key == 0 || key_hashes.is_empty() {
+            return Self {
+                filter: Bytes::new(),
+                k: 0,
+            };
+        }
+        // 0.69 is about ln(2), which minimizes the false positive rate.
+        let k = ((bits_per_key as f64 * 0.69) as u8).clamp(1, 30);
+        let nbits = (key_hashes.len() * bits_per_key).max(64);
+        let nbytes = nbits.div_ceil(8);
+        let nbits = nbytes * 8;
+        let mut filter = vec![0u8; nbytes];
+        for &h in key_hashes {
+            let mut h = h;
+            let delta = h.rotate_left(15);
+            for _ in 0..k {
+                let bit = h as usize % nbits;
+                filter[bit / 8] |= 1 << (bit % 8);
+                h = h.wrapping_add(delta);
+            }
+        }
+        Self {
+            filter: filter.into(),
+            k,
+        }
+    }
+
+    /// Check if the filter may contain the key with this hash.
+    pub fn may_contain(&self, h: u32) -> bool {
+        if self.filter.is_empty() {
+            return true;
+        }
+        let nbits = self.filter.len() * 8;
+        let mut h = h;
+        let delta = h.rotate_left(15);
+        for _ in 0..self.k {
+            let bit = h as usize % nbits;
+            if self.filter[bit / 8] & (1 << (bit % 8)) == 0 {
+                return false;
+            }
+            h = h.wrapping_add(delta);
+        }
+        true
+    }
+
+    /// Encode the filter to a buffer.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_slice(&self.filter);
+        buf.put_u8(self.k);
+    }
+
+    /// Decode the filter from a buffer.
+    pub fn decode(buf: &[u8]) -> Result<Self> {
+        if buf.is_empty() {
+            bail!("empty bloom filter");
+        }
+        let (filter, mut k) = buf.split_at(buf.len() - 1);
+        Ok(Self {
+            filter: Bytes::copy_from_slice(filter),
+            k: k.get_u8(),
+        })
+    }
+}
@@ -1,20 +1,36 @@
 use std::path::Path;
 use std::sync::Arc;
 
-use anyhow::Result;
-use bytes::BufMut;
-
+use super::bloom::{bloom_hash, Bloom};
+use super::format::{BlockHandle, Footer, Properties, FORMAT_VERSION};
 use super::{BlockMeta, FileObject, SsTable};
 use crate::block::BlockBuilder;
+use crate::compression::{compress_block, CompressionType, Compressor};
 use crate::lsm_storage::BlockCache;
+use crate::prefix_extractor::PrefixExtractor;
+use crate::range_tombstone::RangeTombstone;
+use crate::value_type::ValueType;
+use anyhow::Result;
 
 /// Builds an SSTable from key-value pairs.
 pub struct SsTableBuilder {
     builder: BlockBuilder,
     first_key: Vec<u8>,
+    last_key: Vec<u8>,
     data: Vec<u8>,
     pub(super) meta: Vec<BlockMeta>,
     block_size: usize,
+    restart_interval: usize,
+    key_hashes: Vec<u32>,
+    bloom_bits_per_key: usize,
+    prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    /// The hashes of the distinct prefixes of the keys, for the prefix bloom filter.
+    prefix_hashes: Vec<u32>,
+    last_prefix: Option<Vec<u8>>,
+    max_seq: u64,
+    num_entries: u64,
+    compressor: Option<Arc<dyn Compressor>>,
+    range_tombstones: Vec<RangeTombstone>,
 }
 
 impl SsTableBuilder {
@@ -24,26 +40,89 @@ impl SsTableBuilder {
             data: Vec::new(),
             meta: Vec::new(),
             first_key: Vec::new(),
+            last_key: Vec::new(),
             block_size,
+            restart_interval: 16,
             builder: BlockBuilder::new(block_size),
+            key_hashes: Vec::new(),
+            bloom_bits_per_key: 10,
+            prefix_extractor: None,
+            prefix_hashes: Vec::new(),
+            last_prefix: None,
+            max_seq: 0,
+            num_entries: 0,
+            compressor: None,
+            range_tombstones: Vec::new(),
         }
     }
 
-    /// Adds a key-value pair to SSTable
-    pub fn add(&mut self, key: &[u8], value: &[u8]) {
+    /// Set the number of bits for each key in the bloom filter, 10 by default. More bits mean
+    /// fewer false positives; 0 disables the filter.
+    pub fn with_bloom_bits_per_key(mut self, bits_per_key: usize) -> Self {
+        self.bloom_bits_per_key = bits_per_key;
+        self
+    }
+
+    /// Set the number of entries from one restart point to the next in each block, 16 by default.
+    /// See [`BlockBuilder::with_restart_interval`].
+    pub fn with_restart_interval(mut self, restart_interval: usize) -> Self {
+        self.restart_interval = restart_interval;
+        self.builder = BlockBuilder::new(self.block_size).with_restart_interval(restart_interval);
+        self
+    }
+
+    /// Also build a bloom filter over the prefixes of the keys taken by `extractor`, with the same
+    /// number of bits for each prefix as for each key.
+    pub fn with_prefix_extractor(mut self, extractor: Arc<dyn PrefixExtractor>) -> Self {
+        self.prefix_extractor = Some(extractor);
+        self
+    }
+
+    /// Compress the data blocks with `compressor`, none by default. A block that barely
+    /// compresses is stored as it is.
+    pub fn with_compressor(mut self, compressor: Arc<dyn Compressor>) -> Self {
+        self.compressor = Some(compressor);
+        self
+    }
+
+    /// Adds an entry to SSTable. Entries must be added in the order of internal keys, that is, by
+    /// key and then from the latest version to the earliest.
+    pub fn add(&mut self, key: &[u8], seq: u64, value_type: ValueType, value: &[u8]) {
         if self.first_key.is_empty() {
             self.first_key = key.to_vec();
         }
+        if self.last_key != key {
+            self.key_hashes.push(bloom_hash(key));
+            if let Some(prefix) = self.prefix_extractor.as_ref().and_then(|x| x.extract(key)) {
+                if self.last_prefix.as_deref() != Some(prefix) {
+                    self.prefix_hashes.push(bloom_hash(prefix));
+                    self.last_prefix = Some(prefix.to_vec());
+                }
+            }
+        }
+        self.max_seq = self.max_seq.max(seq);
+        self.num_entries += 1;
+
+        if !self.builder.add(key, seq, value_type, value) {
+            // create a new block builder and append block data
+            self.finish_block();
 
-        if self.builder.add(key, value) {
-            return;
+            // add the key-value pair to the next block
+            assert!(self.builder.add(key, seq, value_type, value));
+            self.first_key = key.to_vec();
         }
-        // create a new block builder and append block data
-        self.finish_block();
+        self.last_key = key.to_vec();
+    }
+
+    /// Adds a range tombstone to the SSTable, which may cover keys outside of the added entries.
+    pub fn add_range_tombstone(&mut self, tombstone: RangeTombstone) {
+        self.max_seq = self.max_seq.max(tombstone.seq);
+        self.range_tombstones.push(tombstone);
+    }
 
-        // add the key-value pair to the next block
-        assert!(self.builder.add(key, value));
-        self.first_key = key.to_vec();
+    /// Check if nothing has been added to the SSTable.
+    pub fn is_empty(&self) -> bool {
+        self.meta.is_empty() && self.builder.is_empty() && self.range_tombstones.is_empty()
     }
 
     /// Get the estimated size of the SSTable.
@@ -52,13 +131,17 @@ impl SsTableBuilder {
     }
 
     fn finish_block(&mut self) {
-        let builder = std::mem::replace(&mut self.builder, BlockBuilder::new(self.block_size));
+        let builder = std::mem::replace(
+            &mut self.builder,
+            BlockBuilder::new(self.block_size).with_restart_interval(self.restart_interval),
+        );
         let encoded_block = builder.build().encode();
         self.meta.push(BlockMeta {
             offset: self.data.len(),
             first_key: std::mem::take(&mut self.first_key).into(),
+            last_key: std::mem::take(&mut self.last_key).into(),
         });
-        self.data.extend(encoded_block);
+        compress_block(&encoded_block, self.compressor.as_deref(), &mut self.data);
     }
 
     /// Builds the SSTable and writes it to the given path. No need to actually write to disk until
@@ -69,17 +152,63 @@ impl SsTableBuilder {
         block_cache: Option<Arc<BlockCache>>,
         path: impl AsRef<Path>,
     ) -> Result<SsTable> {
-        self.finish_block();
+        if !self.builder.is_empty() {
+            self.finish_block();
+        }
+        let (first_key, last_key) = SsTable::key_range(&self.meta, &self.range_tombstones)
+            .expect("building an empty SSTable");
         let mut buf = self.data;
         let meta_offset = buf.len();
-        BlockMeta::encode_block_meta(&self.meta, &mut buf);
-        buf.put_u32(meta_offset as u32);
+        let index = BlockHandle::write(&mut buf, |buf| {
+            BlockMeta::encode_block_meta(&self.meta, buf)
+        });
+        let bloom = Bloom::build_from_key_hashes(&self.key_hashes, self.bloom_bits_per_key);
+        let filter = BlockHandle::write(&mut buf, |buf| bloom.encode(buf));
+        let prefix_extractor_name = self
+            .prefix_extractor
+            .as_ref()
+            .map_or_else(String::new, |x| x.name());
+        let prefix_bloom =
+            Bloom::build_from_key_hashes(&self.prefix_hashes, self.bloom_bits_per_key);
+        let prefix_filter = BlockHandle::write(&mut buf, |buf| {
+            SsTable::encode_prefix_filter(&prefix_extractor_name, &prefix_bloom, buf)
+        });
+        let range_tombstones = BlockHandle::write(&mut buf, |buf| {
+            RangeTombstone::encode(&self.range_tombstones, buf)
+        });
+        let properties = Properties {
+            max_seq: self.max_seq,
+            num_entries: self.num_entries,
+            compression: self
+                .compressor
+                .as_ref()
+                .map_or(CompressionType::NONE, |x| x.compression_type()),
+        };
+        let properties_handle = BlockHandle::write(&mut buf, |buf| properties.encode(buf));
+        Footer {
+            index,
+            filter,
+            prefix_filter,
+            range_tombstones,
+            properties: properties_handle,
+            version: FORMAT_VERSION,
+        }
+        .encode(&mut buf);
         let file = FileObject::create(path.as_ref(), buf)?;
         Ok(SsTable {
             id,
             file,
+            first_key,
+            last_key,
+            properties,
+            range_tombstones: self.range_tombstones,
             block_metas: self.meta,
             block_meta_offset: meta_offset,
+            format_version: FORMAT_VERSION,
+            bloom,
+            prefix_extractor_name,
+            prefix_bloom,
+            compressor: self.compressor,
             block_cache,
         })
     }
@@ -0,0 +1,251 @@
+use anyhow::{anyhow, bail, ensure, Result};
+use bytes::{Buf, BufMut};
+
+use crate::block::{SIZEOF_U16, SIZEOF_U32};
+use crate::compression::CompressionType;
+
+/// The magic number at the end of every SSTable, "MINILSM" followed by a zero byte.
+pub const MAGIC: u64 = 0x4d49_4e49_4c53_4d00;
+
+/// The latest format version, written by [`super::SsTableBuilder`]. Readers accept any version up
+/// to this one:
+///
+/// - 1: the lengths in the blocks, the block meta and the range tombstones are u16s, the block
+///   offsets are u32s, and blocks are stored as they are.
+/// - 2: these lengths are varints, the block offsets are u64s, and each block is followed by its
+///   compression type.
+/// - 3: each block is also followed by a checksum of the stored bytes, see
+///   [`crate::compression::compress_block`].
+pub const FORMAT_VERSION: u32 = 3;
+
+/// The location of a meta section in an SSTable, encoded as `| offset (u64) | len (u64) |`. The
+/// section is followed by the checksum of its contents, which is not counted in `len`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BlockHandle {
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl BlockHandle {
+    const SIZE: usize = 16;
+
+    /// Append a section encoded by `f` and its checksum to `buf`, returning its handle.
+    pub fn write(buf: &mut Vec<u8>, f: impl FnOnce(&mut Vec<u8>)) -> Self {
+        let offset = buf.len();
+        f(buf);
+        let checksum = crc32fast::hash(&buf[offset..]);
+        let handle = Self {
+            offset: offset as u64,
+            len: (buf.len() - offset) as u64,
+        };
+        buf.put_u32(checksum);
+        handle
+    }
+
+    /// Get the contents of the section from `buf`, which starts at `base` in the file, and verify
+    /// its checksum.
+    pub fn read<'a>(&self, buf: &'a [u8], base: u64) -> Result<&'a [u8]> {
+        let start = self
+            .offset
+            .checked_sub(base)
+            .filter(|&start| start <= buf.len() as u64)
+            .ok_or_else(|| anyhow!("section out of bounds"))? as usize;
+        ensure!(
+            self.len + SIZEOF_U32 as u64 <= (buf.len() - start) as u64,
+            "section out of bounds"
+        );
+        let (data, mut checksum) = buf[start..].split_at(self.len as usize);
+        ensure!(
+            crc32fast::hash(data) == checksum.get_u32(),
+            "section checksum mismatch"
+        );
+        Ok(data)
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u64(self.offset);
+        buf.put_u64(self.len);
+    }
+
+    fn decode(buf: &mut &[u8]) -> Self {
+        Self {
+            offset: buf.get_u64(),
+            len: buf.get_u64(),
+        }
+    }
+}
+
+/// The fixed-size end of an SSTable, which locates its meta sections:
+///
+/// ```plaintext
+/// | index | filter | prefix filter | range tombstones | properties | format version (u32) |
+/// | checksum (u32) | magic (u64) |
+/// ```
+///
+/// where each section is a [`BlockHandle`], and the checksum covers the handles and the version.
+///
+/// The magic number tells an SSTable from any other file, and the version tells which format its
+/// sections are in. A change that older readers can safely ignore, such as a new property, keeps
+/// the version, and any other change bumps it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Footer {
+    /// The block meta of the data blocks.
+    pub index: BlockHandle,
+    /// The bloom filter over the keys.
+    pub filter: BlockHandle,
+    /// The name of the prefix extractor and the bloom filter over the prefixes of the keys. Never
+    /// empty: an SSTable built without an extractor has an empty name and a filter without bits.
+    pub prefix_filter: BlockHandle,
+    /// The range tombstones, see [`crate::range_tombstone::RangeTombstone::encode`]. An empty
+    /// section means there are none.
+    pub range_tombstones: BlockHandle,
+    /// The [`Properties`]. Never empty, as some properties are required.
+    pub properties: BlockHandle,
+    pub version: u32,
+}
+
+impl Footer {
+    /// The size of the encoded footer.
+    pub const SIZE: usize = 5 * BlockHandle::SIZE + 2 * SIZEOF_U32 + 8;
+
+    fn handles(&self) -> [&BlockHandle; 5] {
+        [
+            &self.index,
+            &self.filter,
+            &self.prefix_filter,
+            &self.range_tombstones,
+            &self.properties,
+        ]
+    }
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        let offset = buf.len();
+        for handle in self.handles() {
+            handle.encode(buf);
+        }
+        buf.put_u32(self.version);
+        let checksum = crc32fast::hash(&buf[offset..]);
+        buf.put_u32(checksum);
+        buf.put_u64(MAGIC);
+    }
+
+    /// Decode the footer from the end of a file. Fails if the file is not an SSTable, or is in a
+    /// newer format.
+    pub fn decode(buf: &[u8]) -> Result<Self> {
+        ensure!(
+            buf.len() == Self::SIZE,
+            "file too short for the footer, truncated or not an SST"
+        );
+        let (data, mut rest) = buf.split_at(Self::SIZE - SIZEOF_U32 - 8);
+        let checksum = rest.get_u32();
+        let magic = rest.get_u64();
+        ensure!(
+            magic == MAGIC,
+            "bad magic number {:#018x}, truncated or not an SST",
+            magic
+        );
+        ensure!(
+            crc32fast::hash(data) == checksum,
+            "footer checksum mismatch"
+        );
+        let mut data = data;
+        let [index, filter, prefix_filter, range_tombstones, properties] =
+            [(); 5].map(|_| BlockHandle::decode(&mut data));
+        let version = data.get_u32();
+        if version == 0 || version > FORMAT_VERSION {
+            bail!(
+                "unsupported format version {} (supports up to {})",
+                version,
+                FORMAT_VERSION
+            );
+        }
+        Ok(Self {
+            index,
+            filter,
+            prefix_filter,
+            range_tombstones,
+            properties,
+            version,
+        })
+    }
+}
+
+/// The properties of an SSTable, encoded as a list of named values:
+///
+/// ```plaintext
+/// | name len (u16) | name | value len (u32) | value | ... |
+/// ```
+///
+/// Unknown names are skipped, so that new properties can be added without a new format version.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Properties {
+    /// The largest sequence number of the entries and the range tombstones.
+    pub max_seq: u64,
+    /// The number of entries, counting each version of a key.
+    pub num_entries: u64,
+    /// The compression of the data blocks, which may still store some of them as they are. Not
+    /// recorded before format version 2, where there is no compression.
+    pub compression: CompressionType,
+}
+
+impl Properties {
+    const MAX_SEQ: &'static str = "max_seq";
+    const NUM_ENTRIES: &'static str = "num_entries";
+    const COMPRESSION: &'static str = "compression";
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        let mut put = |name: &str, value: &[u8]| {
+            buf.put_u16(name.len() as u16);
+            buf.put_slice(name.as_bytes());
+            buf.put_u32(value.len() as u32);
+            buf.put_slice(value);
+        };
+        put(Self::MAX_SEQ, &self.max_seq.to_be_bytes());
+        put(Self::NUM_ENTRIES, &self.num_entries.to_be_bytes());
+        put(Self::COMPRESSION, &[self.compression.0]);
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Result<Self> {
+        let mut max_seq = None;
+        let mut num_entries = None;
+        let mut compression = CompressionType::NONE;
+        while buf.has_remaining() {
+            ensure!(buf.remaining() >= SIZEOF_U16, "truncated property");
+            let name_len = buf.get_u16() as usize;
+            ensure!(
+                buf.remaining() >= name_len + SIZEOF_U32,
+                "truncated property"
+            );
+            let name = buf.copy_to_bytes(name_len);
+            let value_len = buf.get_u32() as usize;
+            ensure!(buf.remaining() >= value_len, "truncated property");
+            let (value, rest) = buf.split_at(value_len);
+            buf = rest;
+            let as_u64 = || -> Result<u64> {
+                let value = value
+                    .try_into()
+                    .map_err(|_| anyhow!("invalid property {}", String::from_utf8_lossy(&name)))?;
+                Ok(u64::from_be_bytes(value))
+            };
+            match &name[..] {
+                name if name == Self::MAX_SEQ.as_bytes() => max_seq = Some(as_u64()?),
+                name if name == Self::NUM_ENTRIES.as_bytes() => num_entries = Some(as_u64()?),
+                name if name == Self::COMPRESSION.as_bytes() => {
+                    let [compression_type] = value else {
+                        bail!("invalid property compression");
+                    };
+                    compression = CompressionType(*compression_type);
+                }
+                _ => {}
+            }
+        }
+        let (Some(max_seq), Some(num_entries)) = (max_seq, num_entries) else {
+            bail!("missing properties");
+        };
+        Ok(Self {
+            max_seq,
+            num_entries,
+            compression,
+        })
+    }
+}
@@ -3,18 +3,23 @@ use std::sync::Arc;
 use anyhow::Result;
 
 use super::SsTable;
-use crate::block::BlockIterator;
+use crate::block::{Block, BlockIterator};
 use crate::iterators::StorageIterator;
+use crate::value_type::ValueType;
 
-/// An iterator over the contents of an SSTable.
+/// An iterator over the contents of an SSTable, in both directions like [`BlockIterator`].
 pub struct SsTableIterator {
     table: Arc<SsTable>,
     blk_iter: BlockIterator,
+    /// The index of the current block, or the number of blocks past the end.
     blk_idx: usize,
 }
 
 impl SsTableIterator {
     fn seek_to_first_inner(table: &Arc<SsTable>) -> Result<(usize, BlockIterator)> {
+        if table.num_of_blocks() == 0 {
+            return Ok((0, Self::empty_block_iter()));
+        }
         Ok((
             0,
             BlockIterator::create_and_seek_to_first(table.read_block_cached(0)?),
@@ -40,7 +45,49 @@ impl SsTableIterator {
         Ok(())
     }
 
+    fn seek_to_last_inner(table: &Arc<SsTable>) -> Result<(usize, BlockIterator)> {
+        let Some(blk_idx) = table.num_of_blocks().checked_sub(1) else {
+            return Ok((0, Self::empty_block_iter()));
+        };
+        Ok((
+            blk_idx,
+            BlockIterator::create_and_seek_to_last(table.read_block_cached(blk_idx)?),
+        ))
+    }
+
+    /// Create a new iterator and seek to the last key-value pair.
+    pub fn create_and_seek_to_last(table: Arc<SsTable>) -> Result<Self> {
+        let (blk_idx, blk_iter) = Self::seek_to_last_inner(&table)?;
+        let iter = Self {
+            blk_iter,
+            table,
+            blk_idx,
+        };
+        Ok(iter)
+    }
+
+    /// Seek to the last key-value pair.
+    pub fn seek_to_last(&mut self) -> Result<()> {
+        let (blk_idx, blk_iter) = Self::seek_to_last_inner(&self.table)?;
+        self.blk_idx = blk_idx;
+        self.blk_iter = blk_iter;
+        Ok(())
+    }
+
+    /// Check if the iterator is past the end of the SSTable, rather than before the start.
+    fn is_past_end(&self) -> bool {
+        self.blk_idx == self.table.num_of_blocks()
+    }
+
+    /// An invalid block iterator, for an SSTable with range tombstones only.
+    fn empty_block_iter() -> BlockIterator {
+        BlockIterator::create_and_seek_to_first(Arc::new(Block::default()))
+    }
+
     fn seek_to_key_inner(table: &Arc<SsTable>, key: &[u8]) -> Result<(usize, BlockIterator)> {
+        if table.num_of_blocks() == 0 {
+            return Ok((0, Self::empty_block_iter()));
+        }
         let mut blk_idx = table.find_block_idx(key);
         let mut blk_iter =
             BlockIterator::create_and_seek_to_key(table.read_block_cached(blk_idx)?, key);
@@ -75,10 +122,22 @@ impl SsTableIterator {
 }
 
 impl StorageIterator for SsTableIterator {
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.seek_to_key(key)
+    }
+
     fn value(&self) -> &[u8] {
         self.blk_iter.value()
     }
 
+    fn value_type(&self) -> ValueType {
+        self.blk_iter.value_type()
+    }
+
+    fn seq(&self) -> u64 {
+        self.blk_iter.seq()
+    }
+
     fn key(&self) -> &[u8] {
         self.blk_iter.key()
     }
@@ -88,6 +147,12 @@ impl StorageIterator for SsTableIterator {
     }
 
     fn next(&mut self) -> Result<()> {
+        if !self.is_valid() {
+            if !self.is_past_end() {
+                self.seek_to_first()?;
+            }
+            return Ok(());
+        }
         self.blk_iter.next();
         if !self.blk_iter.is_valid() {
             self.blk_idx += 1;
@@ -99,4 +164,20 @@ impl StorageIterator for SsTableIterator {
         }
         Ok(())
     }
+
+    fn prev(&mut self) -> Result<()> {
+        if !self.is_valid() {
+            if self.is_past_end() {
+                self.seek_to_last()?;
+            }
+            return Ok(());
+        }
+        self.blk_iter.prev();
+        if !self.blk_iter.is_valid() && self.blk_idx > 0 {
+            self.blk_idx -= 1;
+            self.blk_iter =
+                BlockIterator::create_and_seek_to_last(self.table.read_block_cached(self.blk_idx)?);
+        }
+        Ok(())
+    }
 }
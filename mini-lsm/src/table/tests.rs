@@ -1,16 +1,21 @@
 use std::sync::Arc;
 
+use anyhow::Result;
 use bytes::Bytes;
 use tempfile::{tempdir, TempDir};
 
 use super::*;
+use crate::compression::LzCompressor;
 use crate::iterators::StorageIterator;
+use crate::prefix_extractor::{DelimiterPrefixExtractor, FixedPrefixExtractor};
+use crate::range_tombstone::RangeTombstone;
 use crate::table::SsTableBuilder;
+use crate::value_type::ValueType;
 
 #[test]
 fn test_sst_build_single_key() {
     let mut builder = SsTableBuilder::new(16);
-    builder.add(b"233", b"233333");
+    builder.add(b"233", 0, ValueType::Put, b"233333");
     let dir = tempdir().unwrap();
     builder.build_for_test(dir.path().join("1.sst")).unwrap();
 }
@@ -18,12 +23,12 @@ fn test_sst_build_single_key() {
 #[test]
 fn test_sst_build_two_blocks() {
     let mut builder = SsTableBuilder::new(16);
-    builder.add(b"11", b"11");
-    builder.add(b"22", b"22");
-    builder.add(b"33", b"11");
-    builder.add(b"44", b"22");
-    builder.add(b"55", b"11");
-    builder.add(b"66", b"22");
+    builder.add(b"11", 0, ValueType::Put, b"11");
+    builder.add(b"22", 0, ValueType::Put, b"22");
+    builder.add(b"33", 0, ValueType::Put, b"11");
+    builder.add(b"44", 0, ValueType::Put, b"22");
+    builder.add(b"55", 0, ValueType::Put, b"11");
+    builder.add(b"66", 0, ValueType::Put, b"22");
     assert!(builder.meta.len() >= 2);
     let dir = tempdir().unwrap();
     builder.build_for_test(dir.path().join("1.sst")).unwrap();
@@ -46,7 +51,7 @@ fn generate_sst() -> (TempDir, SsTable) {
     for idx in 0..num_of_keys() {
         let key = key_of(idx);
         let value = value_of(idx);
-        builder.add(&key[..], &value[..]);
+        builder.add(&key[..], 0, ValueType::Put, &value[..]);
     }
     let dir = tempdir().unwrap();
     let path = dir.path().join("1.sst");
@@ -62,8 +67,10 @@ fn test_sst_build_all() {
 fn test_sst_decode() {
     let (_dir, sst) = generate_sst();
     let meta = sst.block_metas.clone();
+    let bloom = sst.bloom.clone();
     let new_sst = SsTable::open_for_test(sst.file).unwrap();
     assert_eq!(new_sst.block_metas, meta);
+    assert_eq!(new_sst.bloom, bloom);
 }
 
 fn as_bytes(x: &[u8]) -> Bytes {
@@ -128,3 +135,515 @@ fn test_sst_seek_key() {
         iter.seek_to_key(b"k").unwrap();
     }
 }
+
+#[test]
+fn test_sst_bloom_filter() {
+    let num_keys = 10000;
+    let false_positive_rate = |bits_per_key: usize| {
+        let mut builder = SsTableBuilder::new(4096).with_bloom_bits_per_key(bits_per_key);
+        for idx in 0..num_keys {
+            builder.add(
+                format!("key_{:08}", idx * 2).as_bytes(),
+                0,
+                ValueType::Put,
+                b"value",
+            );
+        }
+        let dir = tempdir().unwrap();
+        let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+        // No false negatives.
+        for idx in 0..num_keys {
+            assert!(sst.may_contain(format!("key_{:08}", idx * 2).as_bytes()));
+        }
+        let false_positives = (0..num_keys)
+            .filter(|idx| sst.may_contain(format!("key_{:08}", idx * 2 + 1).as_bytes()))
+            .count();
+        false_positives as f64 / num_keys as f64
+    };
+    // The theoretical rates are about 0.8% and 0.01%.
+    let rate_10 = false_positive_rate(10);
+    assert!(rate_10 < 0.02, "false positive rate {}", rate_10);
+    let rate_20 = false_positive_rate(20);
+    assert!(rate_20 < 0.001, "false positive rate {}", rate_20);
+    // Without a filter, every key may be in the table.
+    assert_eq!(false_positive_rate(0), 1.0);
+}
+
+#[test]
+fn test_sst_key_range() {
+    let (_dir, sst) = generate_sst();
+    assert_eq!(sst.first_key(), key_of(0));
+    assert_eq!(sst.last_key(), key_of(num_of_keys() - 1));
+    let new_sst = SsTable::open_for_test(sst.file).unwrap();
+    assert_eq!(new_sst.first_key(), key_of(0));
+    assert_eq!(new_sst.last_key(), key_of(num_of_keys() - 1));
+
+    let (first, last) = (key_of(0), key_of(num_of_keys() - 1));
+    assert!(new_sst.range_overlap(Bound::Unbounded, Bound::Unbounded));
+    assert!(new_sst.range_overlap(Bound::Included(&last), Bound::Unbounded));
+    assert!(!new_sst.range_overlap(Bound::Excluded(&last), Bound::Unbounded));
+    assert!(new_sst.range_overlap(Bound::Unbounded, Bound::Included(&first)));
+    assert!(!new_sst.range_overlap(Bound::Unbounded, Bound::Excluded(&first)));
+    assert!(!new_sst.range_overlap(Bound::Included(b"a"), Bound::Included(b"b")));
+    assert!(new_sst.range_overlap(Bound::Included(b"a"), Bound::Included(b"z")));
+}
+
+#[test]
+fn test_sst_range_tombstones() {
+    let mut builder = SsTableBuilder::new(128);
+    builder.add(b"b", 1, ValueType::Put, b"1");
+    builder.add(b"d", 3, ValueType::Put, b"3");
+    builder.add_range_tombstone(RangeTombstone::new(as_bytes(b"a"), as_bytes(b"c"), 2));
+    builder.add_range_tombstone(RangeTombstone::new(as_bytes(b"c"), as_bytes(b"x"), 4));
+    let dir = tempdir().unwrap();
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    let sst = SsTable::open_for_test(sst.file).unwrap();
+    assert_eq!(sst.range_tombstones().len(), 2);
+    assert_eq!(sst.max_seq(), 4);
+    // The key range spans the tombstones.
+    assert_eq!(sst.first_key(), b"a");
+    assert_eq!(sst.last_key(), b"x");
+    assert_eq!(sst.max_covering_tombstone_seq(b"b", 4), 2);
+    assert_eq!(sst.max_covering_tombstone_seq(b"b", 1), 0);
+    assert_eq!(sst.max_covering_tombstone_seq(b"d", 4), 4);
+    assert_eq!(sst.max_covering_tombstone_seq(b"x", 4), 0);
+
+    // An SSTable may hold range tombstones only.
+    let mut builder = SsTableBuilder::new(128);
+    builder.add_range_tombstone(RangeTombstone::new(as_bytes(b"k"), as_bytes(b"m"), 5));
+    let sst = builder.build_for_test(dir.path().join("2.sst")).unwrap();
+    let sst = SsTable::open_for_test(sst.file).unwrap();
+    assert_eq!(sst.num_of_blocks(), 0);
+    assert_eq!(sst.first_key(), b"k");
+    assert!(sst.get(b"l", 5).unwrap().is_none());
+    assert_eq!(sst.max_covering_tombstone_seq(b"l", 5), 5);
+    let iter = SsTableIterator::create_and_seek_to_first(Arc::new(sst)).unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_range_tombstone_clip() {
+    let t = RangeTombstone::new(as_bytes(b"b"), as_bytes(b"f"), 1);
+    assert!(t.covers(b"b", 0));
+    assert!(!t.covers(b"b", 1));
+    assert!(!t.covers(b"f", 0));
+    assert_eq!(t.clip(None, None), Some(t.clone()));
+    assert_eq!(
+        t.clip(Some(b"c"), Some(b"e")),
+        Some(RangeTombstone::new(as_bytes(b"c"), as_bytes(b"e"), 1))
+    );
+    assert_eq!(t.clip(Some(b"f"), None), None);
+    assert_eq!(t.clip(None, Some(b"b")), None);
+
+    let mut buf = Vec::new();
+    RangeTombstone::encode(std::slice::from_ref(&t), &mut buf);
+    assert_eq!(RangeTombstone::decode(&buf).unwrap(), vec![t]);
+    assert!(RangeTombstone::decode(&buf[..buf.len() - 1]).is_err());
+}
+
+#[test]
+fn test_sst_iterator_backward() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    assert!(sst.num_of_blocks() > 1);
+    let mut iter = SsTableIterator::create_and_seek_to_last(sst).unwrap();
+    for _ in 0..2 {
+        for i in (0..num_of_keys()).rev() {
+            assert_eq!(
+                iter.key(),
+                key_of(i),
+                "expected key: {:?}, actual key: {:?}",
+                as_bytes(&key_of(i)),
+                as_bytes(iter.key())
+            );
+            assert_eq!(iter.value(), value_of(i));
+            iter.prev().unwrap();
+        }
+        assert!(!iter.is_valid());
+        // Before the start, the iterator comes back to the first entry.
+        iter.next().unwrap();
+        assert_eq!(iter.key(), key_of(0));
+        iter.seek_to_last().unwrap();
+    }
+
+    // Change the direction across a block boundary, and come back from past the end.
+    iter.seek_to_first().unwrap();
+    for i in 0..num_of_keys() {
+        assert_eq!(iter.key(), key_of(i));
+        iter.next().unwrap();
+        if i + 1 < num_of_keys() {
+            iter.prev().unwrap();
+            assert_eq!(iter.key(), key_of(i));
+            iter.next().unwrap();
+        }
+    }
+    assert!(!iter.is_valid());
+    iter.prev().unwrap();
+    assert_eq!(iter.key(), key_of(num_of_keys() - 1));
+}
+
+#[test]
+fn test_sst_prefix_bloom_filter() {
+    let extractor = Arc::new(DelimiterPrefixExtractor::new(b'/'));
+    let mut builder = SsTableBuilder::new(4096).with_prefix_extractor(extractor.clone());
+    for tenant in (0..1000).step_by(2) {
+        for object in 0..3 {
+            let key = format!("tenant{:04}/object{}", tenant, object);
+            builder.add(key.as_bytes(), 0, ValueType::Put, b"value");
+        }
+    }
+    // Keys without a prefix are left out of the filter.
+    builder.add(b"zzz", 0, ValueType::Put, b"value");
+    let dir = tempdir().unwrap();
+    builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    let sst = SsTable::open_for_test(FileObject::open(&dir.path().join("1.sst")).unwrap()).unwrap();
+
+    for tenant in (0..1000).step_by(2) {
+        assert!(sst.may_contain_prefix(
+            extractor.as_ref(),
+            format!("tenant{:04}/", tenant).as_bytes()
+        ));
+        // A longer prefix is checked with the prefix it starts with.
+        assert!(sst.may_contain_prefix(
+            extractor.as_ref(),
+            format!("tenant{:04}/obj", tenant).as_bytes()
+        ));
+    }
+    let false_positives = (1..1000)
+        .step_by(2)
+        .filter(|tenant| {
+            sst.may_contain_prefix(
+                extractor.as_ref(),
+                format!("tenant{:04}/", tenant).as_bytes(),
+            )
+        })
+        .count();
+    assert!(false_positives < 20, "{} false positives", false_positives);
+    // Without a prefix of its own, or with another extractor, the filter is not used.
+    assert!(sst.may_contain_prefix(extractor.as_ref(), b"tenant0001"));
+    assert!(sst.may_contain_prefix(&FixedPrefixExtractor::new(4), b"tenant0001/"));
+    assert!(sst.may_contain_prefix(&DelimiterPrefixExtractor::new(b':'), b"tenant0001/"));
+}
+
+/// Flip a bit of the SST file at `offset`, and open it again. Flipping it again restores the file.
+fn open_corrupted(dir: &TempDir, offset: u64) -> Result<SsTable> {
+    let path = dir.path().join("1.sst");
+    let mut data = std::fs::read(&path).unwrap();
+    data[offset as usize] ^= 0x10;
+    std::fs::write(&path, &data).unwrap();
+    SsTable::open_for_test(FileObject::open(&path).unwrap())
+}
+
+#[test]
+fn test_sst_corrupted_block() {
+    let (dir, sst) = generate_sst();
+    let offset = sst.block_metas[1].offset as u64 + 5;
+    // The block is only checked when read.
+    let sst = Arc::new(open_corrupted(&dir, offset).unwrap());
+    sst.read_block(0).unwrap();
+    let err = sst.read_block(1).err().unwrap();
+    assert!(err.downcast_ref::<Corruption>().is_some(), "{:#}", err);
+    let first_key = sst.block_metas[1].first_key.clone();
+    let err = sst.get(&first_key, 0).err().unwrap();
+    assert!(err.downcast_ref::<Corruption>().is_some(), "{:#}", err);
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    let err = loop {
+        if let Err(err) = iter.next() {
+            break err;
+        }
+    };
+    assert!(err.downcast_ref::<Corruption>().is_some(), "{:#}", err);
+}
+
+#[test]
+fn test_sst_corrupted_meta() {
+    let (dir, sst) = generate_sst();
+    let meta_offset = sst.block_meta_offset as u64;
+    let len = sst.table_size();
+    for offset in [meta_offset, meta_offset + 10, len - 30, len - 1] {
+        let err = open_corrupted(&dir, offset).err().unwrap();
+        assert!(err.downcast_ref::<Corruption>().is_some(), "{:#}", err);
+        open_corrupted(&dir, offset).unwrap();
+    }
+
+    // A truncated file is corrupted too.
+    let path = dir.path().join("1.sst");
+    let data = std::fs::read(&path).unwrap();
+    for len in [0, 10, data.len() - 1] {
+        std::fs::write(&path, &data[..len]).unwrap();
+        let err = SsTable::open_for_test(FileObject::open(&path).unwrap())
+            .err()
+            .unwrap();
+        assert!(err.downcast_ref::<Corruption>().is_some(), "{:#}", err);
+    }
+}
+
+#[test]
+fn test_sst_foreign_file() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    for data in [vec![], b"not an sst".to_vec(), vec![0; 4096]] {
+        std::fs::write(&path, &data).unwrap();
+        let err = SsTable::open_for_test(FileObject::open(&path).unwrap())
+            .err()
+            .unwrap();
+        assert!(err.downcast_ref::<Corruption>().is_some(), "{:#}", err);
+        assert!(err.to_string().contains("not an SST"), "{:#}", err);
+    }
+}
+
+#[test]
+fn test_sst_format_version() {
+    let (dir, sst) = generate_sst();
+    let path = dir.path().join("1.sst");
+    let mut data = std::fs::read(&path).unwrap();
+    let footer_offset = data.len() - Footer::SIZE;
+    let footer = Footer::decode(&data[footer_offset..]).unwrap();
+    assert_eq!(footer.version, format::FORMAT_VERSION);
+    assert_eq!(footer.index.offset, sst.block_meta_offset as u64);
+
+    // A file written in a newer format is rejected.
+    data.truncate(footer_offset);
+    Footer {
+        version: format::FORMAT_VERSION + 1,
+        ..footer
+    }
+    .encode(&mut data);
+    std::fs::write(&path, &data).unwrap();
+    let err = SsTable::open_for_test(FileObject::open(&path).unwrap())
+        .err()
+        .unwrap();
+    assert!(err.downcast_ref::<Corruption>().is_some(), "{:#}", err);
+    assert!(err.to_string().contains("format version"), "{:#}", err);
+}
+
+#[test]
+fn test_sst_properties() {
+    let (_dir, sst) = generate_sst();
+    assert_eq!(sst.num_entries(), num_of_keys() as u64);
+
+    // Unknown properties are skipped.
+    let properties = Properties {
+        max_seq: 3,
+        num_entries: 5,
+        compression: CompressionType::LZ,
+    };
+    let mut buf = Vec::new();
+    buf.put_u16(7);
+    buf.put_slice(b"unknown");
+    buf.put_u32(2);
+    buf.put_slice(b"xy");
+    properties.encode(&mut buf);
+    assert_eq!(Properties::decode(&buf).unwrap(), properties);
+    assert!(Properties::decode(&buf[..buf.len() - 1]).is_err());
+}
+
+#[test]
+fn test_sst_large_values() {
+    let key_of = |idx: usize| format!("key_{:03}", idx).into_bytes();
+    let value_of = |idx: usize| {
+        let len = if idx % 10 == 5 {
+            (idx / 10 + 1) << 20
+        } else {
+            100
+        };
+        vec![idx as u8; len]
+    };
+    let mut builder = SsTableBuilder::new(4096);
+    for idx in 0..50 {
+        builder.add(&key_of(idx), 0, ValueType::Put, &value_of(idx));
+    }
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    builder.build_for_test(&path).unwrap();
+    let sst = Arc::new(SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap());
+    assert!(sst.table_size() > 15 << 20);
+
+    // Each value larger than a block has an oversized block of its own.
+    for idx in (5..50).step_by(10) {
+        let block_idx = sst.find_block_idx(&key_of(idx));
+        let meta = &sst.block_metas[block_idx];
+        assert_eq!(
+            (&meta.first_key[..], &meta.last_key[..]),
+            (&key_of(idx)[..], &key_of(idx)[..])
+        );
+        let (_, value, _) = sst.get(&key_of(idx), 0).unwrap().unwrap();
+        assert_eq!(value, value_of(idx));
+    }
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    for idx in 0..50 {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_block_meta_large_offsets() {
+    // Block offsets past 4 GiB, and keys longer than 64 KiB.
+    let metas = vec![
+        BlockMeta {
+            offset: 0,
+            first_key: Bytes::from(vec![b'a'; 70_000]),
+            last_key: Bytes::from_static(b"b"),
+        },
+        BlockMeta {
+            offset: 5 << 30,
+            first_key: Bytes::from_static(b"c"),
+            last_key: Bytes::from(vec![b'd'; 70_000]),
+        },
+    ];
+    let mut buf = Vec::new();
+    BlockMeta::encode_block_meta(&metas, &mut buf);
+    assert_eq!(BlockMeta::decode_block_meta(&buf[..]).unwrap(), metas);
+    assert!(BlockMeta::decode_block_meta(&buf[..buf.len() - 1]).is_err());
+}
+
+fn text_key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn build_text_sst(dir: &TempDir, name: &str, builder: SsTableBuilder) -> Arc<SsTable> {
+    let mut builder = builder;
+    for idx in 0..1000 {
+        let value = format!("the value of key {:05} is some compressible text", idx);
+        builder.add(&text_key_of(idx), 0, ValueType::Put, value.as_bytes());
+    }
+    let path = dir.path().join(name);
+    builder.build_for_test(&path).unwrap();
+    Arc::new(SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap())
+}
+
+fn collect_sst(sst: Arc<SsTable>) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    let mut items = Vec::new();
+    while iter.is_valid() {
+        items.push((iter.key().to_vec(), iter.value().to_vec()));
+        iter.next().unwrap();
+    }
+    items
+}
+
+#[test]
+fn test_sst_compression() {
+    let dir = tempdir().unwrap();
+    let plain = build_text_sst(&dir, "1.sst", SsTableBuilder::new(4096));
+    let compressed = build_text_sst(
+        &dir,
+        "2.sst",
+        SsTableBuilder::new(4096).with_compressor(Arc::new(LzCompressor)),
+    );
+    assert_eq!(plain.compression(), CompressionType::NONE);
+    assert_eq!(compressed.compression(), CompressionType::LZ);
+    assert!(
+        compressed.table_size() * 2 < plain.table_size(),
+        "{} vs {} bytes",
+        compressed.table_size(),
+        plain.table_size()
+    );
+    // Blocks are cut before compression, so both have the same blocks.
+    assert_eq!(compressed.block_metas.len(), plain.block_metas.len());
+    assert_eq!(collect_sst(compressed.clone()), collect_sst(plain));
+    let (_, value, _) = compressed.get(&text_key_of(500), 0).unwrap().unwrap();
+    assert_eq!(value, "the value of key 00500 is some compressible text");
+
+    // A corrupted compressed block fails the checksum of the stored bytes.
+    let offset = compressed.block_metas[1].offset as u64 + 5;
+    let path = dir.path().join("1.sst");
+    std::fs::copy(dir.path().join("2.sst"), &path).unwrap();
+    let sst = open_corrupted(&dir, offset).unwrap();
+    sst.read_block(0).unwrap();
+    let err = sst.read_block(1).err().unwrap();
+    assert!(err.downcast_ref::<Corruption>().is_some(), "{:#}", err);
+    assert!(
+        format!("{:#}", err).contains("block checksum mismatch"),
+        "{:#}",
+        err
+    );
+}
+
+/// Build the SST of the files in `testdata`, each written by the code of an older format version
+/// with the same builder.
+fn build_fixture_sst(builder: SsTableBuilder) -> SsTableBuilder {
+    let mut builder = builder
+        .with_restart_interval(4)
+        .with_prefix_extractor(Arc::new(FixedPrefixExtractor::new(5)));
+    for idx in 0..60usize {
+        let key = format!("key_{:03}", idx);
+        if idx == 20 {
+            builder.add(key.as_bytes(), 90, ValueType::Put, b"new");
+            builder.add(key.as_bytes(), 10, ValueType::Put, b"old");
+        } else if idx % 10 == 7 {
+            builder.add(key.as_bytes(), 100 + idx as u64, ValueType::Delete, b"");
+        } else {
+            let value = format!("value of {} ", idx).repeat(4);
+            builder.add(
+                key.as_bytes(),
+                100 + idx as u64,
+                ValueType::Put,
+                value.as_bytes(),
+            );
+        }
+    }
+    builder.add_range_tombstone(RangeTombstone::new(
+        Bytes::from_static(b"key_030"),
+        Bytes::from_static(b"key_035"),
+        150,
+    ));
+    builder
+}
+
+/// Open an SST file from `testdata`, and check that it reads the same as the SST built now.
+fn check_fixture_sst(dir: &TempDir, data: &[u8], format_version: u32) {
+    let path = dir.path().join("1.sst");
+    std::fs::write(&path, data).unwrap();
+    let old = Arc::new(SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap());
+    assert_eq!(old.format_version, format_version);
+    let new = build_fixture_sst(SsTableBuilder::new(256))
+        .build_for_test(dir.path().join("2.sst"))
+        .unwrap();
+    let new = Arc::new(new);
+
+    let collect = |sst: Arc<SsTable>| {
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+        let mut items = Vec::new();
+        while iter.is_valid() {
+            let value = iter.value().to_vec();
+            items.push((iter.key().to_vec(), iter.seq(), iter.value_type(), value));
+            iter.next().unwrap();
+        }
+        items
+    };
+    let items = collect(old.clone());
+    assert_eq!(items.len(), 61);
+    assert_eq!(items, collect(new.clone()));
+    assert!(old.num_of_blocks() > 1);
+    assert_eq!(old.range_tombstones(), new.range_tombstones());
+    assert_eq!((old.max_seq(), old.num_entries()), (159, 61));
+
+    let iter = SsTableIterator::create_and_seek_to_key(old.clone(), b"key_025").unwrap();
+    assert_eq!(iter.key(), b"key_025");
+    let (value_type, value, seq) = old.get(b"key_020", 50).unwrap().unwrap();
+    assert_eq!(
+        (value_type, &value[..], seq),
+        (ValueType::Put, &b"old"[..], 10)
+    );
+    assert!(old.may_contain_prefix(&FixedPrefixExtractor::new(5), b"key_0"));
+    assert!(!old.may_contain_prefix(&FixedPrefixExtractor::new(5), b"nope_"));
+}
+
+#[test]
+fn test_sst_format_version_1() {
+    // Written by the first versioned format, with u16 lengths and u32 block offsets.
+    let dir = tempdir().unwrap();
+    check_fixture_sst(&dir, include_bytes!("testdata/format_v1.sst"), 1);
+}
+
+#[test]
+fn test_sst_format_version_2() {
+    // Written by the first format with compression, where blocks have no checksum of the stored
+    // bytes. Some of the blocks are compressed.
+    let dir = tempdir().unwrap();
+    check_fixture_sst(&dir, include_bytes!("testdata/format_v2.sst"), 2);
+}
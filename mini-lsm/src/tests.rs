@@ -1 +1,5 @@
 pub mod day4_tests;
+pub mod day5_tests;
+pub mod day6_tests;
+pub mod day7_tests;
+pub mod day8_tests;
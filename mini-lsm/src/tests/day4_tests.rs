@@ -34,9 +34,9 @@ fn check_iter_result(iter: impl StorageIterator, expected: Vec<(Bytes, Bytes)>)
 
 #[test]
 fn test_storage_get() {
-    use crate::lsm_storage::LsmStorage;
+    use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
     let dir = tempdir().unwrap();
-    let storage = LsmStorage::open(&dir).unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
     storage.put(b"1", b"233").unwrap();
     storage.put(b"2", b"2333").unwrap();
     storage.put(b"3", b"23333").unwrap();
@@ -49,9 +49,9 @@ fn test_storage_get() {
 
 #[test]
 fn test_storage_scan_memtable_1() {
-    use crate::lsm_storage::LsmStorage;
+    use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
     let dir = tempdir().unwrap();
-    let storage = LsmStorage::open(&dir).unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
     storage.put(b"1", b"233").unwrap();
     storage.put(b"2", b"2333").unwrap();
     storage.put(b"3", b"23333").unwrap();
@@ -79,9 +79,9 @@ fn test_storage_scan_memtable_1() {
 
 #[test]
 fn test_storage_scan_memtable_2() {
-    use crate::lsm_storage::LsmStorage;
+    use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
     let dir = tempdir().unwrap();
-    let storage = LsmStorage::open(&dir).unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
     storage.put(b"1", b"233").unwrap();
     storage.put(b"2", b"2333").unwrap();
     storage.put(b"3", b"23333").unwrap();
@@ -109,9 +109,9 @@ fn test_storage_scan_memtable_2() {
 
 #[test]
 fn test_storage_get_after_sync() {
-    use crate::lsm_storage::LsmStorage;
+    use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
     let dir = tempdir().unwrap();
-    let storage = LsmStorage::open(&dir).unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
     storage.put(b"1", b"233").unwrap();
     storage.put(b"2", b"2333").unwrap();
     storage.sync().unwrap();
@@ -125,9 +125,9 @@ fn test_storage_get_after_sync() {
 
 #[test]
 fn test_storage_scan_memtable_1_after_sync() {
-    use crate::lsm_storage::LsmStorage;
+    use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
     let dir = tempdir().unwrap();
-    let storage = LsmStorage::open(&dir).unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
     storage.put(b"1", b"233").unwrap();
     storage.put(b"2", b"2333").unwrap();
     storage.sync().unwrap();
@@ -156,9 +156,9 @@ fn test_storage_scan_memtable_1_after_sync() {
 
 #[test]
 fn test_storage_scan_memtable_2_after_sync() {
-    use crate::lsm_storage::LsmStorage;
+    use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
     let dir = tempdir().unwrap();
-    let storage = LsmStorage::open(&dir).unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
     storage.put(b"1", b"233").unwrap();
     storage.put(b"2", b"2333").unwrap();
     storage.sync().unwrap();
@@ -185,3 +185,89 @@ fn test_storage_scan_memtable_2_after_sync() {
         vec![(Bytes::from("2"), Bytes::from("2333"))],
     );
 }
+
+#[test]
+fn test_storage_empty_value() {
+    use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    storage.put(b"1", b"").unwrap();
+    storage.put(b"2", b"").unwrap();
+    storage.put(b"3", b"233").unwrap();
+    storage.delete(b"2").unwrap();
+    // An empty value is present, unlike a deleted key, in memtables and SSTs alike.
+    for _ in 0..2 {
+        assert_eq!(storage.get(b"1").unwrap(), Some(Bytes::new()));
+        assert!(storage.get(b"2").unwrap().is_none());
+        check_iter_result(
+            storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+            vec![
+                (Bytes::from("1"), Bytes::new()),
+                (Bytes::from("3"), Bytes::from("233")),
+            ],
+        );
+        storage.sync().unwrap();
+    }
+    drop(storage);
+
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    assert_eq!(storage.get(b"1").unwrap(), Some(Bytes::new()));
+    assert!(storage.get(b"2").unwrap().is_none());
+}
+
+#[test]
+fn test_storage_get_after_sync_exact_key() {
+    use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    storage.put(b"1", b"233").unwrap();
+    storage.put(b"3", b"23333").unwrap();
+    storage.sync().unwrap();
+    storage.put(b"5", b"2333333").unwrap();
+    storage.sync().unwrap();
+    // Keys between, before and after the flushed ones do not resolve to a neighbour.
+    assert!(storage.get(b"0").unwrap().is_none());
+    assert!(storage.get(b"2").unwrap().is_none());
+    assert!(storage.get(b"4").unwrap().is_none());
+    assert!(storage.get(b"6").unwrap().is_none());
+    assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"23333");
+    assert_eq!(&storage.get(b"5").unwrap().unwrap()[..], b"2333333");
+}
+
+#[test]
+fn test_storage_get_tombstone_across_levels() {
+    use crate::compact::{CompactionOptions, LeveledCompactionOptions};
+    use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        compaction_options: CompactionOptions::Leveled(LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            ..LeveledCompactionOptions::default()
+        }),
+        ..LsmStorageOptions::default()
+    };
+    let storage = LsmStorage::open(&dir, options).unwrap();
+    storage.put(b"1", b"233").unwrap();
+    storage.put(b"2", b"2333").unwrap();
+    storage.sync().unwrap();
+    storage.put(b"3", b"23333").unwrap();
+    storage.sync().unwrap();
+    // All values are compacted into L1, and the tombstone is in L0.
+    storage.delete(b"2").unwrap();
+    storage.sync().unwrap();
+    let snapshot = storage.snapshot_for_test();
+    assert_eq!(snapshot.l0_sstables.len(), 1);
+    assert_eq!(snapshot.levels[0].len(), 1);
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"233");
+    assert!(storage.get(b"2").unwrap().is_none());
+    assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"23333");
+
+    // A tombstone compacted along with the value it shadows still hides it.
+    storage.put(b"4", b"233333").unwrap();
+    storage.sync().unwrap();
+    storage.delete(b"4").unwrap();
+    storage.put(b"5", b"2333333").unwrap();
+    storage.sync().unwrap();
+    assert!(storage.get(b"4").unwrap().is_none());
+    assert_eq!(&storage.get(b"5").unwrap().unwrap()[..], b"2333333");
+}
@@ -0,0 +1,241 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, LeveledCompactionOptions, TieredCompactionOptions};
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::table::SsTableIterator;
+
+fn leveled_options() -> LsmStorageOptions {
+    LsmStorageOptions {
+        block_size: 64,
+        target_sst_size: 1024,
+        compaction_options: CompactionOptions::Leveled(LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+            base_level_size: 2048,
+            level_size_multiplier: 2,
+        }),
+        ..LsmStorageOptions::default()
+    }
+}
+
+fn tiered_options() -> LsmStorageOptions {
+    LsmStorageOptions {
+        block_size: 64,
+        target_sst_size: 1024,
+        compaction_options: CompactionOptions::Tiered(TieredCompactionOptions {
+            num_tiers: 3,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+        }),
+        ..LsmStorageOptions::default()
+    }
+}
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn check_storage(storage: &LsmStorage, expected: &BTreeMap<Vec<u8>, Vec<u8>>) {
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for (key, value) in expected {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), &key[..]);
+        assert_eq!(iter.value(), &value[..]);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    for (key, value) in expected {
+        assert_eq!(
+            storage.get(key).unwrap(),
+            Some(Bytes::copy_from_slice(value))
+        );
+    }
+}
+
+fn check_levels(storage: &LsmStorage) {
+    let snapshot = storage.snapshot_for_test();
+    assert!(snapshot.l0_sstables.len() < 2);
+    check_sorted_runs(storage);
+}
+
+fn check_sorted_runs(storage: &LsmStorage) {
+    let snapshot = storage.snapshot_for_test();
+    for level in snapshot.levels.iter() {
+        // SSTs in a level are sorted and do not overlap.
+        let mut last_key: Option<Vec<u8>> = None;
+        for table in level {
+            let mut iter = SsTableIterator::create_and_seek_to_first(table.clone()).unwrap();
+            while iter.is_valid() {
+                if let Some(ref last_key) = last_key {
+                    assert!(iter.key() > &last_key[..]);
+                }
+                last_key = Some(iter.key().to_vec());
+                iter.next().unwrap();
+            }
+        }
+    }
+}
+
+#[test]
+fn test_leveled_compaction() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, leveled_options()).unwrap();
+    let mut expected = BTreeMap::new();
+    for round in 0..20 {
+        for idx in 0..100 {
+            let idx = (idx * 7 + round * 13) % 300;
+            let key = key_of(idx);
+            if idx % 11 == round % 11 {
+                storage.delete(&key).unwrap();
+                expected.remove(&key);
+            } else {
+                let value = format!("value_{}_{}", idx, round).into_bytes();
+                storage.put(&key, &value).unwrap();
+                expected.insert(key, value);
+            }
+        }
+        storage.sync().unwrap();
+        check_levels(&storage);
+    }
+    let snapshot = storage.snapshot_for_test();
+    assert!(snapshot.levels.len() >= 2);
+    assert!(snapshot.levels.iter().all(|level| level.len() <= 32));
+    check_storage(&storage, &expected);
+
+    // The structure of the LSM tree is restored from the manifest.
+    drop(storage);
+    let storage = LsmStorage::open(&dir, leveled_options()).unwrap();
+    let reopened = storage.snapshot_for_test();
+    let ids = |ssts: &Vec<std::sync::Arc<crate::table::SsTable>>| {
+        ssts.iter().map(|sst| sst.sst_id()).collect::<Vec<_>>()
+    };
+    assert_eq!(ids(&reopened.l0_sstables), ids(&snapshot.l0_sstables));
+    assert_eq!(
+        reopened.levels.iter().map(ids).collect::<Vec<_>>(),
+        snapshot.levels.iter().map(ids).collect::<Vec<_>>()
+    );
+    check_storage(&storage, &expected);
+}
+
+#[test]
+fn test_leveled_compaction_drop_tombstones_at_bottom() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, leveled_options()).unwrap();
+    for _ in 0..2 {
+        for idx in 0..10 {
+            storage.put(&key_of(idx), b"233").unwrap();
+        }
+        storage.sync().unwrap();
+    }
+    let snapshot = storage.snapshot_for_test();
+    assert!(snapshot.l0_sstables.is_empty());
+    assert!(!snapshot.levels[0].is_empty());
+
+    for _ in 0..2 {
+        for idx in 0..10 {
+            storage.delete(&key_of(idx)).unwrap();
+        }
+        storage.sync().unwrap();
+    }
+    let snapshot = storage.snapshot_for_test();
+    assert!(snapshot.l0_sstables.is_empty());
+    assert!(snapshot.levels.iter().all(|level| level.is_empty()));
+    check_storage(&storage, &BTreeMap::new());
+}
+
+#[test]
+fn test_tiered_compaction() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, tiered_options()).unwrap();
+    let mut expected = BTreeMap::new();
+    for round in 0..20 {
+        for idx in 0..100 {
+            let idx = (idx * 7 + round * 13) % 300;
+            let key = key_of(idx);
+            if idx % 11 == round % 11 {
+                storage.delete(&key).unwrap();
+                expected.remove(&key);
+            } else {
+                let value = format!("value_{}_{}", idx, round).into_bytes();
+                storage.put(&key, &value).unwrap();
+                expected.insert(key, value);
+            }
+        }
+        storage.sync().unwrap();
+        check_sorted_runs(&storage);
+        // Compaction keeps the number of sorted runs below the trigger.
+        let snapshot = storage.snapshot_for_test();
+        let num_runs = snapshot.l0_sstables.len()
+            + snapshot
+                .levels
+                .iter()
+                .filter(|level| !level.is_empty())
+                .count();
+        assert!(num_runs < 3);
+    }
+    let snapshot = storage.snapshot_for_test();
+    assert!(!snapshot.levels.is_empty());
+    check_storage(&storage, &expected);
+
+    drop(storage);
+    let storage = LsmStorage::open(&dir, tiered_options()).unwrap();
+    let reopened = storage.snapshot_for_test();
+    let ids = |ssts: &Vec<std::sync::Arc<crate::table::SsTable>>| {
+        ssts.iter().map(|sst| sst.sst_id()).collect::<Vec<_>>()
+    };
+    assert_eq!(ids(&reopened.l0_sstables), ids(&snapshot.l0_sstables));
+    assert_eq!(
+        reopened.levels.iter().map(ids).collect::<Vec<_>>(),
+        snapshot.levels.iter().map(ids).collect::<Vec<_>>()
+    );
+    check_storage(&storage, &expected);
+}
+
+#[test]
+fn test_target_space_amplification() {
+    let leveled = leveled_options().compaction_options;
+    // 2048 + 4096 + 8192 over 8192.
+    assert_eq!(leveled.target_space_amplification(), Some(1.75));
+    let tiered = tiered_options().compaction_options;
+    assert_eq!(tiered.target_space_amplification(), Some(3.0));
+    assert_eq!(
+        CompactionOptions::NoCompaction.target_space_amplification(),
+        None
+    );
+}
+
+#[test]
+fn test_leveled_compaction_only_picks_overlapping_ssts() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, leveled_options()).unwrap();
+    for _ in 0..2 {
+        for idx in 0..10 {
+            storage.put(&key_of(idx), b"233").unwrap();
+        }
+        storage.sync().unwrap();
+    }
+    let snapshot = storage.snapshot_for_test();
+    assert_eq!(snapshot.levels[0].len(), 1);
+    let untouched_id = snapshot.levels[0][0].sst_id();
+
+    // L0 does not overlap the SST already in L1, which is left as is.
+    for _ in 0..2 {
+        for idx in 100..110 {
+            storage.put(&key_of(idx), b"2333").unwrap();
+        }
+        storage.sync().unwrap();
+    }
+    let snapshot = storage.snapshot_for_test();
+    assert!(snapshot.l0_sstables.is_empty());
+    assert_eq!(snapshot.levels[0].len(), 2);
+    assert_eq!(snapshot.levels[0][0].sst_id(), untouched_id);
+    assert_eq!(snapshot.levels[0][1].first_key(), key_of(100));
+    assert_eq!(snapshot.levels[0][1].last_key(), key_of(109));
+    check_levels(&storage);
+}
@@ -0,0 +1,248 @@
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::write_batch::WriteBatch;
+
+#[test]
+fn test_storage_recover_from_wal() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+        storage.put(b"1", b"233").unwrap();
+        storage.put(b"2", b"2333").unwrap();
+        storage.put(b"3", b"23333").unwrap();
+        storage.delete(b"2").unwrap();
+        storage.sync_wal().unwrap();
+    }
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"233");
+    assert!(storage.get(b"2").unwrap().is_none());
+    assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"23333");
+    storage.put(b"4", b"233333").unwrap();
+    drop(storage);
+
+    // Both the recovered memtable and the one created after reopening survive another restart.
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"233");
+    assert_eq!(&storage.get(b"4").unwrap().unwrap()[..], b"233333");
+}
+
+#[test]
+fn test_storage_wal_deleted_after_flush() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    storage.put(b"1", b"233").unwrap();
+    storage.sync().unwrap();
+    storage.put(b"2", b"2333").unwrap();
+    storage.sync().unwrap();
+    let wal_count = std::fs::read_dir(&dir)
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == "wal")
+        })
+        .count();
+    // Only the WAL of the current memtable is left.
+    assert_eq!(wal_count, 1);
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"233");
+    assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"2333");
+}
+
+#[test]
+fn test_storage_reopen_with_ssts() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+        storage.put(b"1", b"233").unwrap();
+        storage.put(b"2", b"2333").unwrap();
+        storage.sync().unwrap();
+        storage.put(b"1", b"23333").unwrap();
+        storage.sync().unwrap();
+        storage.put(b"3", b"233333").unwrap();
+    }
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    // Later SSTs still take precedence over earlier ones.
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"23333");
+    assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"2333");
+    assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"233333");
+
+    // New SSTs must not overwrite the ones loaded from disk.
+    storage.put(b"2", b"2333333").unwrap();
+    storage.sync().unwrap();
+    drop(storage);
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"23333");
+    assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"2333333");
+    assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"233333");
+}
+
+#[test]
+fn test_storage_ignore_unrecorded_sst() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+        storage.put(b"1", b"233").unwrap();
+        storage.sync().unwrap();
+    }
+    // An SST that the manifest does not know of, e.g. left behind by an interrupted compaction.
+    let orphan = dir.path().join("00100.sst");
+    std::fs::write(&orphan, b"garbage").unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"233");
+    assert!(!orphan.exists());
+}
+
+#[test]
+fn test_storage_reject_ssts_without_manifest() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+        storage.put(b"1", b"233").unwrap();
+        storage.sync().unwrap();
+    }
+    std::fs::remove_file(dir.path().join("MANIFEST")).unwrap();
+    let err = LsmStorage::open(&dir, LsmStorageOptions::default())
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("no MANIFEST"), "{:#}", err);
+    // The SSTs are left alone.
+    assert!(std::fs::read_dir(&dir).unwrap().any(|entry| {
+        entry
+            .unwrap()
+            .path()
+            .extension()
+            .is_some_and(|ext| ext == "sst")
+    }));
+}
+
+#[test]
+fn test_storage_background_flush() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        memtable_size_limit: 1024,
+        max_imm_memtables: 2,
+        compaction_options: CompactionOptions::NoCompaction,
+        ..LsmStorageOptions::default()
+    };
+    let key_of = |idx: usize| format!("key_{:05}", idx).into_bytes();
+    let value_of = |idx: usize| format!("value_{:05}", idx).into_bytes();
+    let storage = LsmStorage::open(&dir, options.clone()).unwrap();
+    for idx in 0..1000 {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+        // Writes wait for the worker rather than pile up frozen memtables.
+        assert!(storage.snapshot_for_test().imm_memtables.len() <= 2);
+    }
+    // Memtables are frozen without an explicit sync, and drained by the worker on close.
+    storage.close().unwrap();
+    let err = storage.put(b"key", b"value").err().unwrap();
+    assert!(err.to_string().contains("closed"), "{:#}", err);
+    let snapshot = storage.snapshot_for_test();
+    assert!(snapshot.imm_memtables.is_empty());
+    assert!(snapshot.l0_sstables.len() >= 10);
+    assert!(snapshot.memtable.approximate_size() < 1024);
+    for idx in 0..1000 {
+        assert_eq!(storage.get(&key_of(idx)).unwrap().unwrap(), value_of(idx));
+    }
+    drop(storage);
+
+    let storage = LsmStorage::open(&dir, options).unwrap();
+    for idx in 0..1000 {
+        assert_eq!(storage.get(&key_of(idx)).unwrap().unwrap(), value_of(idx));
+    }
+}
+
+#[test]
+fn test_storage_background_flush_error() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        memtable_size_limit: 1024,
+        max_imm_memtables: 1,
+        compaction_options: CompactionOptions::NoCompaction,
+        ..LsmStorageOptions::default()
+    };
+    let storage = LsmStorage::open(&dir, options).unwrap();
+    // A directory in place of its SST makes the flush of the current memtable fail.
+    let id = storage.snapshot_for_test().memtable.id();
+    std::fs::create_dir(dir.path().join(format!("{:05}.sst", id))).unwrap();
+
+    // The memtable is frozen right away, and the next write waits for the worker to flush it,
+    // which fails.
+    storage.put(b"1", &vec![b'v'; 1024]).unwrap();
+    let err = storage.put(b"2", b"233").err().unwrap();
+    assert!(
+        err.to_string().contains("background flush failed"),
+        "{:#}",
+        err
+    );
+    assert_eq!(storage.snapshot_for_test().imm_memtables.len(), 1);
+    let err = storage.sync().err().unwrap();
+    assert!(
+        err.to_string().contains("background flush failed"),
+        "{:#}",
+        err
+    );
+    assert!(storage.close().is_err());
+}
+
+#[test]
+fn test_storage_write_batch() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"c", b"3").unwrap();
+    storage.sync().unwrap();
+    storage.put(b"d", b"4").unwrap();
+
+    let mut batch = WriteBatch::new();
+    batch
+        .put(b"b", b"2")
+        .delete(b"a")
+        .put(b"e", b"5")
+        .delete_range(b"b", b"e")
+        .put(b"c", b"33");
+    storage.write(&batch).unwrap();
+    // The range delete covers both the keys in the storage and the one put before it in the
+    // batch, but not the ones put after it.
+    let check = |storage: &LsmStorage| {
+        assert!(storage.get(b"a").unwrap().is_none());
+        assert!(storage.get(b"b").unwrap().is_none());
+        assert_eq!(&storage.get(b"c").unwrap().unwrap()[..], b"33");
+        assert!(storage.get(b"d").unwrap().is_none());
+        assert_eq!(&storage.get(b"e").unwrap().unwrap()[..], b"5");
+    };
+    check(&storage);
+    drop(storage);
+
+    // The batch is recovered from the WAL.
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    check(&storage);
+    storage.sync().unwrap();
+    check(&storage);
+}
+
+#[test]
+fn test_storage_write_batch_in_one_memtable() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        memtable_size_limit: 64,
+        compaction_options: CompactionOptions::NoCompaction,
+        ..LsmStorageOptions::default()
+    };
+    let storage = LsmStorage::open(&dir, options).unwrap();
+    let mut batch = WriteBatch::new();
+    for idx in 0..100 {
+        batch.put(format!("key_{:03}", idx).as_bytes(), b"value");
+    }
+    storage.write(&batch).unwrap();
+    storage.close().unwrap();
+    // The batch is larger than the size limit, yet lands in a single memtable and SST.
+    let snapshot = storage.snapshot_for_test();
+    assert_eq!(snapshot.l0_sstables.len(), 1);
+    assert_eq!(snapshot.l0_sstables[0].first_key(), b"key_000");
+    assert_eq!(snapshot.l0_sstables[0].last_key(), b"key_099");
+}
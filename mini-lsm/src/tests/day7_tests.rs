@@ -0,0 +1,416 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, LeveledCompactionOptions};
+use crate::iterators::StorageIterator;
+use crate::lsm_iterator::{FusedIterator, LsmIterator};
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::mvcc::{IsolationLevel, TxnConflict};
+use crate::table::SsTableIterator;
+
+fn options() -> LsmStorageOptions {
+    LsmStorageOptions {
+        block_size: 64,
+        target_sst_size: 256,
+        compaction_options: CompactionOptions::Leveled(LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+            base_level_size: 1 << 20,
+            level_size_multiplier: 2,
+        }),
+        ..LsmStorageOptions::default()
+    }
+}
+
+fn collect(mut iter: FusedIterator<LsmIterator>) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut items = Vec::new();
+    while iter.is_valid() {
+        items.push((iter.key().to_vec(), iter.value().to_vec()));
+        iter.next().unwrap();
+    }
+    items
+}
+
+/// Count the entries of every version in the SSTs of the storage.
+fn count_sst_entries(storage: &LsmStorage) -> usize {
+    let snapshot = storage.snapshot_for_test();
+    let mut count = 0;
+    for table in snapshot
+        .l0_sstables
+        .iter()
+        .chain(snapshot.levels.iter().flatten())
+    {
+        let mut iter = SsTableIterator::create_and_seek_to_first(table.clone()).unwrap();
+        while iter.is_valid() {
+            count += 1;
+            iter.next().unwrap();
+        }
+    }
+    count
+}
+
+#[test]
+fn test_snapshot_isolation() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, options()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    let snapshot = storage.snapshot();
+    storage.put(b"a", b"2").unwrap();
+    storage.delete(b"b").unwrap();
+    storage.put(b"c", b"2").unwrap();
+
+    let check = |storage: &LsmStorage| {
+        assert_eq!(
+            &storage.get_with_snapshot(b"a", &snapshot).unwrap().unwrap()[..],
+            b"1"
+        );
+        assert_eq!(
+            &storage.get_with_snapshot(b"b", &snapshot).unwrap().unwrap()[..],
+            b"1"
+        );
+        assert!(storage
+            .get_with_snapshot(b"c", &snapshot)
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            collect(
+                storage
+                    .scan_with_snapshot(Bound::Unbounded, Bound::Unbounded, &snapshot)
+                    .unwrap()
+            ),
+            vec![
+                (b"a".to_vec(), b"1".to_vec()),
+                (b"b".to_vec(), b"1".to_vec())
+            ]
+        );
+        assert_eq!(&storage.get(b"a").unwrap().unwrap()[..], b"2");
+        assert!(storage.get(b"b").unwrap().is_none());
+        assert_eq!(
+            collect(storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap()),
+            vec![
+                (b"a".to_vec(), b"2".to_vec()),
+                (b"c".to_vec(), b"2".to_vec())
+            ]
+        );
+    };
+    // In the memtable.
+    check(&storage);
+    // In L0.
+    storage.sync().unwrap();
+    check(&storage);
+    // After compacting into L1.
+    storage.put(b"c", b"2").unwrap();
+    storage.sync().unwrap();
+    assert!(storage.snapshot_for_test().l0_sstables.is_empty());
+    check(&storage);
+}
+
+#[test]
+fn test_compaction_keeps_versions_for_snapshots() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, options()).unwrap();
+    let key_of = |idx: usize| format!("key_{:03}", idx).into_bytes();
+    for idx in 0..50 {
+        storage.put(&key_of(idx), b"v1").unwrap();
+    }
+    storage.sync().unwrap();
+    let snapshot = storage.snapshot();
+    for idx in 0..50 {
+        storage.put(&key_of(idx), b"v2").unwrap();
+    }
+    storage.sync().unwrap();
+
+    // Both versions survive compaction while the snapshot is live, and SSTs are split only
+    // between keys.
+    assert_eq!(count_sst_entries(&storage), 100);
+    for idx in 0..50 {
+        let key = key_of(idx);
+        assert_eq!(
+            &storage.get_with_snapshot(&key, &snapshot).unwrap().unwrap()[..],
+            b"v1"
+        );
+        assert_eq!(&storage.get(&key).unwrap().unwrap()[..], b"v2");
+    }
+    let snapshot_scan = collect(
+        storage
+            .scan_with_snapshot(Bound::Excluded(&key_of(10)), Bound::Unbounded, &snapshot)
+            .unwrap(),
+    );
+    assert_eq!(snapshot_scan.len(), 39);
+    assert!(snapshot_scan.iter().all(|(_, value)| value == b"v1"));
+
+    // The older versions are dropped by the next compaction once the snapshot is gone.
+    drop(snapshot);
+    for idx in 0..50 {
+        storage.delete(&key_of(idx)).unwrap();
+        if idx == 25 {
+            storage.sync().unwrap();
+        }
+    }
+    storage.sync().unwrap();
+    assert_eq!(count_sst_entries(&storage), 0);
+}
+
+#[test]
+fn test_seq_recovered_after_reopen() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir, options()).unwrap();
+        storage.put(b"a", b"1").unwrap();
+        storage.sync().unwrap();
+        storage.put(b"a", b"2").unwrap();
+        storage.put(b"b", b"2").unwrap();
+        assert_eq!(storage.snapshot().seq(), 3);
+    }
+    let storage = LsmStorage::open(&dir, options()).unwrap();
+    assert_eq!(storage.snapshot().seq(), 3);
+    storage.put(b"a", b"3").unwrap();
+    assert_eq!(&storage.get(b"a").unwrap().unwrap()[..], b"3");
+    storage.sync().unwrap();
+    assert_eq!(&storage.get(b"a").unwrap().unwrap()[..], b"3");
+    drop(storage);
+
+    let storage = LsmStorage::open(&dir, options()).unwrap();
+    assert_eq!(storage.snapshot().seq(), 4);
+}
+
+#[test]
+fn test_txn_reads_own_writes() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, options()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    storage.put(b"c", b"1").unwrap();
+    storage.sync().unwrap();
+
+    let txn = storage.new_txn();
+    txn.put(b"a", b"2").unwrap();
+    txn.delete(b"b").unwrap();
+    txn.put(b"d", b"2").unwrap();
+    txn.put(b"d", b"3").unwrap();
+    // Writes committed after the transaction started are not visible to it.
+    storage.put(b"e", b"1").unwrap();
+
+    assert_eq!(&txn.get(b"a").unwrap().unwrap()[..], b"2");
+    assert!(txn.get(b"b").unwrap().is_none());
+    assert_eq!(&txn.get(b"c").unwrap().unwrap()[..], b"1");
+    assert_eq!(&txn.get(b"d").unwrap().unwrap()[..], b"3");
+    assert!(txn.get(b"e").unwrap().is_none());
+    let mut iter = txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut items = Vec::new();
+    while iter.is_valid() {
+        items.push((iter.key().to_vec(), iter.value().to_vec()));
+        iter.next().unwrap();
+    }
+    assert_eq!(
+        items,
+        vec![
+            (b"a".to_vec(), b"2".to_vec()),
+            (b"c".to_vec(), b"1".to_vec()),
+            (b"d".to_vec(), b"3".to_vec()),
+        ]
+    );
+    // Nothing is visible outside the transaction until it commits.
+    assert_eq!(&storage.get(b"a").unwrap().unwrap()[..], b"1");
+    assert!(storage.get(b"d").unwrap().is_none());
+
+    txn.commit().unwrap();
+    assert_eq!(&storage.get(b"a").unwrap().unwrap()[..], b"2");
+    assert!(storage.get(b"b").unwrap().is_none());
+    assert_eq!(&storage.get(b"d").unwrap().unwrap()[..], b"3");
+
+    // A dropped transaction leaves no trace.
+    let txn = storage.new_txn();
+    txn.put(b"f", b"1").unwrap();
+    drop(txn);
+    assert!(storage.get(b"f").unwrap().is_none());
+}
+
+#[test]
+fn test_txn_write_conflict() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, options()).unwrap();
+    storage.put(b"counter", b"0").unwrap();
+
+    let txn1 = storage.new_txn();
+    let txn2 = storage.new_txn();
+    let txn3 = storage.new_txn();
+    txn1.put(b"counter", b"1").unwrap();
+    txn2.put(b"counter", b"2").unwrap();
+    txn2.put(b"other", b"2").unwrap();
+    txn3.put(b"unrelated", b"3").unwrap();
+    txn1.commit().unwrap();
+
+    // The second writer of the key fails as a whole.
+    let err = txn2.commit().unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<TxnConflict>(),
+        Some(&TxnConflict {
+            key: Bytes::from_static(b"counter")
+        })
+    );
+    assert_eq!(&storage.get(b"counter").unwrap().unwrap()[..], b"1");
+    assert!(storage.get(b"other").unwrap().is_none());
+    // Disjoint writes do not conflict.
+    txn3.commit().unwrap();
+    assert_eq!(&storage.get(b"unrelated").unwrap().unwrap()[..], b"3");
+
+    // Writes outside of transactions are conflicts as well.
+    let txn = storage.new_txn();
+    txn.delete(b"counter").unwrap();
+    storage.put(b"counter", b"4").unwrap();
+    assert!(txn.commit().unwrap_err().is::<TxnConflict>());
+
+    // A transaction started after a commit does not conflict with it.
+    let txn = storage.new_txn();
+    txn.put(b"counter", b"5").unwrap();
+    txn.commit().unwrap();
+    assert_eq!(&storage.get(b"counter").unwrap().unwrap()[..], b"5");
+}
+
+/// Two doctors are on call, and each goes off call in a transaction if the other one is still on
+/// call. Under snapshot isolation both may commit, leaving nobody on call.
+fn run_write_skew(storage: &LsmStorage, isolation: IsolationLevel) -> (bool, bool) {
+    storage.put(b"alice", b"on").unwrap();
+    storage.put(b"bob", b"on").unwrap();
+    let go_off_call = |me: &[u8], other: &[u8]| {
+        let txn = storage.new_txn_with_isolation(isolation);
+        assert_eq!(txn.isolation(), isolation);
+        assert_eq!(&txn.get(me).unwrap().unwrap()[..], b"on");
+        assert_eq!(&txn.get(other).unwrap().unwrap()[..], b"on");
+        txn.put(me, b"off").unwrap();
+        txn
+    };
+    let txn1 = go_off_call(b"alice", b"bob");
+    let txn2 = go_off_call(b"bob", b"alice");
+    (txn1.commit().is_ok(), txn2.commit().is_ok())
+}
+
+#[test]
+fn test_txn_write_skew() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, options()).unwrap();
+    assert_eq!(
+        run_write_skew(&storage, IsolationLevel::Snapshot),
+        (true, true)
+    );
+    assert_eq!(&storage.get(b"alice").unwrap().unwrap()[..], b"off");
+    assert_eq!(&storage.get(b"bob").unwrap().unwrap()[..], b"off");
+
+    assert_eq!(
+        run_write_skew(&storage, IsolationLevel::Serializable),
+        (true, false)
+    );
+    assert_eq!(&storage.get(b"alice").unwrap().unwrap()[..], b"off");
+    assert_eq!(&storage.get(b"bob").unwrap().unwrap()[..], b"on");
+}
+
+#[test]
+fn test_txn_serializable_scan() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, options()).unwrap();
+    storage.put(b"item_1", b"1").unwrap();
+    storage.put(b"item_3", b"1").unwrap();
+
+    // Count the items in a range, and record the count.
+    let count_items = |lower: Bound<&[u8]>, upper: Bound<&[u8]>| {
+        let txn = storage.new_txn_with_isolation(IsolationLevel::Serializable);
+        let mut iter = txn.scan(lower, upper).unwrap();
+        let mut count = 0;
+        while iter.is_valid() {
+            count += 1;
+            iter.next().unwrap();
+        }
+        txn.put(b"count", count.to_string().as_bytes()).unwrap();
+        txn
+    };
+
+    // A write outside of the scanned range does not conflict.
+    let txn = count_items(Bound::Included(b"item_1"), Bound::Excluded(b"item_3"));
+    storage.put(b"item_3", b"2").unwrap();
+    txn.commit().unwrap();
+
+    // A write into the scanned range does, even if the key did not exist when it was scanned.
+    let txn = count_items(Bound::Excluded(b"item_1"), Bound::Included(b"item_3"));
+    let other = storage.new_txn();
+    other.put(b"item_2", b"1").unwrap();
+    other.commit().unwrap();
+    let err = txn.commit().unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<TxnConflict>(),
+        Some(&TxnConflict {
+            key: Bytes::from_static(b"item_2")
+        })
+    );
+    assert_eq!(&storage.get(b"count").unwrap().unwrap()[..], b"1");
+
+    // So does a delete of a key that was read.
+    let txn = storage.new_txn_with_isolation(IsolationLevel::Serializable);
+    assert!(txn.get(b"item_1").unwrap().is_some());
+    txn.put(b"count", b"3").unwrap();
+    storage.delete(b"item_1").unwrap();
+    assert!(txn.commit().unwrap_err().is::<TxnConflict>());
+
+    // A read-only transaction always commits.
+    let txn = storage.new_txn_with_isolation(IsolationLevel::Serializable);
+    assert!(txn.get(b"item_2").unwrap().is_some());
+    storage.delete(b"item_2").unwrap();
+    txn.commit().unwrap();
+}
+
+#[test]
+fn test_compaction_below_watermark() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, options()).unwrap();
+    let key_of = |idx: usize| format!("key_{:03}", idx).into_bytes();
+    assert_eq!(storage.watermark(), 0);
+    for version in 1..=3 {
+        for idx in 0..20 {
+            storage
+                .put(&key_of(idx), format!("v{}", version).as_bytes())
+                .unwrap();
+        }
+    }
+    storage.delete(&key_of(0)).unwrap();
+    storage.sync().unwrap();
+    assert_eq!(storage.watermark(), 61);
+
+    let snapshot = storage.snapshot();
+    let txn = storage.new_txn();
+    for idx in 0..20 {
+        storage.put(&key_of(idx), b"v4").unwrap();
+    }
+    let later_snapshot = storage.snapshot();
+    // The watermark is the oldest read sequence number in use.
+    assert_eq!(storage.watermark(), 61);
+    drop(snapshot);
+    assert_eq!(storage.watermark(), 61);
+    drop(txn);
+    assert_eq!(storage.watermark(), 81);
+    storage.put(&key_of(0), b"v5").unwrap();
+    storage.sync().unwrap();
+
+    // Each key keeps the versions above the watermark, and the latest one at or below it.
+    assert_eq!(count_sst_entries(&storage), 21);
+    assert_eq!(
+        &storage
+            .get_with_snapshot(&key_of(0), &later_snapshot)
+            .unwrap()
+            .unwrap()[..],
+        b"v4"
+    );
+    assert_eq!(&storage.get(&key_of(0)).unwrap().unwrap()[..], b"v5");
+
+    // The tombstone is dropped at the bottom level once it falls below the watermark.
+    drop(later_snapshot);
+    assert_eq!(storage.watermark(), 82);
+    storage.delete(&key_of(1)).unwrap();
+    storage.sync().unwrap();
+    storage.put(&key_of(2), b"v6").unwrap();
+    storage.sync().unwrap();
+    assert_eq!(count_sst_entries(&storage), 19);
+    assert!(storage.get(&key_of(1)).unwrap().is_none());
+}
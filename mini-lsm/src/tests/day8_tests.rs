@@ -0,0 +1,885 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, LeveledCompactionOptions, TieredCompactionOptions};
+use crate::compression::{CompressionType, Compressor, CompressorRegistry, LzCompressor};
+use crate::iterators::StorageIterator;
+use crate::lsm_iterator::{FusedIterator, LsmIterator};
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::merge_operator::U64AddOperator;
+use crate::mvcc::{IsolationLevel, TxnConflict};
+use crate::prefix_extractor::DelimiterPrefixExtractor;
+use crate::table::{Corruption, SsTableIterator};
+use crate::value_type::ValueType;
+use crate::write_batch::WriteBatch;
+
+fn options() -> LsmStorageOptions {
+    LsmStorageOptions {
+        block_size: 64,
+        target_sst_size: 256,
+        compaction_options: CompactionOptions::Leveled(LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+            base_level_size: 1 << 20,
+            level_size_multiplier: 2,
+        }),
+        ..LsmStorageOptions::default()
+    }
+}
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:03}", idx).into_bytes()
+}
+
+fn collect(mut iter: FusedIterator<LsmIterator>) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut items = Vec::new();
+    while iter.is_valid() {
+        items.push((iter.key().to_vec(), iter.value().to_vec()));
+        iter.next().unwrap();
+    }
+    items
+}
+
+fn collect_keys(mut iter: FusedIterator<LsmIterator>) -> Vec<Vec<u8>> {
+    let mut keys = Vec::new();
+    while iter.is_valid() {
+        keys.push(iter.key().to_vec());
+        iter.next().unwrap();
+    }
+    keys
+}
+
+/// Count the entries of every version in the SSTs of the storage.
+fn count_sst_entries(storage: &LsmStorage) -> usize {
+    let snapshot = storage.snapshot_for_test();
+    let mut count = 0;
+    for table in snapshot
+        .l0_sstables
+        .iter()
+        .chain(snapshot.levels.iter().flatten())
+    {
+        let mut iter = SsTableIterator::create_and_seek_to_first(table.clone()).unwrap();
+        while iter.is_valid() {
+            count += 1;
+            iter.next().unwrap();
+        }
+    }
+    count
+}
+
+#[test]
+fn test_delete_range() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, options()).unwrap();
+    for idx in 0..20 {
+        storage.put(&key_of(idx), b"v").unwrap();
+    }
+    storage.sync().unwrap();
+    for idx in 20..40 {
+        storage.put(&key_of(idx), b"v").unwrap();
+    }
+    let snapshot = storage.snapshot();
+    storage.delete_range(&key_of(10), &key_of(30)).unwrap();
+    storage.put(&key_of(15), b"again").unwrap();
+
+    let expected: Vec<Vec<u8>> = (0..10)
+        .chain(std::iter::once(15))
+        .chain(30..40)
+        .map(key_of)
+        .collect();
+    let check = |storage: &LsmStorage| {
+        for idx in 0..40 {
+            let value = storage.get(&key_of(idx)).unwrap();
+            match idx {
+                15 => assert_eq!(&value.unwrap()[..], b"again"),
+                10..=29 => assert!(value.is_none(), "{} is deleted", idx),
+                _ => assert_eq!(&value.unwrap()[..], b"v"),
+            }
+        }
+        assert_eq!(
+            collect_keys(storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap()),
+            expected
+        );
+        assert_eq!(
+            collect_keys(
+                storage
+                    .scan(Bound::Excluded(&key_of(9)), Bound::Included(&key_of(30)))
+                    .unwrap()
+            ),
+            vec![key_of(15), key_of(30)]
+        );
+    };
+    check(&storage);
+    // The snapshot taken before the range delete still sees every key.
+    assert_eq!(
+        collect_keys(
+            storage
+                .scan_with_snapshot(Bound::Unbounded, Bound::Unbounded, &snapshot)
+                .unwrap()
+        )
+        .len(),
+        40
+    );
+    assert_eq!(
+        &storage
+            .get_with_snapshot(&key_of(20), &snapshot)
+            .unwrap()
+            .unwrap()[..],
+        b"v"
+    );
+    drop(snapshot);
+    storage.close().unwrap();
+    drop(storage);
+
+    // The range tombstone is recovered from the WAL, then flushed into an SST.
+    let storage = LsmStorage::open(&dir, options()).unwrap();
+    check(&storage);
+    storage.sync().unwrap();
+    check(&storage);
+}
+
+#[test]
+fn test_compaction_drops_range_deleted_keys() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, options()).unwrap();
+    for idx in 0..40 {
+        storage.put(&key_of(idx), b"v").unwrap();
+    }
+    storage.sync().unwrap();
+    storage.delete_range(&key_of(0), &key_of(30)).unwrap();
+    // The second L0 SST triggers a compaction into the bottom level.
+    storage.sync().unwrap();
+
+    let snapshot = storage.snapshot_for_test();
+    assert!(snapshot.l0_sstables.is_empty());
+    // Both the deleted keys and the tombstone are gone.
+    assert_eq!(count_sst_entries(&storage), 10);
+    assert!(snapshot
+        .levels
+        .iter()
+        .flatten()
+        .all(|table| table.range_tombstones().is_empty()));
+    assert_eq!(
+        collect_keys(storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap()),
+        (30..40).map(key_of).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_compaction_keeps_range_tombstones_for_snapshots() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, options()).unwrap();
+    for idx in 0..40 {
+        storage.put(&key_of(idx), b"v").unwrap();
+    }
+    storage.sync().unwrap();
+    let snapshot = storage.snapshot();
+    storage.delete_range(&key_of(5), &key_of(35)).unwrap();
+    storage.sync().unwrap();
+
+    // The tombstone is split along the output SSTs, and each key stays deleted.
+    let state = storage.snapshot_for_test();
+    assert!(state.l0_sstables.is_empty());
+    assert!(state.levels[0].len() > 1);
+    assert_eq!(count_sst_entries(&storage), 40);
+    for idx in 0..40 {
+        let value = storage.get(&key_of(idx)).unwrap();
+        assert_eq!(value.is_some(), !(5..35).contains(&idx), "key {}", idx);
+    }
+    assert_eq!(
+        collect_keys(storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap()).len(),
+        10
+    );
+    assert_eq!(
+        collect_keys(
+            storage
+                .scan_with_snapshot(Bound::Unbounded, Bound::Unbounded, &snapshot)
+                .unwrap()
+        )
+        .len(),
+        40
+    );
+
+    // Once the snapshot is gone, the next compaction over the whole key range drops the deleted
+    // keys.
+    drop(snapshot);
+    for _ in 0..2 {
+        storage.put(&key_of(0), b"w").unwrap();
+        storage.put(&key_of(39), b"w").unwrap();
+        storage.sync().unwrap();
+    }
+    assert_eq!(count_sst_entries(&storage), 10);
+}
+
+#[test]
+fn test_txn_conflicts_with_range_delete() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, options()).unwrap();
+    storage.put(b"b", b"1").unwrap();
+
+    // A transaction writing a key in the range conflicts with the range delete.
+    let txn = storage.new_txn();
+    txn.put(b"c", b"1").unwrap();
+    storage.delete_range(b"a", b"d").unwrap();
+    let err = txn.commit().unwrap_err();
+    assert_eq!(err.downcast::<TxnConflict>().unwrap().key, &b"a"[..]);
+
+    // So does a serializable transaction reading a key in the range.
+    let txn = storage.new_txn_with_isolation(IsolationLevel::Serializable);
+    assert!(txn.get(b"b").unwrap().is_none());
+    txn.put(b"x", b"1").unwrap();
+    storage.delete_range(b"b", b"c").unwrap();
+    assert!(txn.commit().is_err());
+
+    // A write outside the range does not conflict.
+    let txn = storage.new_txn();
+    txn.put(b"d", b"1").unwrap();
+    storage.delete_range(b"a", b"d").unwrap();
+    txn.commit().unwrap();
+    assert_eq!(&storage.get(b"d").unwrap().unwrap()[..], b"1");
+}
+
+fn merge_options() -> LsmStorageOptions {
+    LsmStorageOptions {
+        merge_operator: Some(Arc::new(U64AddOperator)),
+        ..options()
+    }
+}
+
+fn counter(value: u64) -> Vec<u8> {
+    value.to_be_bytes().to_vec()
+}
+
+fn get_counter(storage: &LsmStorage, key: &[u8]) -> Option<u64> {
+    storage
+        .get(key)
+        .unwrap()
+        .map(|value| u64::from_be_bytes(value[..].try_into().unwrap()))
+}
+
+#[test]
+fn test_merge() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, merge_options()).unwrap();
+    storage.put(b"a", &counter(10)).unwrap();
+    for _ in 0..3 {
+        storage.merge(b"a", &counter(1)).unwrap();
+        storage.merge(b"b", &counter(2)).unwrap();
+    }
+    storage.delete(b"c").unwrap();
+    storage.merge(b"c", &counter(5)).unwrap();
+    assert_eq!(get_counter(&storage, b"a"), Some(13));
+    assert_eq!(get_counter(&storage, b"b"), Some(6));
+    assert_eq!(get_counter(&storage, b"c"), Some(5));
+    assert_eq!(
+        collect(storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap()),
+        vec![
+            (b"a".to_vec(), counter(13)),
+            (b"b".to_vec(), counter(6)),
+            (b"c".to_vec(), counter(5)),
+        ]
+    );
+
+    // Operands are folded into the earlier writes of the same key in a batch.
+    let mut batch = WriteBatch::new();
+    batch
+        .merge(b"a", &counter(1))
+        .put(b"d", &counter(1))
+        .merge(b"d", &counter(1))
+        .merge(b"a", &counter(1))
+        .delete_range(b"b", b"c")
+        .merge(b"b", &counter(1));
+    storage.write(&batch).unwrap();
+    assert_eq!(get_counter(&storage, b"a"), Some(15));
+    assert_eq!(get_counter(&storage, b"b"), Some(1));
+    assert_eq!(get_counter(&storage, b"d"), Some(2));
+    storage.close().unwrap();
+    drop(storage);
+
+    // The operands are recovered from the WAL.
+    let storage = LsmStorage::open(&dir, merge_options()).unwrap();
+    assert_eq!(get_counter(&storage, b"a"), Some(15));
+    assert_eq!(get_counter(&storage, b"b"), Some(1));
+    assert_eq!(get_counter(&storage, b"c"), Some(5));
+    assert_eq!(get_counter(&storage, b"d"), Some(2));
+}
+
+#[test]
+fn test_merge_under_range_delete_in_compaction() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, merge_options()).unwrap();
+    storage.merge(b"k", &counter(5)).unwrap();
+    storage.sync().unwrap();
+    storage.delete_range(b"a", b"z").unwrap();
+    // The second L0 SST triggers a compaction into the bottom level, which drops the tombstone
+    // along with the operand it covers.
+    storage.sync().unwrap();
+    assert!(storage.snapshot_for_test().l0_sstables.is_empty());
+    assert_eq!(get_counter(&storage, b"k"), None);
+    assert_eq!(count_sst_entries(&storage), 0);
+}
+
+#[test]
+fn test_merge_without_operator() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, options()).unwrap();
+    assert!(storage.merge(b"a", b"1").is_err());
+    assert!(storage.get(b"a").unwrap().is_none());
+}
+
+#[test]
+fn test_merge_collapsed_in_flush_and_compaction() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, merge_options()).unwrap();
+    for _ in 0..10 {
+        storage.merge(b"a", &counter(1)).unwrap();
+    }
+    let snapshot = storage.snapshot();
+    for _ in 0..10 {
+        storage.merge(b"a", &counter(1)).unwrap();
+    }
+    // The operands after the snapshot are kept apart for it, and the ones before it are
+    // combined into one.
+    storage.sync().unwrap();
+    assert_eq!(count_sst_entries(&storage), 11);
+    assert_eq!(get_counter(&storage, b"a"), Some(20));
+
+    for _ in 0..5 {
+        storage.merge(b"a", &counter(1)).unwrap();
+    }
+    // The second L0 SST triggers a compaction into the bottom level, where the operands below
+    // the snapshot are folded into a value.
+    storage.sync().unwrap();
+    assert!(storage.snapshot_for_test().l0_sstables.is_empty());
+    assert_eq!(count_sst_entries(&storage), 16);
+    assert_eq!(get_counter(&storage, b"a"), Some(25));
+    assert_eq!(
+        &storage.get_with_snapshot(b"a", &snapshot).unwrap().unwrap()[..],
+        counter(10)
+    );
+
+    // Once the snapshot is gone, every operand is collapsed.
+    drop(snapshot);
+    storage.merge(b"a", &counter(1)).unwrap();
+    storage.sync().unwrap();
+    storage.merge(b"a", &counter(1)).unwrap();
+    storage.sync().unwrap();
+    assert_eq!(count_sst_entries(&storage), 1);
+    assert_eq!(get_counter(&storage, b"a"), Some(27));
+    let snapshot = storage.snapshot_for_test();
+    let table = &snapshot.levels.iter().flatten().next().unwrap();
+    let iter = SsTableIterator::create_and_seek_to_first((*table).clone()).unwrap();
+    assert_eq!(iter.value_type(), ValueType::Put);
+}
+
+fn collect_rev(mut iter: FusedIterator<LsmIterator>) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut items = Vec::new();
+    while iter.is_valid() {
+        items.push((iter.key().to_vec(), iter.value().to_vec()));
+        iter.prev().unwrap();
+    }
+    items
+}
+
+#[test]
+fn test_scan_rev() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, merge_options()).unwrap();
+    for idx in 0..40 {
+        storage.put(&key_of(idx), b"v1").unwrap();
+    }
+    storage.sync().unwrap();
+    for idx in (0..40).step_by(2) {
+        storage.put(&key_of(idx), b"v2").unwrap();
+    }
+    storage.delete_range(&key_of(10), &key_of(15)).unwrap();
+    storage.sync().unwrap();
+    for idx in (0..40).step_by(3) {
+        storage.delete(&key_of(idx)).unwrap();
+    }
+    storage.merge(&key_of(31), &counter(1)).unwrap();
+    storage.put(&key_of(35), b"v3").unwrap();
+
+    let bounds = [
+        Bound::Unbounded,
+        Bound::Included(key_of(12)),
+        Bound::Excluded(key_of(12)),
+        Bound::Included(key_of(20)),
+        Bound::Excluded(key_of(31)),
+        Bound::Included(key_of(99)),
+    ];
+    for lower in &bounds {
+        for upper in &bounds {
+            let (lower, upper) = (
+                lower.as_ref().map(|x| &x[..]),
+                upper.as_ref().map(|x| &x[..]),
+            );
+            let mut expected = collect(storage.scan(lower, upper).unwrap());
+            expected.reverse();
+            assert_eq!(
+                collect_rev(storage.scan_rev(lower, upper).unwrap()),
+                expected
+            );
+        }
+    }
+
+    // The latest keys before a key.
+    let iter = storage
+        .scan_rev(Bound::Unbounded, Bound::Excluded(&key_of(20)))
+        .unwrap();
+    let keys: Vec<_> = collect_rev(iter)
+        .into_iter()
+        .map(|(key, _)| key)
+        .take(3)
+        .collect();
+    assert_eq!(keys, vec![key_of(19), key_of(17), key_of(16)]);
+
+    // Change the direction in the middle and at both ends.
+    let mut iter = storage
+        .scan_rev(Bound::Included(&key_of(28)), Bound::Unbounded)
+        .unwrap();
+    assert_eq!(iter.key(), key_of(38));
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+    iter.prev().unwrap();
+    assert_eq!((iter.key(), iter.value()), (&key_of(38)[..], &b"v2"[..]));
+    for idx in [37, 35] {
+        iter.prev().unwrap();
+        assert_eq!(iter.key(), key_of(idx));
+    }
+    assert_eq!(iter.value(), b"v3");
+    iter.next().unwrap();
+    assert_eq!(iter.key(), key_of(37));
+    for idx in [35, 34, 32, 31] {
+        iter.prev().unwrap();
+        assert_eq!(iter.key(), key_of(idx));
+    }
+    assert_eq!(iter.value(), counter(1));
+    for idx in [29, 28] {
+        iter.prev().unwrap();
+        assert_eq!(iter.key(), key_of(idx));
+    }
+    iter.prev().unwrap();
+    assert!(!iter.is_valid());
+    iter.next().unwrap();
+    assert_eq!(iter.key(), key_of(28));
+    iter.next().unwrap();
+    assert_eq!(iter.key(), key_of(29));
+
+    // A transaction sees its own writes moving backward.
+    let txn = storage.new_txn();
+    txn.put(&key_of(36), b"txn").unwrap();
+    txn.delete(&key_of(35)).unwrap();
+    let mut iter = txn
+        .scan(Bound::Included(&key_of(34)), Bound::Unbounded)
+        .unwrap();
+    while iter.is_valid() {
+        iter.next().unwrap();
+    }
+    let mut keys = Vec::new();
+    iter.prev().unwrap();
+    while iter.is_valid() {
+        keys.push((iter.key().to_vec(), iter.value().to_vec()));
+        iter.prev().unwrap();
+    }
+    assert_eq!(
+        keys,
+        vec![
+            (key_of(38), b"v2".to_vec()),
+            (key_of(37), b"v1".to_vec()),
+            (key_of(36), b"txn".to_vec()),
+            (key_of(34), b"v2".to_vec()),
+        ]
+    );
+}
+
+#[test]
+fn test_seek() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, options()).unwrap();
+    for idx in 0..40 {
+        storage.put(&key_of(idx), b"v1").unwrap();
+    }
+    storage.sync().unwrap();
+    for idx in (0..40).step_by(2) {
+        storage.put(&key_of(idx), b"v2").unwrap();
+    }
+    storage.sync().unwrap();
+    for idx in (0..40).step_by(3) {
+        storage.delete(&key_of(idx)).unwrap();
+    }
+
+    let mut iter = storage
+        .scan(Bound::Excluded(&key_of(10)), Bound::Included(&key_of(30)))
+        .unwrap();
+    let expected = collect(
+        storage
+            .scan(Bound::Excluded(&key_of(10)), Bound::Included(&key_of(30)))
+            .unwrap(),
+    );
+    // Read pages of 4 keys with the same iterator, seeking after the last key of each page.
+    let mut pages = Vec::new();
+    let mut start = key_of(0);
+    loop {
+        iter.seek(&start).unwrap();
+        let mut page = Vec::new();
+        while iter.is_valid() && page.len() < 4 {
+            page.push((iter.key().to_vec(), iter.value().to_vec()));
+            iter.next().unwrap();
+        }
+        let Some((last, _)) = page.last() else {
+            break;
+        };
+        start = [&last[..], b"\0"].concat();
+        pages.extend(page);
+    }
+    assert_eq!(pages, expected);
+
+    // Seeking to a deleted key goes to the next visible one, and the iterator stays within the
+    // range.
+    iter.seek(&key_of(12)).unwrap();
+    assert_eq!((iter.key(), iter.value()), (&key_of(13)[..], &b"v1"[..]));
+    iter.prev().unwrap();
+    assert_eq!(iter.key(), key_of(11));
+    iter.seek(&key_of(31)).unwrap();
+    assert!(!iter.is_valid());
+    iter.seek(&key_of(10)).unwrap();
+    assert_eq!(iter.key(), key_of(11));
+
+    // A transaction seeks over its own writes too.
+    let txn = storage.new_txn();
+    txn.put(&key_of(12), b"txn").unwrap();
+    txn.delete(&key_of(13)).unwrap();
+    let mut iter = txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    iter.seek(&key_of(12)).unwrap();
+    assert_eq!((iter.key(), iter.value()), (&key_of(12)[..], &b"txn"[..]));
+    iter.next().unwrap();
+    assert_eq!(iter.key(), key_of(14));
+}
+
+fn tenant_key(tenant: usize, object: usize) -> Vec<u8> {
+    format!("tenant{:02}/object{:03}", tenant, object).into_bytes()
+}
+
+#[test]
+fn test_scan_prefix() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        prefix_extractor: Some(Arc::new(DelimiterPrefixExtractor::new(b'/'))),
+        compaction_options: CompactionOptions::NoCompaction,
+        ..options()
+    };
+    let storage = LsmStorage::open(&dir, options).unwrap();
+    // Each tenant is in its own SSTs.
+    for tenant in 0..4 {
+        for object in 0..10 {
+            storage.put(&tenant_key(tenant, object), b"v").unwrap();
+        }
+        storage.sync().unwrap();
+    }
+    // This SST has no key of tenant 1, but a range tombstone over some of its keys.
+    storage.put(&tenant_key(5, 0), b"v").unwrap();
+    storage
+        .delete_range(&tenant_key(1, 2), &tenant_key(1, 5))
+        .unwrap();
+    storage.sync().unwrap();
+    storage.put(&tenant_key(1, 3), b"v2").unwrap();
+    storage.put(b"tenant1", b"v").unwrap();
+
+    let keys = collect_keys(storage.scan_prefix(b"tenant01/").unwrap());
+    let expected: Vec<_> = [0, 1, 3, 5, 6, 7, 8, 9]
+        .into_iter()
+        .map(|object| tenant_key(1, object))
+        .collect();
+    assert_eq!(keys, expected);
+    let keys = collect_keys(storage.scan_prefix(b"tenant01/object003").unwrap());
+    assert_eq!(keys, vec![tenant_key(1, 3)]);
+    // A prefix without a prefix of its own scans every SST.
+    let keys = collect_keys(storage.scan_prefix(b"tenant0").unwrap());
+    assert_eq!(keys.len(), 39);
+    assert_eq!(
+        collect_keys(storage.scan_prefix(b"tenant04/").unwrap()),
+        Vec::<Vec<u8>>::new()
+    );
+
+    // The prefix filters skip most SSTs of other tenants.
+    let snapshot = storage.snapshot_for_test();
+    let extractor = DelimiterPrefixExtractor::new(b'/');
+    let skipped = snapshot
+        .l0_sstables
+        .iter()
+        .filter(|table| !table.may_contain_prefix(&extractor, b"tenant01/"))
+        .count();
+    assert!(skipped >= 3, "{} SSTs skipped", skipped);
+}
+
+#[test]
+fn test_invalid_block_restart_interval() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        block_restart_interval: 0,
+        ..options()
+    };
+    let err = LsmStorage::open(&dir, options).err().unwrap();
+    assert!(err.to_string().contains("restart interval"), "{:#}", err);
+}
+
+#[test]
+fn test_invalid_max_imm_memtables() {
+    let dir = tempdir().unwrap();
+    // No write could ever go through.
+    let options = LsmStorageOptions {
+        max_imm_memtables: 0,
+        ..options()
+    };
+    let err = LsmStorage::open(&dir, options).err().unwrap();
+    assert!(err.to_string().contains("immutable memtables"), "{:#}", err);
+}
+
+#[test]
+fn test_invalid_compaction_options() {
+    let dir = tempdir().unwrap();
+    let leveled = |options: LeveledCompactionOptions| CompactionOptions::Leveled(options);
+    let tiered = |options: TieredCompactionOptions| CompactionOptions::Tiered(options);
+    for (compaction_options, message) in [
+        (
+            leveled(LeveledCompactionOptions {
+                level0_file_num_compaction_trigger: 0,
+                ..Default::default()
+            }),
+            "compaction trigger",
+        ),
+        (
+            leveled(LeveledCompactionOptions {
+                max_levels: 0,
+                ..Default::default()
+            }),
+            "max levels",
+        ),
+        (
+            leveled(LeveledCompactionOptions {
+                level_size_multiplier: 0,
+                ..Default::default()
+            }),
+            "multiplier",
+        ),
+        (
+            leveled(LeveledCompactionOptions {
+                max_levels: 40,
+                ..Default::default()
+            }),
+            "overflows",
+        ),
+        (
+            tiered(TieredCompactionOptions {
+                num_tiers: 1,
+                ..Default::default()
+            }),
+            "number of tiers",
+        ),
+        (
+            tiered(TieredCompactionOptions {
+                min_merge_width: 0,
+                ..Default::default()
+            }),
+            "merge width",
+        ),
+    ] {
+        let options = LsmStorageOptions {
+            compaction_options,
+            ..options()
+        };
+        let err = LsmStorage::open(&dir, options).err().unwrap();
+        assert!(err.to_string().contains(message), "{:#}", err);
+    }
+}
+
+#[test]
+fn test_corrupted_sst() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        compaction_options: CompactionOptions::NoCompaction,
+        ..options()
+    };
+    let storage = LsmStorage::open(&dir, options.clone()).unwrap();
+    for idx in 0..20 {
+        storage.put(&key_of(idx), b"v").unwrap();
+    }
+    storage.sync().unwrap();
+    let id = storage.snapshot_for_test().l0_sstables[0].sst_id();
+    storage.close().unwrap();
+    drop(storage);
+
+    // Flip a bit in the first block, which holds the first key.
+    let path = dir.path().join(format!("{:05}.sst", id));
+    let mut data = std::fs::read(&path).unwrap();
+    data[5] ^= 1;
+    std::fs::write(&path, &data).unwrap();
+    let storage = LsmStorage::open(&dir, options.clone()).unwrap();
+    let err = storage.get(&key_of(0)).err().unwrap();
+    assert!(err.downcast_ref::<Corruption>().is_some(), "{:#}", err);
+    storage.close().unwrap();
+    drop(storage);
+
+    // A corrupted meta section fails the open.
+    data[5] ^= 1;
+    let len = data.len();
+    data[len - 40] ^= 1;
+    std::fs::write(&path, &data).unwrap();
+    let err = LsmStorage::open(&dir, options).err().unwrap();
+    assert!(err.downcast_ref::<Corruption>().is_some(), "{:#}", err);
+}
+
+#[test]
+fn test_large_values() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        block_size: 4096,
+        target_sst_size: 2 << 20,
+        memtable_size_limit: 4 << 20,
+        ..options()
+    };
+    let value_of = |idx: usize| vec![idx as u8; (idx % 4 + 1) << 20];
+    let storage = LsmStorage::open(&dir, options.clone()).unwrap();
+    for idx in 0..12 {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+        storage.put(&key_of(idx + 100), b"small").unwrap();
+    }
+    storage.sync().unwrap();
+    // The last values are only in the WAL.
+    storage.put(&key_of(200), &value_of(3)).unwrap();
+    storage
+        .delete_range(&vec![b'k'; 100_000], &vec![b'l'; 100_000])
+        .unwrap();
+    for idx in 0..12 {
+        assert_eq!(storage.get(&key_of(idx)).unwrap().unwrap(), value_of(idx));
+    }
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = LsmStorage::open(&dir, options).unwrap();
+    for idx in 0..12 {
+        assert_eq!(storage.get(&key_of(idx)).unwrap().unwrap(), value_of(idx));
+    }
+    assert_eq!(storage.get(&key_of(200)).unwrap().unwrap(), value_of(3));
+    let items = collect(storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap());
+    assert_eq!(items.len(), 25);
+    assert!(items
+        .iter()
+        .all(|(key, value)| key != &key_of(5) || value == &value_of(5)));
+}
+
+#[test]
+fn test_compression_per_level() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        block_size: 4096,
+        target_sst_size: 1 << 20,
+        compression_per_level: vec![CompressionType::NONE, CompressionType::LZ],
+        ..options()
+    };
+    let value_of = |idx: usize| format!("value of {} ", idx).repeat(20).into_bytes();
+    let storage = LsmStorage::open(&dir, options.clone()).unwrap();
+    for idx in 0..400 {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    storage.sync().unwrap();
+    let snapshot = storage.snapshot_for_test();
+    assert_eq!(snapshot.l0_sstables.len(), 1);
+    let plain_size = snapshot.l0_sstables[0].table_size();
+    assert_eq!(snapshot.l0_sstables[0].compression(), CompressionType::NONE);
+
+    // The compaction into L1 compresses the SSTs, as does any level below.
+    for idx in 0..400 {
+        storage.put(&key_of(idx), &value_of(idx + 1)).unwrap();
+    }
+    storage.sync().unwrap();
+    let snapshot = storage.snapshot_for_test();
+    assert!(snapshot.l0_sstables.is_empty());
+    let l1 = &snapshot.levels[0];
+    assert!(l1
+        .iter()
+        .all(|table| table.compression() == CompressionType::LZ));
+    let compressed_size: u64 = l1.iter().map(|table| table.table_size()).sum();
+    assert!(
+        compressed_size * 4 < plain_size,
+        "{} vs {} bytes",
+        compressed_size,
+        plain_size
+    );
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = LsmStorage::open(&dir, options).unwrap();
+    let items = collect(storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap());
+    let expected: Vec<_> = (0..400)
+        .map(|idx| (key_of(idx), value_of(idx + 1)))
+        .collect();
+    assert_eq!(items, expected);
+}
+
+/// Compresses like [`LzCompressor`], under another compression type.
+struct CustomCompressor;
+
+impl Compressor for CustomCompressor {
+    fn compression_type(&self) -> CompressionType {
+        CompressionType(9)
+    }
+
+    fn compress(&self, data: &[u8], buf: &mut Vec<u8>) {
+        LzCompressor.compress(data, buf)
+    }
+
+    fn decompress(&self, data: &[u8], buf: &mut Vec<u8>) -> anyhow::Result<()> {
+        LzCompressor.decompress(data, buf)
+    }
+}
+
+#[test]
+fn test_custom_compressor() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        block_size: 4096,
+        compression_per_level: vec![CompressionType(9)],
+        ..options()
+    };
+    // The compression types must be registered.
+    let err = LsmStorage::open(&dir, options.clone()).err().unwrap();
+    assert_eq!(err.to_string(), "unknown compression type 9");
+
+    let mut compressors = CompressorRegistry::default();
+    compressors.register(Arc::new(CustomCompressor)).unwrap();
+    let options = LsmStorageOptions {
+        compressors,
+        ..options
+    };
+    let value_of = |idx: usize| format!("value of {} ", idx).repeat(20).into_bytes();
+    let storage = LsmStorage::open(&dir, options.clone()).unwrap();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    storage.sync().unwrap();
+    let snapshot = storage.snapshot_for_test();
+    assert_eq!(snapshot.l0_sstables[0].compression(), CompressionType(9));
+    storage.close().unwrap();
+    drop(storage);
+
+    // The SSTs cannot be read without their codec, even if nothing is compressed with it anymore.
+    let without_codec = LsmStorageOptions {
+        compression_per_level: Vec::new(),
+        compressors: CompressorRegistry::default(),
+        ..options.clone()
+    };
+    let err = LsmStorage::open(&dir, without_codec).err().unwrap();
+    assert!(
+        format!("{:#}", err).contains("unknown compression type 9"),
+        "{:#}",
+        err
+    );
+    let storage = LsmStorage::open(&dir, options).unwrap();
+    let items = collect(storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap());
+    let expected: Vec<_> = (0..100).map(|idx| (key_of(idx), value_of(idx))).collect();
+    assert_eq!(items, expected);
+}
@@ -0,0 +1,30 @@
+use anyhow::{bail, Error, Result};
+
+/// The type of an entry, stored along with its key in memtables, WALs and blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ValueType {
+    /// The key maps to the value, which may be empty.
+    Put = 0,
+    /// The key is deleted. The value is always empty.
+    Delete = 1,
+    /// The keys from this one up to the value, exclusive, are deleted. Such entries only appear in
+    /// WALs, and are kept as [`crate::range_tombstone::RangeTombstone`]s elsewhere.
+    RangeDelete = 2,
+    /// The value is an operand of the merge operator, to fold into the earlier value of the key.
+    Merge = 3,
+}
+
+impl TryFrom<u8> for ValueType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(ValueType::Put),
+            1 => Ok(ValueType::Delete),
+            2 => Ok(ValueType::RangeDelete),
+            3 => Ok(ValueType::Merge),
+            _ => bail!("invalid value type {}", value),
+        }
+    }
+}
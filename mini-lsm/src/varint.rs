@@ -0,0 +1,39 @@
+use anyhow::{bail, ensure, Result};
+use bytes::{Buf, BufMut};
+
+/// The largest encoded size of a `u64`.
+pub const MAX_VARINT_LEN: usize = 10;
+
+/// Encode `value` as a varint: 7 bits in each byte from the lowest, with the highest bit set on
+/// all bytes but the last, as in LevelDB and protobuf. Small lengths take a single byte, while
+/// any `u64` fits.
+pub fn put_varint(buf: &mut impl BufMut, mut value: u64) {
+    while value >= 0x80 {
+        buf.put_u8(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.put_u8(value as u8);
+}
+
+/// Decode a varint written by [`put_varint`], failing if it is truncated or overflows a `u64`.
+pub fn get_varint(buf: &mut impl Buf) -> Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        ensure!(buf.has_remaining(), "truncated varint");
+        let byte = buf.get_u8();
+        ensure!(shift < 63 || byte <= 1, "varint overflow");
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("varint overflow")
+}
+
+/// Get the encoded size of `value`.
+pub fn varint_len(value: u64) -> usize {
+    (u64::BITS - (value | 1).leading_zeros()).div_ceil(7) as usize
+}
+
+#[cfg(test)]
+mod tests;
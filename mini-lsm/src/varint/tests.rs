@@ -0,0 +1,46 @@
+use super::*;
+
+#[test]
+fn test_varint_roundtrip() {
+    let values = [
+        0,
+        1,
+        0x7f,
+        0x80,
+        0x3fff,
+        0x4000,
+        u16::MAX as u64,
+        u32::MAX as u64,
+        1 << 35,
+        u64::MAX - 1,
+        u64::MAX,
+    ];
+    let mut buf = Vec::new();
+    for value in values {
+        let len = buf.len();
+        put_varint(&mut buf, value);
+        assert_eq!(buf.len() - len, varint_len(value), "{}", value);
+    }
+    assert_eq!(varint_len(0x7f), 1);
+    assert_eq!(varint_len(0x80), 2);
+    assert_eq!(varint_len(u64::MAX), MAX_VARINT_LEN);
+    let mut rbuf = &buf[..];
+    for value in values {
+        assert_eq!(get_varint(&mut rbuf).unwrap(), value);
+    }
+    assert!(rbuf.is_empty());
+}
+
+#[test]
+fn test_varint_invalid() {
+    let mut buf = Vec::new();
+    put_varint(&mut buf, 1 << 40);
+    assert!(get_varint(&mut &buf[..buf.len() - 1]).is_err());
+    assert!(get_varint(&mut &[][..]).is_err());
+    // More than 64 bits.
+    assert!(get_varint(&mut &[0xff; MAX_VARINT_LEN][..]).is_err());
+    assert!(get_varint(&mut &[0x80; MAX_VARINT_LEN + 1][..]).is_err());
+    let mut overflow = [0xff; MAX_VARINT_LEN];
+    overflow[MAX_VARINT_LEN - 1] = 0x02;
+    assert!(get_varint(&mut &overflow[..]).is_err());
+}
@@ -0,0 +1,240 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use anyhow::{ensure, Context, Result};
+use bytes::{Buf, BufMut, Bytes};
+use crossbeam_skiplist::SkipMap;
+use parking_lot::Mutex;
+
+use crate::block::SIZEOF_U64;
+use crate::key::InternalKey;
+use crate::range_tombstone::RangeTombstone;
+use crate::value_type::ValueType;
+use crate::varint::{get_varint, put_varint};
+
+const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+
+/// The magic number at the start of every WAL segment, "MINIWAL" followed by a zero byte.
+const MAGIC: u64 = 0x4d49_4e49_5741_4c00;
+
+/// The version of the WAL format, in the header after the magic number.
+const VERSION: u32 = 1;
+
+const HEADER_SIZE: usize = SIZEOF_U64 + SIZEOF_U32;
+
+/// One write recovered from the WAL: its sequence number and its entries.
+type Record = (u64, Vec<(Bytes, ValueType, Bytes)>);
+
+/// A write-ahead log segment owned by a single memtable.
+///
+/// A segment starts with a header of `| magic (u64) | version (u32) |`, followed by records. Each
+/// record holds the entries of one write, which share the sequence number of the write, and is
+/// encoded as:
+///
+/// ```plaintext
+/// | len (u64) | seq (u64) | entry | ... | entry | checksum (u32) |
+/// ```
+///
+/// where each entry is:
+///
+/// ```plaintext
+/// | key_len (varint) | key | value type (u8) | value_len (varint) | value |
+/// ```
+///
+/// A range delete is an entry of the start key, with the end key as the value.
+///
+/// The checksum covers the length and the entries, so a record is replayed whole or not at all.
+///
+/// Segments written before the header was added have no header and a u32 record length. They are
+/// still recovered, and the records appended to them keep that layout.
+pub struct Wal {
+    file: Mutex<File>,
+    /// Whether the segment has no header and u32 record lengths.
+    legacy: bool,
+}
+
+impl Wal {
+    /// Create a new WAL segment at `path`.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(path.as_ref())
+            .with_context(|| format!("failed to create WAL {}", path.as_ref().display()))?;
+        file.write_all(&Self::header())?;
+        Ok(Self {
+            file: Mutex::new(file),
+            legacy: false,
+        })
+    }
+
+    fn header() -> Vec<u8> {
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        header.put_u64(MAGIC);
+        header.put_u32(VERSION);
+        header
+    }
+
+    /// Replay an existing WAL segment into `skiplist` and `range_tombstones`, and reopen it for
+    /// appending.
+    ///
+    /// A torn record at the tail is what a crash in the middle of a write leaves behind, so replay
+    /// stops there and the segment is truncated to the last complete record. A corrupted record
+    /// followed by more data is not, and fails the recovery, as does an unknown version.
+    pub fn recover(
+        path: impl AsRef<Path>,
+        skiplist: &SkipMap<InternalKey, (ValueType, Bytes)>,
+        range_tombstones: &mut Vec<RangeTombstone>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("failed to recover WAL {}", path.display()))?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+
+        if buf.len() < HEADER_SIZE {
+            // Too short for a record: the segment was created right before a crash.
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&Self::header())?;
+            file.sync_all()?;
+            return Ok(Self {
+                file: Mutex::new(file),
+                legacy: false,
+            });
+        }
+        let legacy = (&buf[..]).get_u64() != MAGIC;
+        let mut rbuf = &buf[..];
+        if !legacy {
+            rbuf.advance(SIZEOF_U64);
+            let version = rbuf.get_u32();
+            ensure!(
+                version == VERSION,
+                "WAL {}: unsupported version {} (supports {})",
+                path.display(),
+                version,
+                VERSION
+            );
+        }
+        while let Some((seq, entries)) = Self::decode_record(&mut rbuf, legacy)
+            .with_context(|| format!("WAL {} is corrupted", path.display()))?
+        {
+            for (key, value_type, value) in entries {
+                match value_type {
+                    ValueType::RangeDelete => {
+                        range_tombstones.push(RangeTombstone::new(key, value, seq))
+                    }
+                    _ => {
+                        skiplist.insert(InternalKey::new(key, seq), (value_type, value));
+                    }
+                }
+            }
+        }
+        let valid_len = (buf.len() - rbuf.len()) as u64;
+        if valid_len < buf.len() as u64 {
+            file.set_len(valid_len)?;
+            file.sync_all()?;
+        }
+        file.seek(SeekFrom::End(0))?;
+        Ok(Self {
+            file: Mutex::new(file),
+            legacy,
+        })
+    }
+
+    /// Decode one record, advancing `buf` past it. Returns `None` on a torn record, which runs
+    /// to the end of `buf`, leaving `buf` untouched. Fails on a corrupted record followed by more
+    /// data.
+    fn decode_record(buf: &mut &[u8], legacy: bool) -> Result<Option<Record>> {
+        let len_size = if legacy { SIZEOF_U32 } else { SIZEOF_U64 };
+        if buf.remaining() < len_size {
+            return Ok(None);
+        }
+        let len = if legacy {
+            (&buf[..]).get_u32() as u64
+        } else {
+            (&buf[..]).get_u64()
+        };
+        // A record running past the end of the log is torn.
+        let frame_len = usize::try_from(len)
+            .ok()
+            .and_then(|len| len.checked_add(len_size));
+        let Some(frame_len) =
+            frame_len.filter(|&x| x <= buf.remaining().saturating_sub(SIZEOF_U32))
+        else {
+            return Ok(None);
+        };
+        let (frame, mut rest) = buf.split_at(frame_len);
+        if crc32fast::hash(frame) != rest.get_u32() {
+            ensure!(!rest.has_remaining(), "record checksum mismatch");
+            return Ok(None);
+        }
+        let record = Self::decode_entries(&frame[len_size..])?;
+        *buf = rest;
+        Ok(Some(record))
+    }
+
+    /// Decode the sequence number and the entries of a record whose checksum matched.
+    fn decode_entries(mut buf: &[u8]) -> Result<Record> {
+        ensure!(buf.remaining() >= SIZEOF_U64, "record too short");
+        let seq = buf.get_u64();
+        let mut entries = Vec::new();
+        while buf.has_remaining() {
+            let key_len = get_varint(&mut buf)? as usize;
+            ensure!(buf.remaining() > key_len, "record entry too short");
+            let key = Bytes::copy_from_slice(&buf[..key_len]);
+            buf.advance(key_len);
+            let value_type = ValueType::try_from(buf.get_u8())?;
+            let value_len = get_varint(&mut buf)? as usize;
+            ensure!(buf.remaining() >= value_len, "record entry too short");
+            let value = Bytes::copy_from_slice(&buf[..value_len]);
+            buf.advance(value_len);
+            entries.push((key, value_type, value));
+        }
+        Ok((seq, entries))
+    }
+
+    /// Append a record of a single entry to the WAL.
+    pub fn put(&self, key: &[u8], seq: u64, value_type: ValueType, value: &[u8]) -> Result<()> {
+        self.put_batch(seq, &[(key, value_type, value)])
+    }
+
+    /// Append a record of several entries written at `seq` to the WAL, which are recovered all
+    /// together or not at all. The record reaches the OS before this returns, so it survives a
+    /// process crash; call [`Wal::sync`] to make it survive a power loss as well.
+    pub fn put_batch(&self, seq: u64, entries: &[(&[u8], ValueType, &[u8])]) -> Result<()> {
+        let len_size = if self.legacy { SIZEOF_U32 } else { SIZEOF_U64 };
+        let mut buf = vec![0; len_size];
+        buf.put_u64(seq);
+        for (key, value_type, value) in entries {
+            put_varint(&mut buf, key.len() as u64);
+            buf.put_slice(key);
+            buf.put_u8(*value_type as u8);
+            put_varint(&mut buf, value.len() as u64);
+            buf.put_slice(value);
+        }
+        let len = buf.len() - len_size;
+        if self.legacy {
+            let len = u32::try_from(len).context("record too large for a legacy WAL segment")?;
+            (&mut buf[..len_size]).put_u32(len);
+        } else {
+            (&mut buf[..len_size]).put_u64(len as u64);
+        }
+        buf.put_u32(crc32fast::hash(&buf));
+        self.file.lock().write_all(&buf)?;
+        Ok(())
+    }
+
+    /// Fsync the WAL.
+    pub fn sync(&self) -> Result<()> {
+        self.file.lock().sync_all()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,250 @@
+use std::fs::OpenOptions;
+
+use bytes::Bytes;
+use crossbeam_skiplist::SkipMap;
+use tempfile::tempdir;
+
+use super::Wal;
+use crate::key::InternalKey;
+use crate::value_type::ValueType;
+
+/// Get the latest version of `key` in the recovered map.
+fn latest(map: &SkipMap<InternalKey, (ValueType, Bytes)>, key: &[u8]) -> (ValueType, Bytes) {
+    let lower = InternalKey::new(Bytes::copy_from_slice(key), u64::MAX);
+    let entry = map.range(lower..).next().unwrap();
+    assert_eq!(entry.key().key, key);
+    entry.value().clone()
+}
+
+#[test]
+fn test_wal_recover() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    {
+        let wal = Wal::create(&path).unwrap();
+        wal.put(b"key1", 1, ValueType::Put, b"value1").unwrap();
+        wal.put(b"key2", 2, ValueType::Put, b"value2").unwrap();
+        wal.put(b"key1", 3, ValueType::Put, b"value11").unwrap();
+        wal.put(b"key2", 4, ValueType::Delete, b"").unwrap();
+        wal.sync().unwrap();
+    }
+    let map = SkipMap::new();
+    let wal = Wal::recover(&path, &map, &mut Vec::new()).unwrap();
+    assert_eq!(map.len(), 4);
+    assert_eq!(
+        latest(&map, b"key1"),
+        (ValueType::Put, Bytes::from("value11"))
+    );
+    assert_eq!(latest(&map, b"key2"), (ValueType::Delete, Bytes::new()));
+    assert_eq!(
+        map.get(&InternalKey::new(Bytes::from("key1"), 1))
+            .unwrap()
+            .value(),
+        &(ValueType::Put, Bytes::from("value1"))
+    );
+
+    // The recovered segment can still be appended to.
+    wal.put(b"key3", 5, ValueType::Put, b"value3").unwrap();
+    drop(wal);
+    let map = SkipMap::new();
+    Wal::recover(&path, &map, &mut Vec::new()).unwrap();
+    assert_eq!(map.len(), 5);
+}
+
+#[test]
+fn test_wal_recover_torn_tail() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    {
+        let wal = Wal::create(&path).unwrap();
+        wal.put(b"key1", 1, ValueType::Put, b"value1").unwrap();
+        wal.put(b"key2", 2, ValueType::Put, b"value2").unwrap();
+    }
+    // Simulate a crash in the middle of writing the second record.
+    let len = std::fs::metadata(&path).unwrap().len();
+    let file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(len - 3).unwrap();
+    drop(file);
+
+    let map = SkipMap::new();
+    let wal = Wal::recover(&path, &map, &mut Vec::new()).unwrap();
+    assert_eq!(map.len(), 1);
+    assert_eq!(
+        latest(&map, b"key1"),
+        (ValueType::Put, Bytes::from("value1"))
+    );
+
+    // New records are appended right after the last complete one.
+    wal.put(b"key3", 2, ValueType::Put, b"value3").unwrap();
+    drop(wal);
+    let map = SkipMap::new();
+    Wal::recover(&path, &map, &mut Vec::new()).unwrap();
+    assert_eq!(map.len(), 2);
+    assert_eq!(
+        latest(&map, b"key3"),
+        (ValueType::Put, Bytes::from("value3"))
+    );
+}
+
+#[test]
+fn test_wal_recover_torn_batch() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    {
+        let wal = Wal::create(&path).unwrap();
+        wal.put(b"key1", 1, ValueType::Put, b"value1").unwrap();
+        wal.put_batch(
+            2,
+            &[
+                (b"key2", ValueType::Put, b"value2"),
+                (b"key1", ValueType::Delete, b""),
+                (b"key3", ValueType::Put, b"value3"),
+            ],
+        )
+        .unwrap();
+    }
+    let map = SkipMap::new();
+    Wal::recover(&path, &map, &mut Vec::new()).unwrap();
+    assert_eq!(map.len(), 4);
+    assert_eq!(latest(&map, b"key1"), (ValueType::Delete, Bytes::new()));
+
+    // A batch cut short by a crash is dropped as a whole.
+    let len = std::fs::metadata(&path).unwrap().len();
+    let file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(len - 10).unwrap();
+    drop(file);
+    let map = SkipMap::new();
+    Wal::recover(&path, &map, &mut Vec::new()).unwrap();
+    assert_eq!(map.len(), 1);
+    assert_eq!(
+        latest(&map, b"key1"),
+        (ValueType::Put, Bytes::from("value1"))
+    );
+}
+
+#[test]
+fn test_wal_recover_oversized_length() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    {
+        let wal = Wal::create(&path).unwrap();
+        wal.put(b"key1", 1, ValueType::Put, b"value1").unwrap();
+    }
+    // A garbage record length past any file size, at the tail, is a torn record.
+    let mut data = std::fs::read(&path).unwrap();
+    let len = data.len();
+    data.extend_from_slice(&u64::MAX.to_be_bytes());
+    data.extend_from_slice(&[0; 16]);
+    std::fs::write(&path, &data).unwrap();
+
+    let map = SkipMap::new();
+    Wal::recover(&path, &map, &mut Vec::new()).unwrap();
+    assert_eq!(map.len(), 1);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), len as u64);
+}
+
+#[test]
+fn test_wal_recover_corrupted_record() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    {
+        let wal = Wal::create(&path).unwrap();
+        wal.put(b"key1", 1, ValueType::Put, b"value1").unwrap();
+        wal.put(b"key2", 2, ValueType::Put, b"value2").unwrap();
+    }
+    let data = std::fs::read(&path).unwrap();
+
+    // A corrupted record followed by another one is not a torn tail, and nothing is truncated.
+    let mut corrupted = data.clone();
+    corrupted[25] ^= 1;
+    std::fs::write(&path, &corrupted).unwrap();
+    let err = Wal::recover(&path, &SkipMap::new(), &mut Vec::new())
+        .err()
+        .unwrap();
+    assert!(
+        format!("{:#}", err).contains("record checksum mismatch"),
+        "{:#}",
+        err
+    );
+    assert_eq!(std::fs::read(&path).unwrap(), corrupted);
+
+    // The same corruption in the last record is a torn tail.
+    let mut corrupted = data.clone();
+    let len = corrupted.len();
+    corrupted[len - 6] ^= 1;
+    std::fs::write(&path, &corrupted).unwrap();
+    let map = SkipMap::new();
+    Wal::recover(&path, &map, &mut Vec::new()).unwrap();
+    assert_eq!(map.len(), 1);
+    assert!(std::fs::metadata(&path).unwrap().len() < len as u64);
+}
+
+#[test]
+fn test_wal_recover_header() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    {
+        let wal = Wal::create(&path).unwrap();
+        wal.put(b"key1", 1, ValueType::Put, b"value1").unwrap();
+    }
+    // A segment cut short in its header, right after it was created, is empty.
+    let data = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &data[..5]).unwrap();
+    let map = SkipMap::new();
+    let wal = Wal::recover(&path, &map, &mut Vec::new()).unwrap();
+    assert!(map.is_empty());
+    wal.put(b"key2", 2, ValueType::Put, b"value2").unwrap();
+    drop(wal);
+    let map = SkipMap::new();
+    Wal::recover(&path, &map, &mut Vec::new()).unwrap();
+    assert_eq!(map.len(), 1);
+
+    // A segment of a newer version is rejected.
+    let mut data = std::fs::read(&path).unwrap();
+    data[11] += 1;
+    std::fs::write(&path, &data).unwrap();
+    let err = Wal::recover(&path, &SkipMap::new(), &mut Vec::new())
+        .err()
+        .unwrap();
+    assert!(
+        err.to_string().contains("unsupported version 2"),
+        "{:#}",
+        err
+    );
+}
+
+#[test]
+fn test_wal_recover_legacy() {
+    // A segment written before the header, with u32 record lengths.
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    let mut data = Vec::new();
+    for (seq, key, value) in [(1u64, b"key1", b"value1"), (2, b"key2", b"value2")] {
+        let start = data.len();
+        data.extend_from_slice(&21u32.to_be_bytes());
+        data.extend_from_slice(&seq.to_be_bytes());
+        data.push(4);
+        data.extend_from_slice(key);
+        data.push(ValueType::Put as u8);
+        data.push(6);
+        data.extend_from_slice(value);
+        let checksum = crc32fast::hash(&data[start..]);
+        data.extend_from_slice(&checksum.to_be_bytes());
+    }
+    std::fs::write(&path, &data).unwrap();
+
+    let map = SkipMap::new();
+    let wal = Wal::recover(&path, &map, &mut Vec::new()).unwrap();
+    assert_eq!(map.len(), 2);
+    assert_eq!(
+        latest(&map, b"key2"),
+        (ValueType::Put, Bytes::from("value2"))
+    );
+    // New records keep the layout of the segment.
+    wal.put(b"key3", 3, ValueType::Put, b"value3").unwrap();
+    drop(wal);
+    assert_eq!(std::fs::read(&path).unwrap()[..data.len()], data[..]);
+    let map = SkipMap::new();
+    Wal::recover(&path, &map, &mut Vec::new()).unwrap();
+    assert_eq!(map.len(), 3);
+}
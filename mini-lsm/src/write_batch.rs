@@ -0,0 +1,81 @@
+use bytes::Bytes;
+
+/// An operation in a [`WriteBatch`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WriteBatchRecord {
+    /// Put a key-value pair.
+    Put(Bytes, Bytes),
+    /// Delete a key.
+    Delete(Bytes),
+    /// Delete all keys in `[start, end)`.
+    DeleteRange(Bytes, Bytes),
+    /// Merge an operand into the value of a key.
+    Merge(Bytes, Bytes),
+}
+
+/// A group of writes applied atomically by [`crate::lsm_storage::LsmStorage::write`], in the
+/// order they were added to the batch.
+#[derive(Clone, Debug, Default)]
+pub struct WriteBatch {
+    records: Vec<WriteBatchRecord>,
+}
+
+impl WriteBatch {
+    /// Create an empty batch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Put a key-value pair. The value may be empty.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> &mut Self {
+        assert!(!key.is_empty(), "key cannot be empty");
+        self.records.push(WriteBatchRecord::Put(
+            Bytes::copy_from_slice(key),
+            Bytes::copy_from_slice(value),
+        ));
+        self
+    }
+
+    /// Delete a key.
+    pub fn delete(&mut self, key: &[u8]) -> &mut Self {
+        assert!(!key.is_empty(), "key cannot be empty");
+        self.records
+            .push(WriteBatchRecord::Delete(Bytes::copy_from_slice(key)));
+        self
+    }
+
+    /// Delete all keys in `[start, end)`, including the ones put earlier in the batch.
+    pub fn delete_range(&mut self, start: &[u8], end: &[u8]) -> &mut Self {
+        assert!(!start.is_empty(), "key cannot be empty");
+        self.records.push(WriteBatchRecord::DeleteRange(
+            Bytes::copy_from_slice(start),
+            Bytes::copy_from_slice(end),
+        ));
+        self
+    }
+
+    /// Merge an operand into the value of a key with the merge operator of the storage.
+    pub fn merge(&mut self, key: &[u8], operand: &[u8]) -> &mut Self {
+        assert!(!key.is_empty(), "key cannot be empty");
+        self.records.push(WriteBatchRecord::Merge(
+            Bytes::copy_from_slice(key),
+            Bytes::copy_from_slice(operand),
+        ));
+        self
+    }
+
+    /// Get the operations of the batch.
+    pub fn records(&self) -> &[WriteBatchRecord] {
+        &self.records
+    }
+
+    /// Get the number of operations in the batch.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Check if the batch has no operation.
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}